@@ -0,0 +1,51 @@
+//! Helpers shared by the benchmarks. They run without a benchmarking framework, timing a closure
+//! with [Instant] and counting the heap allocations it makes with [CountingAllocator], which every
+//! benchmark installs as its global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Forwards to the system allocator, counting every allocation and reallocation.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Calls `f` once for every nonce in `0..iterations` after warming up, then prints the average time
+/// and number of allocations per call.
+pub fn measure(name: &str, iterations: u64, mut f: impl FnMut(u64)) {
+    for nonce in 0..iterations / 10 {
+        f(nonce);
+    }
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for nonce in 0..iterations {
+        f(nonce);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    println!(
+        "{:<40} {:>10.1} ns/iter {:>8.2} allocations/iter",
+        name,
+        elapsed.as_nanos() as f64 / iterations as f64,
+        allocations as f64 / iterations as f64
+    );
+}
@@ -0,0 +1,54 @@
+//! Compares generating passwords from the HMAC output copied to a `Vec`, as the crate used to do, to
+//! truncating the fixed size array the digest produces, which needs no heap allocation. Both write
+//! the password to a reused buffer so only the HMAC output differs.
+//!
+//! Run with `cargo bench --bench hmac_digest`.
+
+mod common;
+
+use common::{measure, CountingAllocator};
+use hmac::{Hmac, Mac};
+use std::fmt::Write;
+use std::hint::black_box;
+use thotp::custom::{otp_custom_into, Sha1};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const SECRET: &[u8] = b"12345678901234567890";
+const ITERATIONS: u64 = 1_000_000;
+
+/// Generates a password the way the crate did before the HMAC output was kept on the stack.
+fn otp_from_vec(secret: &[u8], nonce: u64, digits: u8, out: &mut String) {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).unwrap();
+    mac.update(&nonce.to_be_bytes());
+    let hmac = mac.finalize().into_bytes().to_vec();
+
+    let offset = (hmac[hmac.len() - 1] & 0xf) as usize;
+    let mut trunc: [u8; 4] = hmac[offset..=offset + 3].try_into().unwrap();
+    trunc[0] &= 0x7f;
+
+    let result = u32::from_be_bytes(trunc) as u64 % 10_u64.pow(digits as u32);
+    out.clear();
+    write!(out, "{:0width$}", result, width = digits as usize).unwrap();
+}
+
+fn main() {
+    // Both paths have to generate the same passwords for the comparison to mean anything
+    let (mut before, mut after) = (String::new(), String::new());
+    for nonce in 0..1000 {
+        otp_from_vec(SECRET, nonce, 6, &mut before);
+        otp_custom_into::<Sha1>(SECRET, nonce, 6, &mut after).unwrap();
+        assert_eq!(before, after);
+    }
+
+    measure("HMAC output in a Vec", ITERATIONS, |nonce| {
+        otp_from_vec(SECRET, nonce, 6, &mut before);
+        black_box(&before);
+    });
+
+    measure("HMAC output in a GenericArray", ITERATIONS, |nonce| {
+        otp_custom_into::<Sha1>(SECRET, nonce, 6, &mut after).unwrap();
+        black_box(&after);
+    });
+}
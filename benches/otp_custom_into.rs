@@ -0,0 +1,28 @@
+//! Compares generating passwords with [otp_custom], which allocates a new string for every password,
+//! to [otp_custom_into] writing them to a reused buffer.
+//!
+//! Run with `cargo bench --bench otp_custom_into`.
+
+mod common;
+
+use common::{measure, CountingAllocator};
+use std::hint::black_box;
+use thotp::custom::{otp_custom, otp_custom_into, Sha1};
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const SECRET: &[u8] = b"12345678901234567890";
+const ITERATIONS: u64 = 1_000_000;
+
+fn main() {
+    measure("otp_custom", ITERATIONS, |nonce| {
+        black_box(otp_custom::<Sha1>(SECRET, nonce, 6).unwrap());
+    });
+
+    let mut password = String::with_capacity(6);
+    measure("otp_custom_into", ITERATIONS, |nonce| {
+        otp_custom_into::<Sha1>(SECRET, nonce, 6, &mut password).unwrap();
+        black_box(&password);
+    });
+}
@@ -1,145 +1,3281 @@
 //! Contains functions providing finer control over OTP generation and verification parameters. This
 //! module re-exports the hashing algorithms `Sha1`, `Sha256` and `Sha512` to use with the provided
 //! functions.
+//!
+//! Functions taking a `timestamp` use the current system time if it is 0, unless their documentation
+//! says otherwise.
 
 use super::*;
 use digest::{
-    block_buffer::Eager,
-    core_api::{BufferKindUser, CoreProxy, FixedOutputCore, UpdateCore},
-    crypto_common::BlockSizeUser,
-    typenum::{IsLess, Le, NonZero, U256},
-    FixedOutput, HashMarker, Update,
+    core_api::{AlgorithmName, CoreProxy},
+    KeyInit,
 };
+use hmac::Mac;
+use otp_core::{
+    algorithm_name, checked_time_slice, ct_eq, digit_modulus, hkdf, resolve_timestamp,
+    write_password, zeroize,
+};
+use std::future::Future;
+use std::ops::RangeInclusive;
 
-// Re-export the hashing algorithms
+// Re-export the hashing algorithms along with the trait bounding them
+pub use super::otp_core::OtpHash;
 pub use sha1::Sha1;
 pub use sha2::{Sha256, Sha512};
 
+/// The hashing algorithms supported by this module, for when the algorithm is only known at runtime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Returns all the supported algorithms, e.g. to let users pick one in a settings page.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::custom::Algorithm;
+    ///
+    /// let labels: Vec<&str> = Algorithm::all().iter().map(Algorithm::label).collect();
+    ///
+    /// assert_eq!(labels, ["SHA-1", "SHA-256", "SHA-512"]);
+    /// ```
+    pub fn all() -> &'static [Algorithm] {
+        &[Algorithm::Sha1, Algorithm::Sha256, Algorithm::Sha512]
+    }
+
+    /// Returns the name of the algorithm meant to be shown to users, e.g. `SHA-256`. Otp uris use the
+    /// names without the dash instead.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Algorithm::Sha1 => "SHA-1",
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha512 => "SHA-512",
+        }
+    }
+
+    /// Returns the output length of the algorithm in bytes, i.e. the length of the HMACs passwords are
+    /// truncated from.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::custom::Algorithm;
+    ///
+    /// assert_eq!(Algorithm::Sha1.output_len(), 20);
+    /// assert_eq!(Algorithm::Sha256.block_len(), 64);
+    /// ```
+    pub fn output_len(&self) -> usize {
+        match self {
+            Algorithm::Sha1 => 20,
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha512 => 64,
+        }
+    }
+
+    /// Returns the block size of the algorithm in bytes. Secrets longer than this are hashed before
+    /// being used as the HMAC key.
+    pub fn block_len(&self) -> usize {
+        match self {
+            Algorithm::Sha1 | Algorithm::Sha256 => 64,
+            Algorithm::Sha512 => 128,
+        }
+    }
+}
+
+/// A secret key. Its contents are never shown when formatting it with [Debug](std::fmt::Debug) and
+/// it is compared in constant time.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{Algorithm, Secret};
+/// use thotp::generate_secret;
+///
+/// let secret = Secret::new(generate_secret(20));
+///
+/// assert_eq!(secret.entropy_bits(), 160);
+/// assert!(secret.is_recommended_for(Algorithm::Sha1));
+/// assert!(!secret.is_recommended_for(Algorithm::Sha256));
+/// ```
+#[derive(Clone, Eq)]
+pub struct Secret(Vec<u8>);
+
+impl Secret {
+    /// Creates a secret from the given bytes.
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self(secret.into())
+    }
+
+    /// Returns the bytes of the secret.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the length of the secret in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if the secret is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the entropy of the secret in bits assuming it was randomly generated, i.e. its length
+    /// times 8.
+    pub fn entropy_bits(&self) -> usize {
+        self.0.len() * 8
+    }
+
+    /// Returns whether the secret is at least as long as RFC 6238 recommends for the algorithm, which
+    /// is the output length of its hash, i.e. 20 bytes for SHA1, 32 for SHA256 and 64 for SHA512.
+    /// Shorter secrets still work, but secrets shorter than 16 bytes are below the minimum of
+    /// RFC 4226.
+    pub fn is_recommended_for(&self, algorithm: Algorithm) -> bool {
+        self.0.len() >= algorithm.output_len()
+    }
+
+    /// Parses a secret of an unknown encoding. The encoding is guessed with
+    /// [detect_encoding](super::encoding::detect_encoding) and if decoding with it fails, or none was
+    /// detected, the remaining encodings are attempted. Returns a [ThotpError::UnknownEncoding]
+    /// listing the attempted encodings if none of them succeed.
+    ///
+    /// Since the detection is only a heuristic, secrets of a known encoding should be decoded with it
+    /// explicitly instead.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::custom::Secret;
+    ///
+    /// assert_eq!(Secret::parse("JBSWY3DPEHPK3PXP").unwrap().as_bytes(), b"Hello!\xde\xad\xbe\xef");
+    /// assert_eq!(Secret::parse("0x48656c6c6f").unwrap().as_bytes(), b"Hello");
+    /// assert!(Secret::parse("not a secret").is_err());
+    /// ```
+    #[cfg(feature = "encoding")]
+    pub fn parse(secret: &str) -> Result<Self, ThotpError> {
+        use super::encoding::{detect_encoding, SecretEncoding};
+
+        let secret = secret.trim();
+        let mut encodings = vec![
+            SecretEncoding::Base32,
+            SecretEncoding::Hex,
+            SecretEncoding::Base64,
+        ];
+        if let Some(detected) = detect_encoding(secret) {
+            encodings.retain(|&encoding| encoding != detected);
+            encodings.insert(0, detected);
+        }
+
+        for encoding in &encodings {
+            if let Ok(decoded) = encoding.decode(secret) {
+                return Ok(Self(decoded));
+            }
+        }
+
+        let attempted: Vec<&str> = encodings.iter().map(SecretEncoding::name).collect();
+        Err(ThotpError::UnknownEncoding(attempted.join(", ")))
+    }
+}
+
+impl From<Vec<u8>> for Secret {
+    fn from(secret: Vec<u8>) -> Self {
+        Self(secret)
+    }
+}
+
+impl AsRef<[u8]> for Secret {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq for Secret {
+    fn eq(&self, other: &Self) -> bool {
+        ct_eq(&self.0, &other.0)
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    // The contents are omitted on purpose
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({} bytes)", self.0.len())
+    }
+}
+
+/// Returns the message the HMAC is computed over for the given nonce, i.e. the counter of a HOTP or the
+/// time slice of a TOTP as 8 big endian bytes, as specified by RFC 4226. It is used by [otp_custom],
+/// [HmacKey::code] and every function built on them, e.g. to reproduce the computation step by step
+/// when debugging a mismatch with another implementation.
+///
+/// Only the functions taking a [CounterEncoding] or [CounterEndian] compute the HMAC over a different
+/// message.
+///
+/// ## Example
+/// ```
+/// use hmac::Mac;
+/// use thotp::custom::{nonce_message_bytes, verify_from_hmac, Sha1};
+///
+/// // The time slice of the RFC 6238 timestamp 1111111109
+/// let message = nonce_message_bytes(1111111109 / 30);
+/// assert_eq!(message, [0x00, 0x00, 0x00, 0x00, 0x02, 0x35, 0x23, 0xec]);
+///
+/// // The HMAC computed by another implementation
+/// let mut mac = hmac::Hmac::<Sha1>::new_from_slice(b"12345678901234567890").unwrap();
+/// mac.update(&message);
+/// let hmac = mac.finalize().into_bytes();
+///
+/// assert!(verify_from_hmac(&hmac, "07081804", 8).unwrap());
+/// ```
+pub const fn nonce_message_bytes(nonce: u64) -> [u8; 8] {
+    nonce.to_be_bytes()
+}
+
 /// Generates a one time password using the given secret, nonce, digits and algorithm.
 pub fn otp_custom<H>(secret: &[u8], nonce: u64, digits: u8) -> Result<String, ThotpError>
 where
-    H: Update + FixedOutput + CoreProxy,
-    H::Core: HashMarker
-        + UpdateCore
-        + FixedOutputCore
-        + BufferKindUser<BufferKind = Eager>
-        + Default
-        + Clone,
-    <H::Core as BlockSizeUser>::BlockSize: IsLess<U256>,
-    Le<<H::Core as BlockSizeUser>::BlockSize, U256>: NonZero,
+    H: OtpHash,
+{
+    // Transform to bytes
+    let nonce = &nonce_message_bytes(nonce);
+
+    // Create an HMAC digest with the given key, nonce and algorithm
+    let hmac = hmac_digest::<H>(secret, nonce)?;
+
+    // Truncate to 4 bytes
+    let trunc = dynamic_trunc(&hmac);
+
+    // Mod it with the number of digits and pad it with 0s to get the password
+    Ok(to_password(trunc, digits))
+}
+
+/// How the counter is encoded in the message the HMAC is computed over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CounterEncoding {
+    /// The RFC 4226 encoding, the counter as 8 big endian bytes
+    #[default]
+    Rfc8Byte,
+
+    /// The counter as big endian bytes without leading 0 bytes, used by some nonconforming tokens.
+    /// A counter of 0 is encoded as a single 0 byte.
+    MinimalBe,
+
+    /// The low 32 bits of the counter as 4 big endian bytes, used by legacy tokens with a 32 bit time
+    /// counter. Counters that do not fit are truncated, so this is only meant for such tokens.
+    Truncated4Byte,
+}
+
+impl CounterEncoding {
+    /// Encodes the counter as the message the HMAC is computed over.
+    pub fn encode(&self, counter: u64) -> Vec<u8> {
+        let bytes = counter.to_be_bytes();
+        match self {
+            CounterEncoding::Rfc8Byte => bytes.to_vec(),
+            CounterEncoding::MinimalBe => {
+                let leading = (counter.leading_zeros() / 8).min(7) as usize;
+                bytes[leading..].to_vec()
+            }
+            CounterEncoding::Truncated4Byte => bytes[4..].to_vec(),
+        }
+    }
+}
+
+/// Generates a one time password just like [otp_custom], except the counter is encoded with the given
+/// [CounterEncoding]. Use this only to interoperate with tokens that do not follow RFC 4226, with
+/// [CounterEncoding::Rfc8Byte] this is the same as [otp_custom].
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, otp_custom_encoded, CounterEncoding, Sha1};
+///
+/// let secret = b"super secret";
+///
+/// assert_eq!(
+///     otp_custom_encoded::<Sha1>(secret, 1, 6, CounterEncoding::Rfc8Byte).unwrap(),
+///     otp_custom::<Sha1>(secret, 1, 6).unwrap()
+/// );
+/// assert_ne!(
+///     otp_custom_encoded::<Sha1>(secret, 1, 6, CounterEncoding::MinimalBe).unwrap(),
+///     otp_custom::<Sha1>(secret, 1, 6).unwrap()
+/// );
+/// ```
+pub fn otp_custom_encoded<H>(
+    secret: &[u8],
+    nonce: u64,
+    digits: u8,
+    encoding: CounterEncoding,
+) -> Result<String, ThotpError>
+where
+    H: OtpHash,
+{
+    let hmac = hmac_digest::<H>(secret, &encoding.encode(nonce))?;
+
+    Ok(to_password(dynamic_trunc(&hmac), digits))
+}
+
+/// The byte order of the 8 byte counter in the message the HMAC is computed over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CounterEndian {
+    /// The RFC 4226 byte order
+    #[default]
+    Big,
+
+    /// The reversed byte order, used by some broken tokens
+    Little,
+}
+
+impl CounterEndian {
+    /// Encodes the counter in the byte order.
+    pub fn to_bytes(&self, counter: u64) -> [u8; 8] {
+        match self {
+            CounterEndian::Big => counter.to_be_bytes(),
+            CounterEndian::Little => counter.to_le_bytes(),
+        }
+    }
+}
+
+/// Generates a one time password just like [otp_custom], except the 8 byte counter is encoded in the
+/// given byte order. Use this only to interoperate with tokens that do not follow RFC 4226, with
+/// [CounterEndian::Big] this is the same as [otp_custom].
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, otp_custom_endian, CounterEndian, Sha1};
+///
+/// let secret = b"super secret";
+///
+/// assert_eq!(
+///     otp_custom_endian::<Sha1>(secret, 1, 6, CounterEndian::Big).unwrap(),
+///     otp_custom::<Sha1>(secret, 1, 6).unwrap()
+/// );
+/// assert_ne!(
+///     otp_custom_endian::<Sha1>(secret, 1, 6, CounterEndian::Little).unwrap(),
+///     otp_custom::<Sha1>(secret, 1, 6).unwrap()
+/// );
+/// ```
+pub fn otp_custom_endian<H>(
+    secret: &[u8],
+    nonce: u64,
+    digits: u8,
+    endian: CounterEndian,
+) -> Result<String, ThotpError>
+where
+    H: OtpHash,
+{
+    let hmac = hmac_digest::<H>(secret, &endian.to_bytes(nonce))?;
+
+    Ok(to_password(dynamic_trunc(&hmac), digits))
+}
+
+/// Generates a one time password just like [otp_custom], but returns it as a [Code] so its leading 0s
+/// can not accidentally be lost by treating it as a number.
+pub fn otp_custom_code<H>(secret: &[u8], nonce: u64, digits: u8) -> Result<Code, ThotpError>
+where
+    H: OtpHash,
+{
+    otp_custom::<H>(secret, nonce, digits).map(Code)
+}
+
+/// Generates a one time password just like [otp_custom], but writes it to the given buffer instead of
+/// allocating a new string. The buffer is cleared first, so reusing the same one when generating many
+/// passwords in a loop avoids an allocation per password once it has grown to `digits` bytes.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, otp_custom_into, Sha1};
+///
+/// let secret = b"super secret";
+/// let mut password = String::with_capacity(6);
+///
+/// for nonce in 0..10 {
+///     otp_custom_into::<Sha1>(secret, nonce, 6, &mut password).unwrap();
+///     assert_eq!(password, otp_custom::<Sha1>(secret, nonce, 6).unwrap());
+/// }
+/// ```
+pub fn otp_custom_into<H>(
+    secret: &[u8],
+    nonce: u64,
+    digits: u8,
+    out: &mut String,
+) -> Result<(), ThotpError>
+where
+    H: OtpHash,
+{
+    let hmac = hmac_digest::<H>(secret, &nonce.to_be_bytes())?;
+    write_password(dynamic_trunc(&hmac), digits, out);
+    Ok(())
+}
+
+/// Generates a one time password just like [otp_custom], but returns it as an iterator over its ASCII
+/// digits, leading 0s included, e.g. to extend a network buffer with it without allocating a string.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, otp_custom_digits, Sha1};
+///
+/// let secret = b"super secret";
+/// let mut buffer = b"code=".to_vec();
+///
+/// buffer.extend(otp_custom_digits::<Sha1>(secret, 1, 6).unwrap());
+///
+/// let password = otp_custom::<Sha1>(secret, 1, 6).unwrap();
+/// assert_eq!(buffer, format!("code={}", password).as_bytes());
+/// ```
+pub fn otp_custom_digits<H>(
+    secret: &[u8],
+    nonce: u64,
+    digits: u8,
+) -> Result<impl Iterator<Item = u8>, ThotpError>
+where
+    H: OtpHash,
+{
+    let hmac = hmac_digest::<H>(secret, &nonce.to_be_bytes())?;
+    let trunc = dynamic_trunc(&hmac) as u64;
+
+    // Digits beyond the 10 a truncated HMAC can have are leading 0s
+    Ok((0..digits as u32).rev().map(move |position| {
+        let digit = 10_u64
+            .checked_pow(position)
+            .map_or(0, |divisor| trunc / divisor % 10);
+        b'0' + digit as u8
+    }))
+}
+
+/// Generates a one time password just like [otp_custom], but writes its ASCII digits, leading 0s
+/// included, to the start of the given buffer and returns them as a string slice. The HMAC is kept on
+/// the stack, so nothing is allocated on the heap.
+///
+/// Returns a [ThotpError::BufferTooSmall] if the buffer is shorter than `digits`.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, otp_custom_write, Sha1};
+///
+/// let secret = b"super secret";
+/// let mut buffer = [0; 10];
+///
+/// let password = otp_custom_write::<Sha1>(secret, 1, 6, &mut buffer).unwrap();
+///
+/// assert_eq!(password, otp_custom::<Sha1>(secret, 1, 6).unwrap());
+/// ```
+pub fn otp_custom_write<'a, H>(
+    secret: &[u8],
+    nonce: u64,
+    digits: u8,
+    out: &'a mut [u8],
+) -> Result<&'a str, ThotpError>
+where
+    H: OtpHash,
+{
+    let out = out
+        .get_mut(..digits as usize)
+        .ok_or(ThotpError::BufferTooSmall(digits))?;
+
+    for (byte, digit) in out
+        .iter_mut()
+        .zip(otp_custom_digits::<H>(secret, nonce, digits)?)
+    {
+        *byte = digit;
+    }
+
+    Ok(std::str::from_utf8(out).expect("the password only consists of ASCII digits"))
+}
+
+/// Returns the check digit [otp_with_digit_sum_check] appends, i.e. the sum of the digits mod 10.
+fn digit_sum_check(digits: &str) -> u8 {
+    digits
+        .bytes()
+        .map(|digit| digit - b'0')
+        .fold(0, |sum, digit| (sum + digit) % 10)
+}
+
+/// Generates a one time password just like [otp_custom] and appends a check digit, the sum of its
+/// digits mod 10, so a user reading the password aloud or typing it can catch a mistyped digit. The
+/// returned password is `digits + 1` characters long and is verified with
+/// [verify_totp_digit_sum_check].
+///
+/// The check digit is only a usability aid and not a security feature. It is derived from the other
+/// digits, so it does not make the password any harder to guess.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, otp_with_digit_sum_check, Sha1};
+///
+/// let secret = b"12345678901234567890";
+///
+/// // 7 + 5 + 5 + 2 + 2 + 4 = 25
+/// assert_eq!(otp_custom::<Sha1>(secret, 0, 6).unwrap(), "755224");
+/// assert_eq!(otp_with_digit_sum_check::<Sha1>(secret, 0, 6).unwrap(), "7552245");
+/// ```
+pub fn otp_with_digit_sum_check<H>(
+    secret: &[u8],
+    nonce: u64,
+    digits: u8,
+) -> Result<String, ThotpError>
+where
+    H: OtpHash,
+{
+    let mut password = otp_custom::<H>(secret, nonce, digits)?;
+    let check = digit_sum_check(&password);
+
+    password.push(char::from(b'0' + check));
+    Ok(password)
+}
+
+/// Verifies a password generated with [otp_with_digit_sum_check], where `digits` is the digit length
+/// without the check digit. The check digit is validated first and a
+/// [ThotpError::ChecksumMismatch] is returned if it does not match the other digits, e.g. so the
+/// user can be told a digit was mistyped. Otherwise the password without the check digit is verified
+/// the same way [verify_totp_custom] does.
+///
+/// Since the check digit can be calculated by anyone, a matching one says nothing about whether the
+/// password is valid.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_with_digit_sum_check, verify_totp_digit_sum_check, Sha1};
+/// use thotp::ThotpError;
+///
+/// let secret = b"super secret";
+/// let password = otp_with_digit_sum_check::<Sha1>(secret, 1_000_000_000 / 30, 6).unwrap();
+///
+/// let verify = |password: &str| {
+///     verify_totp_digit_sum_check::<Sha1>(password, secret, 1_000_000_000, 6, 30, 1)
+/// };
+///
+/// assert_eq!(verify(&password).unwrap(), (true, 0));
+///
+/// // A mistyped digit changes the sum
+/// let mut mistyped = password.into_bytes();
+/// mistyped[0] = if mistyped[0] == b'9' { b'0' } else { mistyped[0] + 1 };
+/// let mistyped = String::from_utf8(mistyped).unwrap();
+///
+/// assert!(matches!(verify(&mistyped), Err(ThotpError::ChecksumMismatch)));
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_digit_sum_check<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    // Passwords of the wrong length or with anything but digits can never match
+    if password.len() != digits as usize + 1 || !password.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok((false, 0));
+    }
+
+    let (password, check) = password.split_at(digits as usize);
+
+    if digit_sum_check(password) != check.as_bytes()[0] - b'0' {
+        return Err(ThotpError::ChecksumMismatch);
+    }
+
+    verify_totp_custom::<H>(password, secret, timestamp, digits, step, allowed_drift)
+}
+
+/// Formats a truncated HMAC into a password, e.g. to present passwords in a different alphabet than
+/// decimal digits. Used by [otp_custom_formatted].
+pub trait OtpFormatter {
+    /// Formats the 31 bit truncated HMAC into a password of `digits` characters.
+    fn format(&self, truncated: u32, digits: u8) -> String;
+}
+
+/// The RFC 4226 formatter, the truncated HMAC modulo `10^digits` padded with leading 0s. This is how
+/// [otp_custom] formats its passwords.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecimalFormatter;
+
+impl OtpFormatter for DecimalFormatter {
+    fn format(&self, truncated: u32, digits: u8) -> String {
+        to_password(truncated, digits)
+    }
+}
+
+/// Formats passwords with the characters of a custom alphabet. Starting from the truncated HMAC, each
+/// character is the alphabet character at the remainder of dividing by the alphabet length, which is
+/// then divided by the length for the next character.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlphabetFormatter {
+    alphabet: Vec<char>,
+}
+
+impl AlphabetFormatter {
+    /// Creates a formatter for the alphabet, returns a [ThotpError::InvalidAlphabet] if it has fewer
+    /// than 2 characters.
+    pub fn new(alphabet: &str) -> Result<Self, ThotpError> {
+        let alphabet: Vec<char> = alphabet.chars().collect();
+        if alphabet.len() < 2 {
+            return Err(ThotpError::InvalidAlphabet);
+        }
+
+        Ok(Self { alphabet })
+    }
+}
+
+impl OtpFormatter for AlphabetFormatter {
+    fn format(&self, truncated: u32, digits: u8) -> String {
+        let len = self.alphabet.len() as u32;
+        let mut remaining = truncated;
+
+        (0..digits)
+            .map(|_| {
+                let character = self.alphabet[(remaining % len) as usize];
+                remaining /= len;
+                character
+            })
+            .collect()
+    }
+}
+
+/// The alphabet of Steam Guard passwords.
+const STEAM_ALPHABET: &str = "23456789BCDFGHJKMNPQRTVWXY";
+
+/// Formats passwords the way Steam Guard does, with a 26 character alphabet of digits and consonants.
+/// Steam passwords are 5 characters long, so use 5 digits along with SHA1 and a time step of 30.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SteamFormatter;
+
+impl OtpFormatter for SteamFormatter {
+    fn format(&self, truncated: u32, digits: u8) -> String {
+        let alphabet: Vec<char> = STEAM_ALPHABET.chars().collect();
+        AlphabetFormatter { alphabet }.format(truncated, digits)
+    }
+}
+
+/// The kind of a submitted password, as told apart by [classify_code].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CodeKind {
+    /// 6 to 8 decimal digits, like the passwords of RFC 4226 and RFC 6238
+    Decimal,
+
+    /// 5 characters of the Steam Guard alphabet, like the passwords of the [SteamFormatter]
+    SteamAlphabet,
+
+    /// Neither of the above
+    Unknown,
+}
+
+/// Tells apart decimal passwords from Steam Guard passwords by their length and characters, e.g. to
+/// route a submitted password to the right verifier when both kinds are accepted. Nothing is
+/// verified, and surrounding whitespace is not trimmed.
+///
+/// The Steam alphabet contains the digits 2 to 9, so a 5 character password made of them, such as
+/// `23456`, could be either kind. Since decimal passwords have at least 6 digits, it is classified as
+/// a Steam password. Steam passwords are upper case, lower case letters are not part of the alphabet.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{classify_code, CodeKind};
+///
+/// assert_eq!(classify_code("287082"), CodeKind::Decimal);
+/// assert_eq!(classify_code("2KC9W"), CodeKind::SteamAlphabet);
+/// assert_eq!(classify_code("12345"), CodeKind::Unknown);
+/// ```
+pub fn classify_code(code: &str) -> CodeKind {
+    let len = code.chars().count();
+
+    if (6..=8).contains(&len) && code.bytes().all(|byte| byte.is_ascii_digit()) {
+        CodeKind::Decimal
+    } else if len == 5 && code.chars().all(|c| STEAM_ALPHABET.contains(c)) {
+        CodeKind::SteamAlphabet
+    } else {
+        CodeKind::Unknown
+    }
+}
+
+/// Generates a one time password just like [otp_custom], but formats it with the given formatter.
+/// With the [DecimalFormatter] this is the same as [otp_custom].
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, otp_custom_formatted, DecimalFormatter, SteamFormatter, Sha1};
+///
+/// let secret = b"super secret";
+///
+/// let password = otp_custom_formatted::<Sha1>(secret, 1, 6, &DecimalFormatter).unwrap();
+/// assert_eq!(password, otp_custom::<Sha1>(secret, 1, 6).unwrap());
+///
+/// let steam = otp_custom_formatted::<Sha1>(secret, 1, 5, &SteamFormatter).unwrap();
+/// assert_eq!(steam.len(), 5);
+/// ```
+pub fn otp_custom_formatted<H>(
+    secret: &[u8],
+    nonce: u64,
+    digits: u8,
+    formatter: &dyn OtpFormatter,
+) -> Result<String, ThotpError>
+where
+    H: OtpHash,
+{
+    let hmac = hmac_digest::<H>(secret, &nonce.to_be_bytes())?;
+
+    Ok(formatter.format(dynamic_trunc(&hmac), digits))
+}
+
+/// A secret key with the HMAC state already initialized, for generating many passwords with the same
+/// secret without setting up the key every time. Each password is generated from a clone of the
+/// initialized state.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, HmacKey, Sha1};
+///
+/// let secret = b"super secret";
+/// let key = HmacKey::<Sha1>::new(secret).unwrap();
+///
+/// for nonce in 0..10 {
+///     assert_eq!(key.code(nonce, 6).unwrap(), otp_custom::<Sha1>(secret, nonce, 6).unwrap());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct HmacKey<H>
+where
+    H: OtpHash,
+{
+    mac: H::Mac,
+}
+
+impl<H> HmacKey<H>
+where
+    H: OtpHash,
+{
+    /// Initializes the HMAC state with the given secret.
+    pub fn new(secret: &[u8]) -> Result<Self, ThotpError> {
+        let mac = <H::Mac as KeyInit>::new_from_slice(secret)?;
+        Ok(Self { mac })
+    }
+
+    /// Generates a one time password for the given nonce and digits the same way [otp_custom] does.
+    pub fn code(&self, nonce: u64, digits: u8) -> Result<String, ThotpError> {
+        let mut mac = self.mac.clone();
+        mac.update(&nonce_message_bytes(nonce));
+        let hmac = mac.finalize().into_bytes();
+
+        // Truncate to 4 bytes
+        let trunc = dynamic_trunc(&hmac);
+
+        // Mod it with the number of digits and pad it with 0s to get the password
+        Ok(to_password(trunc, digits))
+    }
+}
+
+/// Generates a one time password the same way [otp_custom] does, except the algorithm is selected at
+/// runtime. The HMAC is computed by the [hmac] crate with the hashing algorithm matching the given one,
+/// so this function is not generic and the passwords are the same as those of [otp_custom].
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, otp_dyn, Algorithm, Sha256};
+///
+/// let secret = b"super secret";
+///
+/// assert_eq!(
+///     otp_dyn(Algorithm::Sha256, secret, 1, 6).unwrap(),
+///     otp_custom::<Sha256>(secret, 1, 6).unwrap()
+/// );
+/// ```
+pub fn otp_dyn(
+    algorithm: Algorithm,
+    secret: &[u8],
+    nonce: u64,
+    digits: u8,
+) -> Result<String, ThotpError> {
+    // Transform to bytes
+    let nonce = &nonce.to_be_bytes();
+
+    // Create an HMAC digest with the given key, nonce and algorithm and truncate it to 4 bytes
+    let trunc = match algorithm {
+        Algorithm::Sha1 => dynamic_trunc(&hmac_digest::<Sha1>(secret, nonce)?),
+        Algorithm::Sha256 => dynamic_trunc(&hmac_digest::<Sha256>(secret, nonce)?),
+        Algorithm::Sha512 => dynamic_trunc(&hmac_digest::<Sha512>(secret, nonce)?),
+    };
+
+    // Mod it with the number of digits and pad it with 0s to get the password
+    Ok(to_password(trunc, digits))
+}
+
+/// Verifies the given password against an externally computed HMAC, e.g. one computed by an HSM
+/// which holds the secret.
+///
+/// The `hmac` is dynamically truncated and reduced to `digits` the same way [otp_custom] does it, after which
+/// the resulting password is compared to the given one in constant time.
+///
+/// Returns an error if the `hmac` is shorter than 20 bytes or if `digits` is not in the range of `6..=10`.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::verify_from_hmac;
+///
+/// // HMAC-SHA1 of the RFC 4226 secret `12345678901234567890` and the counter 0
+/// let hmac = [
+///     0xcc, 0x93, 0xcf, 0x18, 0x50, 0x8d, 0x94, 0x93, 0x4c, 0x64, 0xb6, 0x5d, 0x8b, 0xa7, 0x66,
+///     0x7f, 0xb7, 0xcd, 0xe4, 0xb0,
+/// ];
+///
+/// assert!(verify_from_hmac(&hmac, "755224", 6).unwrap());
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_from_hmac(hmac: &[u8], password: &str, digits: u8) -> Result<bool, ThotpError> {
+    if hmac.len() < 20 {
+        return Err(ThotpError::InvalidLength(digest::InvalidLength));
+    }
+
+    if !(6..=10).contains(&digits) {
+        return Err(ThotpError::InvalidDigits);
+    }
+
+    if password.len() != digits as usize {
+        return Ok(false);
+    }
+
+    // Truncate to 4 bytes
+    let trunc = dynamic_trunc(hmac);
+
+    // Mod it with the number of digits for the password, u64 so 10 digits do not overflow
+    let result = trunc as u64 % digit_modulus(digits);
+    let pass = format!("{:0width$}", result, width = digits as usize);
+
+    Ok(ct_eq(pass.as_bytes(), password.as_bytes()))
+}
+
+/// A digit length known at compile time. Using a length outside of the accepted range of `6..=10`
+/// fails to compile once [Digits::VALUE] is used, i.e. when calling [otp_custom_const].
+///
+/// This complements the runtime digits parameter of the other functions in this module for callers
+/// who know the digit length statically and want no runtime checks.
+///
+/// ```compile_fail
+/// use thotp::custom::{otp_custom_const, Sha1};
+///
+/// // Fails to compile since 0 digits are out of range
+/// let password = otp_custom_const::<Sha1, 0>(b"super secret", 1);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Digits<const N: u8>;
+
+impl<const N: u8> Digits<N> {
+    /// The digit length, evaluated at compile time.
+    pub const VALUE: u8 = {
+        assert!(
+            N >= 6 && N <= 10,
+            "Invalid digits provided, the minimum is 6 and the maximum is 10"
+        );
+        N
+    };
+
+    /// The modulus reducing a truncated HMAC to a password of the digit length, evaluated at
+    /// compile time.
+    pub const MODULUS: u64 = digit_modulus(Self::VALUE);
+}
+
+/// Generates a one time password using the given secret, nonce and algorithm with a digit length
+/// checked at compile time.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, otp_custom_const, Sha1};
+///
+/// let secret = b"super secret";
+///
+/// let password = otp_custom_const::<Sha1, 6>(secret, 1).unwrap();
+///
+/// assert_eq!(password, otp_custom::<Sha1>(secret, 1, 6).unwrap());
+/// ```
+pub fn otp_custom_const<H, const DIGITS: u8>(
+    secret: &[u8],
+    nonce: u64,
+) -> Result<String, ThotpError>
+where
+    H: OtpHash,
+{
+    let digits = Digits::<DIGITS>::VALUE;
+
+    // Transform to bytes
+    let nonce = &nonce.to_be_bytes();
+
+    // Create an HMAC digest with the given key, nonce and algorithm
+    let hmac = hmac_digest::<H>(secret, nonce)?;
+
+    // Truncate to 4 bytes
+    let trunc = dynamic_trunc(&hmac);
+
+    // Mod it with the modulus computed at compile time
+    let result = trunc as u64 % Digits::<DIGITS>::MODULUS;
+
+    Ok(format!("{:0width$}", result, width = digits as usize))
+}
+
+/// Verifies the given password for the given timestamp and secret.
+///
+/// Uses the provided algorithm, digit length and time step
+/// to generate a password to compare with the given one in the range of
+///
+/// `[-allowed_drift, allowed_drift]`
+///
+/// time slices. If a `timestamp` of 0
+/// is provided, the current system time will be used for the calculation.
+///
+/// The function returns a tuple whose first element is a boolean indicating whether any
+/// of the passwords in the allowed drift match and the second element is a number
+/// indicating the number of time slices the valid password deviates from the current
+/// time slice.
+///
+/// Passwords whose length differs from `digits` are rejected without generating any passwords,
+/// passwords of the right length are compared in constant time.
+///
+/// The window is clamped to the `u64` range, so for the first and last `allowed_drift` time slices it
+/// only extends to one side. The discrepancy is always relative to the current time slice, even when
+/// the window is clamped. It is positive if the password is from a later slice than the current one,
+/// i.e. the client's clock is ahead, and negative if it is from an earlier one, see [Discrepancy].
+///
+/// All slices in the window are always compared. If the password matches more than one of them, which
+/// becomes likely with few digits, the discrepancy of the match closest to the current slice is
+/// returned.
+///
+/// ## Example
+/// ```
+/// // An example from RFC 6238 with SHA1
+/// use thotp::custom::{otp_custom, verify_totp_custom};
+/// use thotp::custom::Sha1;
+///
+/// // The internal constants used by the default otp functions
+/// const TIME_STEP: u8 = 30;
+/// const ALLOWED_DRIFT: u8 = 1;
+///
+/// let secret = b"12345678901234567890";
+///
+/// let pairs = vec![
+///     ("94287082", 59),
+///     ("07081804", 1111111109),
+///     ("14050471", 1111111111),
+///     ("89005924", 1234567890),
+///     ("69279037", 2000000000),
+///     ("65353130", 20000000000),
+/// ];
+///
+/// pairs.into_iter().for_each(|(expected, timestamp)| {
+///     // When generating a totp the provided unix time is divided by the time step
+///     assert_eq!(
+///         expected,
+///         otp_custom::<Sha1>(secret, timestamp / TIME_STEP as u64, 8).unwrap()
+///     );
+///
+///     // The verify function does this internally
+///     let (result, discrepancy) = verify_totp_custom::<Sha1>(
+///         expected, secret, timestamp, 8, TIME_STEP, ALLOWED_DRIFT
+///     )
+///     .unwrap();
+///
+///     assert_eq!((true, 0), (result, discrepancy));
+/// });
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_custom<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    // Passwords of the wrong length can never match, so skip generating any
+    if password.len() != digits as usize {
+        return Ok((false, 0));
+    }
+
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    // The window is clamped to the u64 range, so near 0 and u64::MAX it only extends to one side
+    verify_slices::<H>(password, secret, nonce, digits, allowed_drift)
+}
+
+/// Verifies a TOTP the same way [verify_totp_custom] does, except the time counter is encoded with the
+/// given [CounterEncoding]. Use this only to interoperate with tokens that do not follow RFC 6238, such
+/// as legacy tokens with a 32 bit time counter. With [CounterEncoding::Rfc8Byte] this is the same as
+/// [verify_totp_custom].
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom_encoded, verify_totp_encoded, CounterEncoding, Sha1};
+///
+/// let secret = b"super secret";
+/// let legacy = CounterEncoding::Truncated4Byte;
+/// let password = otp_custom_encoded::<Sha1>(secret, 1_000_000_000 / 30, 6, legacy).unwrap();
+///
+/// assert_eq!(
+///     verify_totp_encoded::<Sha1>(&password, secret, 1_000_000_000, 6, 30, 1, legacy).unwrap(),
+///     (true, 0)
+/// );
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_encoded<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    encoding: CounterEncoding,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    // The RFC encoding is the one verify_totp_custom uses
+    if encoding == CounterEncoding::Rfc8Byte {
+        return verify_totp_custom::<H>(password, secret, timestamp, digits, step, allowed_drift);
+    }
+
+    // Passwords of the wrong length can never match, so skip generating any
+    if password.len() != digits as usize {
+        return Ok((false, 0));
+    }
+
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    let drift = allowed_drift as i16;
+
+    verify_window(password, nonce, -drift..=drift, |n| {
+        otp_custom_encoded::<H>(secret, n, digits, encoding)
+    })
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does with an allowed drift of 0, so
+/// only the password of the current time slice is accepted and passwords of adjacent slices are not,
+/// for deployments that tolerate no clock drift at all.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_strict, Sha1};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 1_000_000_000 / 30, 6).unwrap();
+///
+/// assert!(verify_totp_strict::<Sha1>(&password, secret, 1_000_000_000, 6, 30).unwrap());
+/// assert!(!verify_totp_strict::<Sha1>(&password, secret, 1_000_000_030, 6, 30).unwrap());
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_strict<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+) -> Result<bool, ThotpError>
+where
+    H: OtpHash,
+{
+    let (valid, _) = verify_totp_custom::<H>(password, secret, timestamp, digits, step, 0)?;
+    Ok(valid)
+}
+
+/// Verifies the given password against the current time slice and the one right before it, but never
+/// against a future slice. This covers users who read the password near the end of a slice and submit
+/// it right after the next one started, without also accepting the next slice's password like an
+/// allowed drift of 1 would.
+///
+/// The discrepancy is `0` for the current slice and `-1` for the previous one.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_prev_grace, Sha1};
+///
+/// let secret = b"super secret";
+/// let previous = otp_custom::<Sha1>(secret, 99, 6).unwrap();
+/// let next = otp_custom::<Sha1>(secret, 101, 6).unwrap();
+///
+/// assert_eq!(verify_totp_prev_grace::<Sha1>(&previous, secret, 3001, 6, 30).unwrap(), (true, -1));
+/// assert_eq!(verify_totp_prev_grace::<Sha1>(&next, secret, 3001, 6, 30).unwrap(), (false, 0));
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_prev_grace<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok((false, 0));
+    }
+
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    let key = HmacKey::<H>::new(secret)?;
+    let mut matched: Option<i16> = None;
+
+    // Both slices are always checked, the current one wins if the password matches both
+    for discrepancy in [-1, 0] {
+        let n = match nonce.checked_add_signed(discrepancy as i64) {
+            Some(n) => n,
+            None => continue,
+        };
+
+        if ct_eq(key.code(n, digits)?.as_bytes(), password.as_bytes()) {
+            matched = Some(closest_match(matched, discrepancy));
+        }
+    }
+
+    Ok(matched.map_or((false, 0), |discrepancy| (true, discrepancy)))
+}
+
+/// Verifies the given password in the range of `[-allowed_drift, allowed_drift]` time slices and
+/// returns the number of HMACs computed along with the outcome, e.g. to find out how often passwords
+/// match at the edges of the window and size it accordingly.
+///
+/// Unlike [verify_totp_custom], the slices are checked from the current one outwards, i.e. `0`, `-1`,
+/// `1`, `-2`, `2` and so on, stopping at the first match. A password of the current slice therefore
+/// costs a single HMAC, and a password matching no slice costs one for every slice in the window. The
+/// time taken reveals how far the matched slice is from the current one, but not the password.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_instrumented, Sha1};
+/// use thotp::VerifyOutcome;
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 101, 6).unwrap();
+///
+/// let (outcome, hmacs) = verify_totp_instrumented::<Sha1>(&password, secret, 3000, 6, 30, 2).unwrap();
+///
+/// assert_eq!(outcome, VerifyOutcome::Accepted { drift: 1 });
+/// assert_eq!(hmacs, 3);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_instrumented<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<(VerifyOutcome, usize), ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok((VerifyOutcome::Malformed, 0));
+    }
+
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    let key = HmacKey::<H>::new(secret)?;
+    let mut hmacs = 0;
+
+    let drift = allowed_drift as i16;
+    let order = (1..=drift).flat_map(|distance| [-distance, distance]);
+
+    for discrepancy in std::iter::once(0).chain(order) {
+        let n = match nonce.checked_add_signed(discrepancy as i64) {
+            Some(n) => n,
+            None => continue,
+        };
+
+        hmacs += 1;
+        if ct_eq(key.code(n, digits)?.as_bytes(), password.as_bytes()) {
+            return Ok((VerifyOutcome::Accepted { drift: discrepancy }, hmacs));
+        }
+    }
+
+    Ok((VerifyOutcome::Rejected, hmacs))
+}
+
+/// The least number of items [verify_totp_batch] verifies per thread.
+#[cfg(feature = "parallel")]
+const BATCH_CHUNK_MIN: usize = 32;
+
+/// Verifies a batch of passwords, each with its own secret, for the same timestamp, e.g. for a job
+/// reauthenticating many users at once. Returns the outcome of each item in the same order as the
+/// `items`.
+///
+/// Each password is verified the same way [verify_totp_custom] does, i.e. every time slice in the
+/// window is always compared in constant time. Passwords of the wrong length or containing anything
+/// but digits are [VerifyOutcome::Malformed].
+///
+/// The items are verified one after the other on the calling thread. With the `parallel` feature
+/// they are split evenly between as many threads as the system has cores instead, with at least 32
+/// items per thread since spawning one costs more than verifying a few passwords. A batch of up to
+/// 32 items is still verified on the calling thread.
+///
+/// If a `timestamp` of 0 is provided, the current system time will be used for the calculation. It
+/// is read once, so every item is verified for the same time.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_batch, Secret, Sha1};
+/// use thotp::VerifyOutcome;
+///
+/// let john = Secret::new(b"john's secret".to_vec());
+/// let jane = Secret::new(b"jane's secret".to_vec());
+/// let password = otp_custom::<Sha1>(john.as_bytes(), 1_000_000_000 / 30, 6).unwrap();
+///
+/// let items = [(john, password.as_str()), (jane, password.as_str())];
+/// let outcomes = verify_totp_batch::<Sha1>(&items, 1_000_000_000, 6, 30, 1);
+///
+/// assert_eq!(outcomes[0].as_ref().unwrap(), &VerifyOutcome::Accepted { drift: 0 });
+/// assert_eq!(outcomes[1].as_ref().unwrap(), &VerifyOutcome::Rejected);
+/// ```
+pub fn verify_totp_batch<H>(
+    items: &[(Secret, &str)],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Vec<Result<VerifyOutcome, ThotpError>>
+where
+    H: OtpHash,
+{
+    let timestamp = if timestamp == 0 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+    } else {
+        Ok(timestamp)
+    };
+
+    let verify = |(secret, password): &(Secret, &str)| {
+        let timestamp = timestamp.clone()?;
+
+        if password.len() != digits as usize || !password.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(VerifyOutcome::Malformed);
+        }
+
+        let (valid, drift) = verify_totp_custom::<H>(
+            password,
+            secret.as_bytes(),
+            timestamp,
+            digits,
+            step,
+            allowed_drift,
+        )?;
+
+        Ok(match valid {
+            true => VerifyOutcome::Accepted { drift },
+            false => VerifyOutcome::Rejected,
+        })
+    };
+
+    #[cfg(feature = "parallel")]
+    {
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        let chunk = items.len().div_ceil(threads).max(BATCH_CHUNK_MIN);
+
+        if chunk < items.len() {
+            return std::thread::scope(|scope| {
+                let handles: Vec<_> = items
+                    .chunks(chunk)
+                    .map(|items| scope.spawn(move || items.iter().map(verify).collect::<Vec<_>>()))
+                    .collect();
+
+                handles
+                    .into_iter()
+                    .flat_map(|handle| {
+                        handle
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                    })
+                    .collect()
+            });
+        }
+    }
+
+    items.iter().map(verify).collect()
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, but if it does not match, also
+/// accepts it with any single pair of adjacent digits swapped, e.g. `123465` for `123456`. Meant as an
+/// opt-in accessibility mode for users who tend to transpose digits, such as users with dyslexia.
+///
+/// #### Warning!
+/// **This enlarges the set of accepted passwords.** Besides the password itself, up to `digits - 1`
+/// transpositions of it are accepted for every time slice in the window, so a guessed 6 digit
+/// password is up to 6 times more likely to be accepted. Only use it where that reduction is
+/// acceptable, ideally together with attempt limiting such as the
+/// [AttemptLimiter](super::server::AttemptLimiter).
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_custom, verify_totp_transposition_tolerant, Sha1};
+///
+/// let secret = b"12345678901234567890";
+///
+/// // The password of this timestamp is 287082
+/// assert_eq!(otp_custom::<Sha1>(secret, 1, 6).unwrap(), "287082");
+///
+/// let (valid, _) = verify_totp_transposition_tolerant::<Sha1>("280782", secret, 30, 6, 30, 0).unwrap();
+/// assert!(valid);
+///
+/// let (valid, _) = verify_totp_custom::<Sha1>("280782", secret, 30, 6, 30, 0).unwrap();
+/// assert!(!valid);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_transposition_tolerant<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok((false, 0));
+    }
+
+    let timestamp = resolve_timestamp(timestamp)?;
+
+    let exact = verify_totp_custom::<H>(password, secret, timestamp, digits, step, allowed_drift)?;
+    if exact.0 {
+        return Ok(exact);
+    }
+
+    let mut transposed: Vec<char> = password.chars().collect();
+    let mut matched: Option<i16> = None;
+
+    for i in 1..transposed.len() {
+        // Swapping equal digits results in the password itself, which already did not match
+        if transposed[i - 1] == transposed[i] {
+            continue;
+        }
+
+        transposed.swap(i - 1, i);
+        let candidate: String = transposed.iter().collect();
+        transposed.swap(i - 1, i);
+
+        let (valid, discrepancy) =
+            verify_totp_custom::<H>(&candidate, secret, timestamp, digits, step, allowed_drift)?;
+        if valid {
+            matched = Some(closest_match(matched, discrepancy));
+        }
+    }
+
+    Ok(matched.map_or((false, 0), |discrepancy| (true, discrepancy)))
+}
+
+/// Maps a letter to its digit on a phone keypad, e.g. `A`, `B` and `C` to `2`. Any other character is
+/// returned as is.
+fn t9_digit(c: char) -> char {
+    match c.to_ascii_uppercase() {
+        'A'..='C' => '2',
+        'D'..='F' => '3',
+        'G'..='I' => '4',
+        'J'..='L' => '5',
+        'M'..='O' => '6',
+        'P'..='S' => '7',
+        'T'..='V' => '8',
+        'W'..='Z' => '9',
+        _ => c,
+    }
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, after mapping the letters in it
+/// to their digits on a phone keypad, e.g. `ADGJMP` to `234567`. Meant as an opt-in mode for partners
+/// whose legacy keypads let users enter letters instead of digits. Passwords consisting of digits
+/// only are verified as they are.
+///
+/// #### Warning!
+/// **This accepts several inputs for every password.** Each digit from 2 to 9 can also be entered as
+/// any of its 3 or 4 letters, so there are more ways to enter a valid password, but no more valid
+/// passwords to guess since the letters are mapped back before comparing. The leniency is mostly a
+/// concern where inputs are logged or compared elsewhere, as the same password can appear in different
+/// forms.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_t9_lenient, Sha1};
+///
+/// let secret = b"12345678901234567890";
+///
+/// // The password of this timestamp is 287082
+/// assert_eq!(otp_custom::<Sha1>(secret, 1, 6).unwrap(), "287082");
+///
+/// let (valid, _) = verify_totp_t9_lenient::<Sha1>("2tp0v2", secret, 30, 6, 30, 0).unwrap();
+/// assert!(valid);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_t9_lenient<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    if !password.chars().any(|c| c.is_ascii_alphabetic()) {
+        return verify_totp_custom::<H>(password, secret, timestamp, digits, step, allowed_drift);
+    }
+
+    let mapped: String = password.chars().map(t9_digit).collect();
+
+    verify_totp_custom::<H>(&mapped, secret, timestamp, digits, step, allowed_drift)
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, except the time step is a `u32`
+/// so periods longer than 255 seconds can be used. Returns a [ThotpError::InvalidStep] if the period
+/// is 0.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_period, Sha1};
+///
+/// let secret = b"super secret";
+/// let timestamp = 1_000_000_000;
+///
+/// // A 5 minute period
+/// let password = otp_custom::<Sha1>(secret, timestamp / 300, 6).unwrap();
+///
+/// let (valid, discrepancy) = verify_totp_period::<Sha1>(&password, secret, timestamp, 6, 300, 1).unwrap();
+/// assert!(valid);
+/// assert_eq!(discrepancy, 0);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_period<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    period: u32,
+    allowed_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    if period == 0 {
+        return Err(ThotpError::InvalidStep);
+    }
+
+    // Passwords of the wrong length can never match, so skip generating any
+    if password.len() != digits as usize {
+        return Ok((false, 0));
+    }
+
+    let timestamp = resolve_timestamp(timestamp)?;
+
+    verify_slices::<H>(
+        password,
+        secret,
+        timestamp / period as u64,
+        digits,
+        allowed_drift,
+    )
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, except a `timestamp` of 0 is
+/// the unix epoch itself instead of the current time. The system time is never used, so the result
+/// only depends on the arguments.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_at_exact, Sha1};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 0, 6).unwrap();
+///
+/// assert_eq!(verify_totp_at_exact::<Sha1>(&password, secret, 0, 6, 30, 1).unwrap(), (true, 0));
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_at_exact<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok((false, 0));
+    }
+
+    verify_slices::<H>(
+        password,
+        secret,
+        checked_time_slice(timestamp, step)?,
+        digits,
+        allowed_drift,
+    )
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does and returns the number of
+/// seconds until the time slice it matched ends, or `None` if it did not match. A password of a
+/// previous slice accepted because of the drift has already expired and reports 0.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_with_expiry, Sha1};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 100, 6).unwrap();
+///
+/// let remaining = verify_totp_with_expiry::<Sha1>(&password, secret, 3010, 6, 30, 1).unwrap();
+/// assert_eq!(remaining, Some(20));
+///
+/// let remaining = verify_totp_with_expiry::<Sha1>("000000", secret, 3010, 6, 30, 1).unwrap();
+/// assert_eq!(remaining, None);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_with_expiry<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<Option<u64>, ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok(None);
+    }
+
+    let timestamp = resolve_timestamp(timestamp)?;
+    let nonce = checked_time_slice(timestamp, step)?;
+
+    match verify_slices::<H>(password, secret, nonce, digits, allowed_drift)? {
+        (true, discrepancy) => {
+            let matched = nonce.saturating_add_signed(discrepancy as i64);
+            let expires_at = matched.saturating_add(1).saturating_mul(step as u64);
+            Ok(Some(expires_at.saturating_sub(timestamp)))
+        }
+        (false, _) => Ok(None),
+    }
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does and returns the absolute time
+/// slice it matched, i.e. `timestamp / step + discrepancy`, or `None` if it did not match. Useful for
+/// systems indexing events by time slice, and the returned slice can be used as the watermark of a
+/// [TotpReplayGuard](super::server::TotpReplayGuard).
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_slice, Sha1};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 99, 6).unwrap();
+///
+/// assert_eq!(verify_totp_slice::<Sha1>(&password, secret, 3010, 6, 30, 1).unwrap(), Some(99));
+/// assert_eq!(verify_totp_slice::<Sha1>("000000", secret, 3010, 6, 30, 1).unwrap(), None);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_slice<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<Option<u64>, ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok(None);
+    }
+
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    match verify_slices::<H>(password, secret, nonce, digits, allowed_drift)? {
+        (true, discrepancy) => Ok(nonce.checked_add_signed(discrepancy as i64)),
+        (false, _) => Ok(None),
+    }
+}
+
+/// Verifies the given password against the passwords of the given absolute time slices, e.g. when the
+/// slices are agreed on out of band instead of being a contiguous window around the current time.
+/// Every slice is checked, and the first listed slice that matched is returned, or `None` if none of
+/// them did.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_slices, Sha1};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 42, 6).unwrap();
+///
+/// assert_eq!(verify_totp_slices::<Sha1>(&password, secret, &[7, 42, 99], 6).unwrap(), Some(42));
+/// assert_eq!(verify_totp_slices::<Sha1>(&password, secret, &[41, 43], 6).unwrap(), None);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_slices<H>(
+    password: &str,
+    secret: &[u8],
+    slices: &[u64],
+    digits: u8,
+) -> Result<Option<u64>, ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok(None);
+    }
+
+    let mut matched = None;
+
+    for &slice in slices {
+        let pass = otp_custom::<H>(secret, slice, digits)?;
+        if ct_eq(pass.as_bytes(), password.as_bytes()) && matched.is_none() {
+            matched = Some(slice);
+        }
+    }
+
+    Ok(matched)
+}
+
+/// Returns the passwords [verify_totp_custom] currently accepts for the secret, one for every time
+/// slice in the range of `[-allowed_drift, allowed_drift]` along with its discrepancy, ordered from
+/// the oldest to the newest slice. Meant for support tools comparing them to what a user's
+/// authenticator app shows.
+///
+/// #### Warning!
+/// **The returned passwords are valid right now.** Anyone who sees them can log in as the user, so
+/// only show them in privileged tools and never log or store them.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{accepted_codes_now, Sha1};
+///
+/// let codes = accepted_codes_now::<Sha1>(b"super secret", 30, 6, 1).unwrap();
+///
+/// let drifts: Vec<i16> = codes.iter().map(|(drift, _)| *drift).collect();
+/// assert_eq!(drifts, [-1, 0, 1]);
+/// ```
+pub fn accepted_codes_now<H>(
+    secret: &[u8],
+    step: u8,
+    digits: u8,
+    allowed_drift: u8,
+) -> Result<Vec<(i16, String)>, ThotpError>
+where
+    H: OtpHash,
+{
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let nonce = checked_time_slice(now, step)?;
+    let key = HmacKey::<H>::new(secret)?;
+    let drift = allowed_drift as i16;
+
+    let mut codes = Vec::with_capacity(2 * allowed_drift as usize + 1);
+    for discrepancy in -drift..=drift {
+        if let Some(n) = nonce.checked_add_signed(discrepancy as i64) {
+            codes.push((discrepancy, key.code(n, digits)?));
+        }
+    }
+
+    Ok(codes)
+}
+
+/// Compares the password to the passwords of the time slices in the range of
+/// `[nonce - allowed_drift, nonce + allowed_drift]`, skipping slices outside of the `u64` range. The
+/// closest match is returned if there are several.
+fn verify_slices<H>(
+    password: &str,
+    secret: &[u8],
+    nonce: u64,
+    digits: u8,
+    allowed_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    let drift = allowed_drift as i16;
+
+    verify_window(password, nonce, -drift..=drift, |n| {
+        otp_custom::<H>(secret, n, digits)
+    })
+}
+
+/// Compares the password to the passwords `code` generates for the time slices whose discrepancy to
+/// `nonce` is in the given window, skipping slices outside of the `u64` range. Every slice is compared
+/// and the closest match is returned if there are several.
+fn verify_window<F>(
+    password: &str,
+    nonce: u64,
+    window: RangeInclusive<i16>,
+    mut code: F,
+) -> Result<(bool, i16), ThotpError>
+where
+    F: FnMut(u64) -> Result<String, ThotpError>,
+{
+    let mut matched: Option<i16> = None;
+
+    for discrepancy in window {
+        let n = match nonce.checked_add_signed(discrepancy as i64) {
+            Some(n) => n,
+            None => continue,
+        };
+
+        if ct_eq(code(n)?.as_bytes(), password.as_bytes()) {
+            matched = Some(closest_match(matched, discrepancy));
+        }
+    }
+
+    Ok(matched.map_or((false, 0), |discrepancy| (true, discrepancy)))
+}
+
+/// Returns whichever of the discrepancies is closer to the current time slice, preferring the earlier
+/// match on ties. With few digits a password can match more than one slice in the window, in which
+/// case the closest one is the most likely to be the slice it was generated for.
+fn closest_match(matched: Option<i16>, discrepancy: i16) -> i16 {
+    match matched {
+        Some(matched) if matched.abs() <= discrepancy.abs() => matched,
+        _ => discrepancy,
+    }
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, except the secret is only
+/// obtained from `secret_fn` right before the verification, e.g. by decrypting it, and is overwritten
+/// with 0s as soon as the verification is done, whether it succeeded or not. This keeps the plaintext
+/// secret in memory for as short as possible. Copies of the key made internally by the HMAC are not
+/// covered by this.
+///
+/// The secret can be any buffer of bytes, e.g. a `Vec<u8>` or a mutable reference to a buffer the
+/// caller decrypts into and keeps. Any error returned from `secret_fn` is returned as is.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_with, Sha1};
+///
+/// let password = otp_custom::<Sha1>(b"super secret", 1_000_000_000 / 30, 6).unwrap();
+///
+/// let (valid, _) = verify_totp_with::<Sha1, _>(
+///     || Ok(b"super secret".to_vec()),
+///     &password,
+///     1_000_000_000,
+///     6,
+///     30,
+///     1,
+/// )
+/// .unwrap();
+///
+/// assert!(valid);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_with<H, S>(
+    secret_fn: impl FnOnce() -> Result<S, ThotpError>,
+    password: &str,
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+    S: AsMut<[u8]>,
+{
+    let mut secret = secret_fn()?;
+    let secret = secret.as_mut();
+
+    let result = verify_totp_period::<H>(
+        password,
+        secret,
+        timestamp,
+        digits,
+        step as u32,
+        allowed_drift,
+    );
+
+    zeroize(secret);
+    result
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, except `secret_transform` is
+/// applied to the secret first and the password is verified with the secret it returns. Meant as an
+/// escape hatch for migrating devices provisioned by legacy systems which altered secrets before
+/// using them, e.g. by truncating them, and should be phased out once those devices are
+/// re-provisioned.
+///
+/// The transformed secret is overwritten with 0s as soon as the verification is done.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_transformed, Sha1};
+///
+/// // The legacy system truncated Base32 secrets to 16 characters, i.e. to their first 10 bytes
+/// let secret = b"12345678901234567890";
+/// let password = otp_custom::<Sha1>(&secret[..10], 1_000_000_000 / 30, 6).unwrap();
+///
+/// let (valid, _) = verify_totp_transformed::<Sha1>(
+///     &password,
+///     secret,
+///     1_000_000_000,
+///     6,
+///     30,
+///     1,
+///     |secret| secret[..10].to_vec(),
+/// )
+/// .unwrap();
+///
+/// assert!(valid);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_transformed<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    secret_transform: impl Fn(&[u8]) -> Vec<u8>,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    let mut secret = secret_transform(secret);
+
+    let result = verify_totp_custom::<H>(password, &secret, timestamp, digits, step, allowed_drift);
+
+    zeroize(&mut secret);
+    result
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, with the secret returned by
+/// the given future, e.g. one fetching it from a remote key management service. Only fetching the
+/// secret is awaited, the verification itself does not block and runs right after, so this can be
+/// used with any async runtime.
+///
+/// Errors returned by `fetch` are returned as they are. The fetched secret is overwritten with 0s as
+/// soon as the verification is done.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_async_secret, Sha1};
+/// # use std::future::Future;
+/// # use std::pin::pin;
+/// # use std::task::{Context, Poll, Waker};
+/// #
+/// # fn block_on<F: Future>(future: F) -> F::Output {
+/// #     let mut future = pin!(future);
+/// #     let mut cx = Context::from_waker(Waker::noop());
+/// #     loop {
+/// #         if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+/// #             return output;
+/// #         }
+/// #     }
+/// # }
+///
+/// let password = otp_custom::<Sha1>(b"super secret", 1_000_000_000 / 30, 6).unwrap();
+/// let fetch = async { Ok(b"super secret".to_vec()) };
+///
+/// let verification = verify_totp_async_secret::<Sha1>(fetch, &password, 1_000_000_000, 6, 30, 1);
+///
+/// // Awaited by the runtime of choice
+/// assert_eq!(block_on(verification).unwrap(), (true, 0));
+/// ```
+pub async fn verify_totp_async_secret<H>(
+    fetch: impl Future<Output = Result<Vec<u8>, ThotpError>>,
+    password: &str,
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    let mut secret = fetch.await?;
+
+    let result = verify_totp_custom::<H>(password, &secret, timestamp, digits, step, allowed_drift);
+
+    zeroize(&mut secret);
+    result
+}
+
+/// Verifies the given password against every one of the given algorithms, in the range of
+/// `[-allowed_drift, allowed_drift]` time slices. Returns the algorithm the password matched along
+/// with the discrepancy, or `None` if it matched none of them, e.g. to accept passwords from both
+/// SHA1 and SHA256 credentials while migrating between them.
+///
+/// All passwords of all algorithms are always generated and compared, so the time taken does not
+/// reveal which algorithm matched. If the password matches more than one, the first one in
+/// `algorithms` is returned.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_algorithms, Algorithm, Sha256};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha256>(secret, 1_000_000_000 / 30, 6).unwrap();
+///
+/// let matched = verify_totp_algorithms(
+///     &password,
+///     secret,
+///     1_000_000_000,
+///     6,
+///     30,
+///     1,
+///     &[Algorithm::Sha1, Algorithm::Sha256],
+/// )
+/// .unwrap();
+///
+/// assert_eq!(matched, Some((Algorithm::Sha256, 0)));
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_algorithms(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    algorithms: &[Algorithm],
+) -> Result<Option<(Algorithm, i16)>, ThotpError> {
+    if password.len() != digits as usize {
+        return Ok(None);
+    }
+
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    let drift = allowed_drift as i16;
+    let mut matched = None;
+
+    for &algorithm in algorithms {
+        for discrepancy in -drift..=drift {
+            let n = match nonce.checked_add_signed(discrepancy as i64) {
+                Some(n) => n,
+                None => continue,
+            };
+
+            let pass = otp_dyn(algorithm, secret, n, digits)?;
+            if ct_eq(pass.as_bytes(), password.as_bytes()) && matched.is_none() {
+                matched = Some((algorithm, discrepancy));
+            }
+        }
+    }
+
+    Ok(matched)
+}
+
+/// Verifies the given password against every one of the given secrets, in the range of
+/// `[-allowed_drift, allowed_drift]` time slices. Returns the index of the secret the password
+/// matched along with the discrepancy, or `None` if it matched none of them, e.g. to accept passwords
+/// of both the old and the new secret while rotating it.
+///
+/// All passwords of all secrets are always generated and compared, so the time taken does not reveal
+/// which secret matched. If the password matches more than one, the first one in `secrets` is returned.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_multi_secret, Sha1};
+///
+/// let (old, new): (&[u8], &[u8]) = (b"old secret", b"new secret");
+/// let password = otp_custom::<Sha1>(old, 1_000_000_000 / 30, 6).unwrap();
+///
+/// let matched =
+///     verify_totp_multi_secret::<Sha1>(&password, &[new, old], 1_000_000_000, 6, 30, 1).unwrap();
+///
+/// assert_eq!(matched, Some((1, 0)));
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_multi_secret<H>(
+    password: &str,
+    secrets: &[&[u8]],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<Option<(usize, i16)>, ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok(None);
+    }
+
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    let drift = allowed_drift as i16;
+    let mut matched = None;
+
+    for (index, secret) in secrets.iter().enumerate() {
+        for discrepancy in -drift..=drift {
+            let n = match nonce.checked_add_signed(discrepancy as i64) {
+                Some(n) => n,
+                None => continue,
+            };
+
+            let pass = otp_custom::<H>(secret, n, digits)?;
+            if ct_eq(pass.as_bytes(), password.as_bytes()) && matched.is_none() {
+                matched = Some((index, discrepancy));
+            }
+        }
+    }
+
+    Ok(matched)
+}
+
+/// Verifies TOTPs of a secret being rotated. Passwords of the new secret are always accepted, while
+/// passwords of the old one are only accepted before the end of the grace window, so users have time
+/// to add the new secret to their authenticator. Usually obtained from
+/// [rotate_secret](super::qr::rotate_secret).
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, GraceVerifier, Sha1};
+///
+/// let verifier = GraceVerifier::<Sha1>::new(b"old".to_vec(), b"new".to_vec(), 2_000, 6, 30, 1);
+/// let old_password = otp_custom::<Sha1>(b"old", 1_000 / 30, 6).unwrap();
+///
+/// assert!(verifier.verify(&old_password, 1_000).unwrap().0);
+/// assert!(!verifier.verify(&old_password, 3_000).unwrap().0);
+/// ```
+#[derive(Clone)]
+pub struct GraceVerifier<H> {
+    old_secret: Vec<u8>,
+    new_secret: Vec<u8>,
+    grace_until: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    _hash: std::marker::PhantomData<fn() -> H>,
+}
+
+impl<H> GraceVerifier<H>
+where
+    H: OtpHash,
+{
+    /// Creates a verifier accepting passwords of the old secret until the unix timestamp `grace_until`
+    /// and passwords of the new secret indefinitely, in the range of `[-allowed_drift, allowed_drift]`
+    /// time slices.
+    pub fn new(
+        old_secret: Vec<u8>,
+        new_secret: Vec<u8>,
+        grace_until: u64,
+        digits: u8,
+        step: u8,
+        allowed_drift: u8,
+    ) -> Self {
+        Self {
+            old_secret,
+            new_secret,
+            grace_until,
+            digits,
+            step,
+            allowed_drift,
+            _hash: std::marker::PhantomData,
+        }
+    }
+
+    /// Returns the unix timestamp at which passwords of the old secret stop being accepted.
+    pub fn grace_until(&self) -> u64 {
+        self.grace_until
+    }
+
+    /// Verifies the given password against the new secret and, if the timestamp is before the end of
+    /// the grace window, the old one. Returns whether it matched along with the discrepancy.
+    #[must_use = "the verification result must be checked"]
+    pub fn verify(&self, password: &str, timestamp: u64) -> Result<(bool, i16), ThotpError> {
+        let timestamp = resolve_timestamp(timestamp)?;
+
+        let secrets: &[&[u8]] = if timestamp < self.grace_until {
+            &[&self.new_secret, &self.old_secret]
+        } else {
+            &[&self.new_secret]
+        };
+
+        let matched = verify_totp_multi_secret::<H>(
+            password,
+            secrets,
+            timestamp,
+            self.digits,
+            self.step,
+            self.allowed_drift,
+        )?;
+
+        Ok(matched.map_or((false, 0), |(_, discrepancy)| (true, discrepancy)))
+    }
+}
+
+impl<H> std::fmt::Debug for GraceVerifier<H> {
+    // The secrets are omitted on purpose
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GraceVerifier")
+            .field("grace_until", &self.grace_until)
+            .field("digits", &self.digits)
+            .field("step", &self.step)
+            .field("allowed_drift", &self.allowed_drift)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Confirms a new TOTP enrollment by verifying the first password the user entered after adding the
+/// secret to their authenticator, in the range of `[-allowed_drift, allowed_drift]` time slices.
+/// Only once this succeeds is it certain the user has the secret, so it should be stored afterwards.
+///
+/// The usual enrollment flow is generating a secret with [generate_secret],
+/// showing it to the user as a QR code (see the `qr` module), confirming the code they enter with
+/// this function and finally persisting the secret.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{confirm_enrollment, otp_custom, Sha1};
+/// use thotp::generate_secret;
+///
+/// let secret = generate_secret(20);
+///
+/// // The code the user's authenticator shows after scanning the QR code
+/// let user_code = otp_custom::<Sha1>(&secret, 1_000_000_000 / 30, 6).unwrap();
+///
+/// assert!(confirm_enrollment::<Sha1>(&secret, &user_code, 1_000_000_000, 6, 30, 1).unwrap());
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn confirm_enrollment<H>(
+    secret: &[u8],
+    user_code: &str,
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<bool, ThotpError>
+where
+    H: OtpHash,
+{
+    let (valid, _) = verify_totp_period::<H>(
+        user_code,
+        secret,
+        timestamp,
+        digits,
+        step as u32,
+        allowed_drift,
+    )?;
+    Ok(valid)
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, except the secret is provided
+/// Base32 encoded, as it is usually stored. The secret is decoded with
+/// [decode_base32](super::encoding::decode_base32) and an invalid encoding results in a
+/// [ThotpError::Encoding].
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_b32, Sha1};
+///
+/// let password = otp_custom::<Sha1>(b"12345678901234567890", 59 / 30, 8).unwrap();
+///
+/// let (result, _) =
+///     verify_totp_b32::<Sha1>(&password, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", 59, 8, 30, 1).unwrap();
+///
+/// assert!(result);
+/// ```
+#[cfg(feature = "encoding")]
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_b32<H>(
+    password: &str,
+    secret: &str,
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    let secret = super::encoding::decode_base32(secret)?;
+    verify_totp_custom::<H>(password, &secret, timestamp, digits, step, allowed_drift)
+}
+
+/// The result of a TOTP verification. Ignoring it results in a warning, as do the results of all
+/// the verify functions.
+///
+/// ```compile_fail
+/// #![deny(unused_must_use)]
+/// use thotp::totp::Totp;
+///
+/// let totp = Totp::new(b"super secret".to_vec());
+///
+/// // The password is never checked
+/// totp.verify("123456", 0).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[must_use = "the verification result must be checked"]
+pub struct TotpResult {
+    /// Whether the password matched any of the time slices in the allowed drift
+    pub valid: bool,
+
+    /// The number of time slices the matched password deviates from the current one, positive if it
+    /// is from a later slice, 0 if it did not match. See [Discrepancy] for the sign convention
+    pub discrepancy: i16,
+
+    /// Whether the same password was already accepted recently, only ever set when the verification
+    /// is performed with a [RecentCodeCache](super::server::RecentCodeCache)
+    pub suspected_replay: bool,
+}
+
+impl TotpResult {
+    /// Returns whether the password matched. Since a suspected replay is only advisory, it does not
+    /// affect the result and has to be checked separately if desired.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::custom::TotpResult;
+    ///
+    /// let result = TotpResult { valid: true, ..Default::default() };
+    ///
+    /// assert!(result.is_valid());
+    /// assert!(bool::from(result));
+    /// assert!(!!result);
+    /// ```
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+}
+
+impl From<TotpResult> for bool {
+    fn from(result: TotpResult) -> Self {
+        result.valid
+    }
+}
+
+impl std::ops::Not for TotpResult {
+    type Output = bool;
+
+    fn not(self) -> Self::Output {
+        !self.valid
+    }
+}
+
+/// The number of time slices a matched password deviates from the current one, as returned by the
+/// verify functions.
+///
+/// The discrepancy is the matched slice minus the current slice. A positive discrepancy means the
+/// password is from a later slice, i.e. the client's clock is ahead of the server's, and a negative
+/// one means it is from an earlier slice, i.e. the client's clock is behind.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_custom, Discrepancy, Sha1};
+///
+/// let secret = b"super secret";
+///
+/// // The password of the next time slice, the client's clock is ahead
+/// let password = otp_custom::<Sha1>(secret, 1_000_000_000 / 30 + 1, 6).unwrap();
+/// let (_, discrepancy) = verify_totp_custom::<Sha1>(&password, secret, 1_000_000_000, 6, 30, 1).unwrap();
+/// let discrepancy = Discrepancy::from(discrepancy);
+///
+/// assert!(discrepancy.is_ahead());
+/// assert_eq!(discrepancy.seconds(30), 30);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Discrepancy(pub i16);
+
+impl Discrepancy {
+    /// Returns `true` if the password is from a later time slice, i.e. the client's clock is ahead.
+    pub fn is_ahead(&self) -> bool {
+        self.0 > 0
+    }
+
+    /// Returns `true` if the password is from an earlier time slice, i.e. the client's clock is behind.
+    pub fn is_behind(&self) -> bool {
+        self.0 < 0
+    }
+
+    /// Returns the approximate offset of the client's clock in seconds for the given time step,
+    /// positive if it is ahead and negative if it is behind.
+    pub fn seconds(&self, step: u8) -> i64 {
+        self.0 as i64 * step as i64
+    }
+}
+
+impl From<i16> for Discrepancy {
+    fn from(discrepancy: i16) -> Self {
+        Self(discrepancy)
+    }
+}
+
+impl From<Discrepancy> for i16 {
+    fn from(discrepancy: Discrepancy) -> Self {
+        discrepancy.0
+    }
+}
+
+/// The risk of a verified password in the range of `0.0..=1.0`, as returned by [verify_totp_risk],
+/// e.g. to require another factor above a certain risk. Passwords of the current time slice are the
+/// least risky, passwords further away from it hint at a skewed or manipulated clock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct RiskScore(f64);
+
+impl RiskScore {
+    /// Creates a risk score, clamping it to the range of `0.0..=1.0`. `NaN` is the highest risk.
+    pub fn new(score: f64) -> Self {
+        match score.is_nan() {
+            true => Self(1.0),
+            false => Self(score.clamp(0.0, 1.0)),
+        }
+    }
+
+    /// Returns the score in the range of `0.0..=1.0`.
+    pub fn value(&self) -> f64 {
+        self.0
+    }
+
+    /// The linear weighting for [verify_totp_risk], scoring a password of the current slice as `0.0`
+    /// and one at the edge of the allowed drift as `1.0`, with the slices in between spread evenly.
+    pub fn linear(discrepancy: u16, allowed_drift: u8) -> f64 {
+        match allowed_drift {
+            0 => 0.0,
+            drift => discrepancy as f64 / drift as f64,
+        }
+    }
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does and scores the risk of a match,
+/// returning `None` if the password does not match. The score is the `weight` of the absolute
+/// discrepancy of the match and the allowed drift, clamped to the range of `0.0..=1.0`. Use
+/// [RiskScore::linear] for a score growing evenly towards the edges of the window.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_risk, RiskScore, Sha1};
+///
+/// let secret = b"super secret";
+/// let current = otp_custom::<Sha1>(secret, 1_000_000_000 / 30, 6).unwrap();
+/// let edge = otp_custom::<Sha1>(secret, 1_000_000_000 / 30 - 2, 6).unwrap();
+///
+/// let verify = |password: &str| {
+///     verify_totp_risk::<Sha1>(password, secret, 1_000_000_000, 6, 30, 2, RiskScore::linear).unwrap()
+/// };
+///
+/// assert_eq!(verify(&current), Some(RiskScore::new(0.0)));
+/// assert_eq!(verify(&edge), Some(RiskScore::new(1.0)));
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_risk<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    weight: impl Fn(u16, u8) -> f64,
+) -> Result<Option<RiskScore>, ThotpError>
+where
+    H: OtpHash,
+{
+    let (valid, discrepancy) =
+        verify_totp_custom::<H>(password, secret, timestamp, digits, step, allowed_drift)?;
+
+    Ok(valid.then(|| RiskScore::new(weight(discrepancy.unsigned_abs(), allowed_drift))))
+}
+
+/// Verifies two passwords from consecutive time slices, e.g. when a client submits both the previous
+/// and the current password around a slice transition. This proves possession of the secret and a
+/// correct clock more strongly than a single password.
+///
+/// The `earlier` password must belong to the slice directly before the one of the `later` password,
+/// and one of the two slices must be the current one. In other words the accepted pairs are the
+/// passwords of the slices `(current - 1, current)` and `(current, current + 1)`.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_pair, Sha1};
+///
+/// let secret = b"super secret";
+/// let previous = otp_custom::<Sha1>(secret, 1, 6).unwrap();
+/// let current = otp_custom::<Sha1>(secret, 2, 6).unwrap();
+///
+/// assert!(verify_totp_pair::<Sha1>(&previous, &current, secret, 60, 6, 30).unwrap());
+/// assert!(!verify_totp_pair::<Sha1>(&current, &previous, secret, 60, 6, 30).unwrap());
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_pair<H>(
+    earlier: &str,
+    later: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+) -> Result<bool, ThotpError>
+where
+    H: OtpHash,
+{
+    if earlier.len() != digits as usize || later.len() != digits as usize {
+        return Ok(false);
+    }
+
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    let key = HmacKey::<H>::new(secret)?;
+
+    let current = key.code(nonce, digits)?;
+    let matches = |slice: Option<u64>, password: &str| -> Result<bool, ThotpError> {
+        match slice {
+            Some(slice) => Ok(ct_eq(
+                key.code(slice, digits)?.as_bytes(),
+                password.as_bytes(),
+            )),
+            None => Ok(false),
+        }
+    };
+
+    // Both pairs are always checked so the result does not depend on which one matched
+    let previous_pair =
+        matches(nonce.checked_sub(1), earlier)? & ct_eq(current.as_bytes(), later.as_bytes());
+    let next_pair =
+        ct_eq(current.as_bytes(), earlier.as_bytes()) & matches(nonce.checked_add(1), later)?;
+
+    Ok(previous_pair | next_pair)
+}
+
+/// The reason a TOTP verification performed by [verify_totp_diagnostic] succeeded or failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use = "the verification result must be checked"]
+pub enum VerifyDiagnostic {
+    /// The password matched a time slice in the allowed drift, deviating `drift` slices from the
+    /// current one.
+    Accepted { drift: i16 },
+
+    /// The password does not have the expected number of digits.
+    WrongLength,
+
+    /// The password contains something other than ASCII digits.
+    NonDigit,
+
+    /// The password is not valid, but matches a time slice `drift` slices from the current one,
+    /// outside of the allowed drift but inside of the diagnostic window. Usually caused by a clock
+    /// that is off.
+    OutsideWindow { drift: i16 },
+
+    /// The password did not match any time slice in the diagnostic window.
+    Incorrect,
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, but reports why it failed, e.g.
+/// for support staff helping users who can not log in. Only [VerifyDiagnostic::Accepted] means the
+/// password is valid.
+///
+/// If the password does not match, the time slices in the range of
+/// `[-diagnostic_drift, diagnostic_drift]` are scanned to tell a password generated with a clock that
+/// is off apart from a wrong one. This scan is opt-in and only meant for diagnostics, a
+/// `diagnostic_drift` no greater than `allowed_drift` skips it and wrong passwords are always reported
+/// as [VerifyDiagnostic::Incorrect]. Since the scan generates `2 * diagnostic_drift + 1` passwords it
+/// should not be exposed to users without rate limiting.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_diagnostic, Sha1, VerifyDiagnostic};
+///
+/// let secret = b"super secret";
+///
+/// // The password of 5 minutes ago
+/// let password = otp_custom::<Sha1>(secret, 1_000_000_000 / 30 - 10, 6).unwrap();
+///
+/// let diagnostic = verify_totp_diagnostic::<Sha1>(&password, secret, 1_000_000_000, 6, 30, 1, 20).unwrap();
+/// assert_eq!(diagnostic, VerifyDiagnostic::OutsideWindow { drift: -10 });
+/// ```
+pub fn verify_totp_diagnostic<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    diagnostic_drift: u8,
+) -> Result<VerifyDiagnostic, ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok(VerifyDiagnostic::WrongLength);
+    }
+
+    if !password.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Ok(VerifyDiagnostic::NonDigit);
+    }
+
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    if let (true, drift) = verify_slices::<H>(password, secret, nonce, digits, allowed_drift)? {
+        return Ok(VerifyDiagnostic::Accepted { drift });
+    }
+
+    if diagnostic_drift > allowed_drift {
+        if let (true, drift) =
+            verify_slices::<H>(password, secret, nonce, digits, diagnostic_drift)?
+        {
+            return Ok(VerifyDiagnostic::OutsideWindow { drift });
+        }
+    }
+
+    Ok(VerifyDiagnostic::Incorrect)
+}
+
+/// A record of a single TOTP verification attempt, containing the data an audit log usually needs.
+///
+/// The record never contains the secret or the password, so it is safe to log as is. The only
+/// exception is the `context`, which is set by the caller and copied verbatim.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationRecord {
+    /// The name of the hashing algorithm, e.g. `SHA1`
+    pub algorithm: String,
+
+    /// The digit length of the password
+    pub digits: u8,
+
+    /// The time step used to calculate the time slice
+    pub step: u8,
+
+    /// Whether the password matched any of the time slices in the allowed drift
+    pub matched: bool,
+
+    /// The number of time slices the matched password deviates from the current one, 0 if it
+    /// did not match
+    pub drift: i16,
+
+    /// The unix timestamp the verification was performed for
+    pub timestamp: u64,
+
+    /// Opaque data of the caller never interpreted by this crate, e.g. a request identifier to
+    /// correlate the record with other logs. `None` unless set with [VerificationRecord::with_context]
+    pub context: Option<String>,
+}
+
+impl VerificationRecord {
+    /// Sets the context of the record, which is copied into it verbatim.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::custom::{verify_totp_record, Sha1};
+    ///
+    /// let record = verify_totp_record::<Sha1>("123456", b"super secret", 59, 6, 30, 1)
+    ///     .unwrap()
+    ///     .with_context("request-42");
+    ///
+    /// assert_eq!(record.context.as_deref(), Some("request-42"));
+    /// ```
+    pub fn with_context(mut self, context: impl Into<String>) -> Self {
+        self.context = Some(context.into());
+        self
+    }
+}
+
+/// Verifies the password the same way [verify_totp_custom] does and returns a [VerificationRecord]
+/// describing the attempt.
+///
+/// If a `timestamp` of 0 is provided, the current system time will be used for the calculation and
+/// recorded in the returned record.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_record, Sha1};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 59 / 30, 6).unwrap();
+///
+/// let record = verify_totp_record::<Sha1>(&password, secret, 59, 6, 30, 1).unwrap();
+///
+/// assert!(record.matched);
+/// assert_eq!(record.algorithm, "SHA1");
+/// assert_eq!(record.timestamp, 59);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_record<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<VerificationRecord, ThotpError>
+where
+    H: OtpHash + CoreProxy,
+    H::Core: AlgorithmName,
+{
+    let timestamp = resolve_timestamp(timestamp)?;
+
+    let (matched, drift) =
+        verify_totp_custom::<H>(password, secret, timestamp, digits, step, allowed_drift)?;
+
+    Ok(VerificationRecord {
+        algorithm: algorithm_name::<H>(),
+        digits,
+        step,
+        matched,
+        drift,
+        timestamp,
+        context: None,
+    })
+}
+
+/// Calculates the TOTP counter, i.e. the time slice, of the given unix timestamp.
+///
+/// Returns a [ThotpError::InvalidStep] if the `step` is 0.
+pub fn totp_counter(timestamp: u64, step: u8) -> Result<u64, ThotpError> {
+    checked_time_slice(timestamp, step)
+}
+
+/// Calculates the unix timestamp at which the time slice of the given timestamp ends, i.e. the
+/// start of the next one.
+///
+/// Returns a [ThotpError::Conversion] if the boundary does not fit in a `u64`, which can only happen
+/// for timestamps in the last time slice before `u64::MAX`, and a [ThotpError::InvalidStep] if the
+/// `step` is 0.
+pub fn next_boundary_at(timestamp: u64, step: u8) -> Result<u64, ThotpError> {
+    checked_time_slice(timestamp, step)?
+        .checked_add(1)
+        .and_then(|next| next.checked_mul(step as u64))
+        .ok_or(ThotpError::Conversion)
+}
+
+/// Formats the given unix timestamp as an RFC 3339 timestamp in UTC, e.g. `2001-09-09T01:46:40Z`, for
+/// logs read by operators.
+///
+/// Returns a [ThotpError::Conversion] for timestamps after the year 9999, which RFC 3339 cannot
+/// represent.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::to_rfc3339;
+///
+/// assert_eq!(to_rfc3339(0).unwrap(), "1970-01-01T00:00:00Z");
+/// assert_eq!(to_rfc3339(1_000_000_000).unwrap(), "2001-09-09T01:46:40Z");
+/// ```
+#[cfg(feature = "rfc3339")]
+pub fn to_rfc3339(timestamp: u64) -> Result<String, ThotpError> {
+    // 9999-12-31T23:59:59Z
+    if timestamp > 253_402_300_799 {
+        return Err(ThotpError::Conversion);
+    }
+
+    let days = timestamp / 86_400;
+    let secs = timestamp % 86_400;
+
+    // The civil date of the day count, with years starting in March so leap days come last
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    Ok(format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    ))
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, and on success returns the
+/// validity window of the matched time slice as `(valid_from, valid_until)` RFC 3339 timestamps, where
+/// `valid_until` is the start of the next slice. Returns `None` if the password does not match.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_rfc3339, Sha1};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 1_000_000_000 / 30, 6).unwrap();
+///
+/// let (valid_from, valid_until) =
+///     verify_totp_rfc3339::<Sha1>(&password, secret, 1_000_000_000, 6, 30, 1)
+///         .unwrap()
+///         .unwrap();
+///
+/// assert_eq!(valid_from, "2001-09-09T01:46:30Z");
+/// assert_eq!(valid_until, "2001-09-09T01:47:00Z");
+/// ```
+#[cfg(feature = "rfc3339")]
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_rfc3339<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<Option<(String, String)>, ThotpError>
+where
+    H: OtpHash,
+{
+    let timestamp = resolve_timestamp(timestamp)?;
+
+    let (matched, discrepancy) =
+        verify_totp_custom::<H>(password, secret, timestamp, digits, step, allowed_drift)?;
+
+    if !matched {
+        return Ok(None);
+    }
+
+    // The matched slice is within the window, which was clamped to the u64 range
+    let slice = (totp_counter(timestamp, step)? as i128 + discrepancy as i128) as u64;
+    let valid_from = slice
+        .checked_mul(step as u64)
+        .ok_or(ThotpError::Conversion)?;
+    let valid_until = valid_from
+        .checked_add(step as u64)
+        .ok_or(ThotpError::Conversion)?;
+
+    Ok(Some((to_rfc3339(valid_from)?, to_rfc3339(valid_until)?)))
+}
+
+/// A TOTP along with the time slice it was generated for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TotpToken {
+    /// The password
+    pub code: String,
+
+    /// The unix timestamp at which the password becomes valid
+    pub valid_from: u64,
+
+    /// The unix timestamp at which the password stops being valid, i.e. the start of the next slice
+    pub valid_until: u64,
+
+    /// The time slice the password was generated for
+    pub counter: u64,
+}
+
+/// Generates the TOTP for the given timestamp along with its validity window, e.g. to show how long
+/// the password is valid for in a UI.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{current_totp, otp_custom, Sha1};
+///
+/// let secret = b"super secret";
+/// let token = current_totp::<Sha1>(secret, 59, 30, 6).unwrap();
+///
+/// assert_eq!(token.valid_from, 30);
+/// assert_eq!(token.valid_until, 60);
+/// assert_eq!(token.code, otp_custom::<Sha1>(secret, 1, 6).unwrap());
+/// ```
+pub fn current_totp<H>(
+    secret: &[u8],
+    timestamp: u64,
+    step: u8,
+    digits: u8,
+) -> Result<TotpToken, ThotpError>
+where
+    H: OtpHash,
+{
+    let timestamp = resolve_timestamp(timestamp)?;
+
+    let counter = checked_time_slice(timestamp, step)?;
+
+    Ok(TotpToken {
+        code: otp_custom::<H>(secret, counter, digits)?,
+        valid_from: counter * step as u64,
+        valid_until: next_boundary_at(timestamp, step)?,
+        counter,
+    })
+}
+
+/// Generates the TOTP for the given timestamp just like [current_totp], except the returned validity
+/// window extends over the `validity_slices` time slices after the one it was generated for, e.g. for
+/// a password delivered by SMS or email which may take a while to arrive. Such passwords are verified
+/// with [verify_extended].
+///
+/// The `valid_until` of the token is the end of the last slice the password is accepted in, and is
+/// capped at `u64::MAX`.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{generate_extended, verify_extended, Sha1};
+///
+/// let secret = b"super secret";
+///
+/// // Valid for the current slice and the 9 after it, around 5 minutes with a step of 30
+/// let token = generate_extended::<Sha1>(secret, 1_000_000_000, 30, 6, 9).unwrap();
+/// assert_eq!(token.valid_until, (1_000_000_000 / 30 + 10) * 30);
+///
+/// let (valid, _) = verify_extended::<Sha1>(&token.code, secret, 1_000_000_240, 6, 30, 9).unwrap();
+/// assert!(valid);
+/// ```
+pub fn generate_extended<H>(
+    secret: &[u8],
+    timestamp: u64,
+    step: u8,
+    digits: u8,
+    validity_slices: u8,
+) -> Result<TotpToken, ThotpError>
+where
+    H: OtpHash,
+{
+    let mut token = current_totp::<H>(secret, timestamp, step, digits)?;
+
+    token.valid_until = token
+        .valid_until
+        .saturating_add((validity_slices as u64).saturating_mul(step as u64));
+
+    Ok(token)
+}
+
+/// Verifies a password generated with [generate_extended]. The password is accepted if it was
+/// generated for the current time slice or any of the `validity_slices` slices before it, and never
+/// for a future slice. Unlike the allowed drift of [verify_totp_custom], which tolerates clocks that
+/// are off, the window only covers the time it takes to deliver the password.
+///
+/// The returned discrepancy is the number of slices since the one the password was generated for, as
+/// a negative number. If the password matches more than one slice, the most recent one is returned.
+#[must_use = "the verification result must be checked"]
+pub fn verify_extended<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    validity_slices: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok((false, 0));
+    }
+
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    let key = HmacKey::<H>::new(secret)?;
+    let mut matched: Option<i16> = None;
+
+    for age in 0..=validity_slices as u64 {
+        let n = match nonce.checked_sub(age) {
+            Some(n) => n,
+            None => break,
+        };
+
+        if ct_eq(key.code(n, digits)?.as_bytes(), password.as_bytes()) {
+            matched = Some(closest_match(matched, -(age as i16)));
+        }
+    }
+
+    Ok(matched.map_or((false, 0), |discrepancy| (true, discrepancy)))
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, except only the current time
+/// slice and the `back_drift` slices before it are checked, so passwords of future slices are never
+/// accepted. This still tolerates clients with slow clocks while rejecting passwords from clients with
+/// fast clocks or ones precomputed ahead of time, for strict deployments.
+///
+/// The returned discrepancy is 0 or negative. The window is the same one [verify_extended] checks.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_totp_no_future, Sha1};
+///
+/// let secret = b"super secret";
+/// let previous = otp_custom::<Sha1>(secret, 1_000_000_000 / 30 - 1, 6).unwrap();
+/// let next = otp_custom::<Sha1>(secret, 1_000_000_000 / 30 + 1, 6).unwrap();
+///
+/// assert_eq!(
+///     verify_totp_no_future::<Sha1>(&previous, secret, 1_000_000_000, 6, 30, 1).unwrap(),
+///     (true, -1)
+/// );
+/// assert_eq!(
+///     verify_totp_no_future::<Sha1>(&next, secret, 1_000_000_000, 6, 30, 1).unwrap(),
+///     (false, 0)
+/// );
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_no_future<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    back_drift: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    verify_extended::<H>(password, secret, timestamp, digits, step, back_drift)
+}
+
+/// Finds the validity windows of the given password in the range of `[search_from, search_to]`, e.g.
+/// to find out when a password from an incident report was generated.
+///
+/// Every time slice overlapping the range is checked and the `(valid_from, valid_until)` unix timestamps
+/// of each one generating the password are returned, where `valid_until` is the start of the next slice.
+/// Since passwords are short, a long range can contain multiple windows. A range where `search_from` is
+/// larger than `search_to` contains no windows.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{code_time_range, otp_custom, Sha1};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 100, 6).unwrap();
+///
+/// let windows = code_time_range::<Sha1>(&password, secret, 2900, 3100, 30, 6).unwrap();
+///
+/// assert_eq!(windows, [(3000, 3030)]);
+/// ```
+pub fn code_time_range<H>(
+    password: &str,
+    secret: &[u8],
+    search_from: u64,
+    search_to: u64,
+    step: u8,
+    digits: u8,
+) -> Result<Vec<(u64, u64)>, ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok(Vec::new());
+    }
+
+    if search_from > search_to {
+        return Ok(Vec::new());
+    }
+
+    let key = HmacKey::<H>::new(secret)?;
+    let mut windows = Vec::new();
+
+    for n in checked_time_slice(search_from, step)?..=checked_time_slice(search_to, step)? {
+        if ct_eq(key.code(n, digits)?.as_bytes(), password.as_bytes()) {
+            let valid_from = n * step as u64;
+            windows.push((valid_from, valid_from.saturating_add(step as u64)));
+        }
+    }
+
+    Ok(windows)
+}
+
+/// Generates the passwords of every time slice overlapping the range of `[from, to]`, keyed by the unix
+/// timestamp each slice starts at, e.g. for snapshot tests of client implementations. A range where
+/// `from` is larger than `to` results in an empty table.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, totp_table, Sha1};
+///
+/// let secret = b"super secret";
+/// let table = totp_table::<Sha1>(secret, 3010, 3070, 30, 6).unwrap();
+///
+/// assert_eq!(table.keys().copied().collect::<Vec<_>>(), [3000, 3030, 3060]);
+/// assert_eq!(table[&3030], otp_custom::<Sha1>(secret, 101, 6).unwrap());
+/// ```
+pub fn totp_table<H>(
+    secret: &[u8],
+    from: u64,
+    to: u64,
+    step: u8,
+    digits: u8,
+) -> Result<std::collections::BTreeMap<u64, String>, ThotpError>
+where
+    H: OtpHash,
 {
-    // Transform to bytes
-    let nonce = &nonce.to_be_bytes();
-
-    // Create an HMAC digest with the given key, nonce and algorithm
-    let mut hmac = hmac_digest::<H>(secret, nonce)?;
+    let mut table = std::collections::BTreeMap::new();
 
-    // Truncate to 4 bytes
-    let trunc = dynamic_trunc(&mut hmac);
+    if from > to {
+        return Ok(table);
+    }
 
-    // Mod it with the number of digits for the password
-    let mut result = (trunc % 10_u32.pow(digits as u32)).to_string();
+    let key = HmacKey::<H>::new(secret)?;
 
-    // Pad with 0s if the number is shorter than the necessary digits
-    for i in 0..(digits as usize - result.len()) {
-        result.insert(i, '0');
+    for n in checked_time_slice(from, step)?..=checked_time_slice(to, step)? {
+        table.insert(n * step as u64, key.code(n, digits)?);
     }
 
-    Ok(result)
+    Ok(table)
 }
 
-/// Verifies the given password for the given timestamp and secret.
+/// Determines which time slice a timestamp on or near a slice boundary is assigned to.
 ///
-/// Uses the provided algorithm, digit length and time step
-/// to generate a password to compare with the given one in the range of
+/// A timestamp exactly on a boundary, i.e. `n * step`, is the start of slice `n` with either policy.
+/// The policies only differ for the second half of a slice, where `Nearest` assigns the timestamp to
+/// the following slice since it is closer to its start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BoundaryRounding {
+    /// The RFC behaviour, the timestamp is divided by the step and rounded down
+    #[default]
+    Floor,
+
+    /// The timestamp is divided by the step and rounded to the nearest slice, with halves rounded up
+    Nearest,
+}
+
+/// Calculates the time slice of the given unix timestamp using the given rounding policy.
 ///
-/// `[-allowed_drift, allowed_drift]`
+/// Returns a [ThotpError::InvalidStep] if the `step` is 0, with either policy.
 ///
-/// time slices. If a `timestamp` of 0
-/// is provided, the current system time will be used for the calculation.
+/// ## Example
+/// ```
+/// use thotp::custom::{time_slice, BoundaryRounding};
 ///
-/// The function returns a tuple whose first element is a boolean indicating whether any
-/// of the passwords in the allowed drift match and the second element is a number
-/// indicating the number of time slices the valid password deviates from the current
-/// time slice.
+/// assert_eq!(time_slice(60, 30, BoundaryRounding::Floor).unwrap(), 2);
+/// assert_eq!(time_slice(60, 30, BoundaryRounding::Nearest).unwrap(), 2);
+///
+/// assert_eq!(time_slice(89, 30, BoundaryRounding::Floor).unwrap(), 2);
+/// assert_eq!(time_slice(89, 30, BoundaryRounding::Nearest).unwrap(), 3);
+///
+/// assert!(time_slice(89, 0, BoundaryRounding::Nearest).is_err());
+/// ```
+pub fn time_slice(timestamp: u64, step: u8, rounding: BoundaryRounding) -> Result<u64, ThotpError> {
+    match rounding {
+        BoundaryRounding::Floor => checked_time_slice(timestamp, step),
+        BoundaryRounding::Nearest => {
+            checked_time_slice(timestamp.saturating_add(step as u64 / 2), step)
+        }
+    }
+}
+
+/// Verifies the given password the same way [verify_totp_custom] does, except the current time slice
+/// is calculated with the given [BoundaryRounding] policy.
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_rounded<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    rounding: BoundaryRounding,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    let timestamp = resolve_timestamp(timestamp)?;
+
+    // Shift the timestamp to the start of the slice it is assigned to
+    let timestamp = time_slice(timestamp, step, rounding)? * step as u64;
+
+    verify_totp_custom::<H>(password, secret, timestamp, digits, step, allowed_drift)
+}
+
+/// Verifies the given password in the range of `[-allowed_drift, allowed_drift]` time slices around
+/// every slice the timestamp is within `boundary_tolerance_secs` seconds of.
+///
+/// A client computing its password a second or two before a boundary and a server verifying it right
+/// after, or the other way around, disagree on the current slice. With a drift of 0, a small tolerance
+/// accepts the neighbouring slice only for timestamps close to the boundary instead of for the whole
+/// slice, as a drift of 1 would. The discrepancy is relative to the slice of the timestamp itself,
+/// and the closest match is returned if the password matches several slices.
 ///
 /// ## Example
 /// ```
-/// // An example from RFC 6238 with SHA1
-/// use thotp::custom::{otp_custom, verify_totp_custom};
-/// use thotp::custom::Sha1;
+/// use thotp::custom::{otp_custom, verify_totp_tolerance, Sha1};
 ///
-/// // The internal constants used by the default otp functions
-/// const TIME_STEP: u8 = 30;
-/// const ALLOWED_DRIFT: u8 = 1;
+/// let secret = b"super secret";
+/// let next = otp_custom::<Sha1>(secret, 101, 6).unwrap();
 ///
-/// let secret = b"12345678901234567890";
+/// // One second before the boundary of slices 100 and 101
+/// let (valid, discrepancy) = verify_totp_tolerance::<Sha1>(&next, secret, 3029, 6, 30, 0, 2).unwrap();
+/// assert!(valid);
+/// assert_eq!(discrepancy, 1);
 ///
-/// let pairs = vec![
-///     ("94287082", 59),
-///     ("07081804", 1111111109),
-///     ("14050471", 1111111111),
-///     ("89005924", 1234567890),
-///     ("69279037", 2000000000),
-///     ("65353130", 20000000000),
-/// ];
+/// // The middle of slice 100 is too far from the boundary
+/// let (valid, _) = verify_totp_tolerance::<Sha1>(&next, secret, 3015, 6, 30, 0, 2).unwrap();
+/// assert!(!valid);
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_tolerance<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    boundary_tolerance_secs: u8,
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok((false, 0));
+    }
+
+    let timestamp = resolve_timestamp(timestamp)?;
+
+    let nonce = checked_time_slice(timestamp, step)?;
+    let tolerance = boundary_tolerance_secs as u64;
+
+    let start =
+        (timestamp.saturating_sub(tolerance) / step as u64).saturating_sub(allowed_drift as u64);
+    let end =
+        (timestamp.saturating_add(tolerance) / step as u64).saturating_add(allowed_drift as u64);
+
+    // Both ends are at most the u8 tolerance plus the u8 drift away from the current slice, so the
+    // window always fits
+    let window = (start as i128 - nonce as i128) as i16..=(end as i128 - nonce as i128) as i16;
+
+    let key = HmacKey::<H>::new(secret)?;
+
+    verify_window(password, nonce, window, |n| key.code(n, digits))
+}
+
+/// Generates a time based password bound to a challenge issued by the server, e.g. a hash of the
+/// transaction amount and recipient, so the password is only valid for that transaction.
 ///
-/// pairs.into_iter().for_each(|(expected, timestamp)| {
-///     // When generating a totp the provided unix time is divided by the time step
-///     assert_eq!(
-///         expected,
-///         otp_custom::<Sha1>(secret, timestamp / TIME_STEP as u64, 8).unwrap()
-///     );
+/// The HMAC is computed over the 8 byte big endian time slice followed by the challenge as is. An empty
+/// challenge therefore results in the regular TOTP.
 ///
-///     // The verify function does this internally
-///     let (result, discrepancy) = verify_totp_custom::<Sha1>(
-///         expected, secret, timestamp, 8, TIME_STEP, ALLOWED_DRIFT
-///     )
-///     .unwrap();
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_challenge, verify_totp_challenge, Sha256};
 ///
-///     assert_eq!((true, 0), (result, discrepancy));
-/// });
+/// let secret = b"super secret";
+/// let challenge = b"transfer 100 to alice";
+///
+/// let password = otp_challenge::<Sha256>(secret, 1_000_000_000, challenge, 30, 6).unwrap();
+///
+/// let (valid, _) =
+///     verify_totp_challenge::<Sha256>(&password, secret, 1_000_000_000, challenge, 30, 6, 1).unwrap();
+/// assert!(valid);
+///
+/// let (valid, _) =
+///     verify_totp_challenge::<Sha256>(&password, secret, 1_000_000_000, b"transfer 100 to eve", 30, 6, 1)
+///         .unwrap();
+/// assert!(!valid);
 /// ```
-pub fn verify_totp_custom<H>(
-    password: &str,
+pub fn otp_challenge<H>(
     secret: &[u8],
     timestamp: u64,
+    challenge: &[u8],
+    step: u8,
     digits: u8,
+) -> Result<String, ThotpError>
+where
+    H: OtpHash,
+{
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    challenge_password::<H>(secret, nonce, challenge, digits)
+}
+
+/// Verifies a password generated with [otp_challenge] in the range of `[-allowed_drift, allowed_drift]`
+/// time slices. The password only matches if it was generated for the same challenge. The closest
+/// match is returned if the password matches several slices.
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_challenge<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    challenge: &[u8],
     step: u8,
+    digits: u8,
     allowed_drift: u8,
 ) -> Result<(bool, i16), ThotpError>
 where
-    H: Update + FixedOutput + CoreProxy,
-    H::Core: HashMarker
-        + UpdateCore
-        + FixedOutputCore
-        + BufferKindUser<BufferKind = Eager>
-        + Default
-        + Clone,
-    <H::Core as BlockSizeUser>::BlockSize: IsLess<U256>,
-    Le<<H::Core as BlockSizeUser>::BlockSize, U256>: NonZero,
+    H: OtpHash,
 {
-    let nonce = if timestamp == 0 {
-        SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() / step as u64
-    } else {
-        timestamp / step as u64
-    };
+    if password.len() != digits as usize {
+        return Ok((false, 0));
+    }
 
-    let start = nonce.saturating_sub(allowed_drift as u64);
-    let end = nonce.saturating_add(allowed_drift as u64);
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
 
-    // Keeps track of how large the deicrepancy is
-    let mut i = -(ALLOWED_DRIFT as i16);
+    let drift = allowed_drift as i16;
 
-    for n in start..=end {
-        let pass = otp_custom::<H>(secret, n, digits)?;
-        if pass.eq(password) {
-            return Ok((true, i));
-        }
-        i += 1;
+    verify_window(password, nonce, -drift..=drift, |n| {
+        challenge_password::<H>(secret, n, challenge, digits)
+    })
+}
+
+/// Generates the password of the given time slice bound to the challenge.
+fn challenge_password<H>(
+    secret: &[u8],
+    nonce: u64,
+    challenge: &[u8],
+    digits: u8,
+) -> Result<String, ThotpError>
+where
+    H: OtpHash,
+{
+    let mut message = nonce.to_be_bytes().to_vec();
+    message.extend_from_slice(challenge);
+
+    let hmac = hmac_digest::<H>(secret, &message)?;
+
+    Ok(to_password(dynamic_trunc(&hmac), digits))
+}
+
+/// The constant prepended to the HMAC message by [otp_domain], so domain separated passwords never
+/// collide with regular or challenge bound ones.
+pub const DOMAIN_TAG: &[u8] = b"thotp-domain";
+
+/// Generates a one time password bound to an application specific domain, so the passwords of a
+/// secret shared between multiple applications, e.g. through a common secret store, are not valid in
+/// any of the others.
+///
+/// The HMAC is computed over [DOMAIN_TAG], the 8 byte big endian length of the domain, the domain and
+/// finally the 8 byte big endian nonce. The length keeps domains which are prefixes of one another
+/// from colliding. Authenticator apps do not support this, so it is only useful for passwords
+/// generated and verified by applications using this crate.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_domain, Sha256};
+///
+/// let secret = b"shared secret";
+///
+/// let billing = otp_domain::<Sha256>(secret, 1, 6, b"billing").unwrap();
+/// let support = otp_domain::<Sha256>(secret, 1, 6, b"support").unwrap();
+///
+/// assert_ne!(billing, support);
+/// ```
+pub fn otp_domain<H>(
+    secret: &[u8],
+    nonce: u64,
+    digits: u8,
+    domain: &[u8],
+) -> Result<String, ThotpError>
+where
+    H: OtpHash,
+{
+    let mut message = DOMAIN_TAG.to_vec();
+    message.extend_from_slice(&(domain.len() as u64).to_be_bytes());
+    message.extend_from_slice(domain);
+    message.extend_from_slice(&nonce.to_be_bytes());
+
+    let hmac = hmac_digest::<H>(secret, &message)?;
+
+    Ok(to_password(dynamic_trunc(&hmac), digits))
+}
+
+/// Verifies a time based password generated with [otp_domain] in the range of
+/// `[-allowed_drift, allowed_drift]` time slices. The password only matches if it was generated for
+/// the same domain. The closest match is returned if the password matches several slices.
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_domain<H>(
+    password: &str,
+    secret: &[u8],
+    timestamp: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    domain: &[u8],
+) -> Result<(bool, i16), ThotpError>
+where
+    H: OtpHash,
+{
+    if password.len() != digits as usize {
+        return Ok((false, 0));
     }
 
-    Ok((false, 0))
+    let nonce = checked_time_slice(resolve_timestamp(timestamp)?, step)?;
+
+    let drift = allowed_drift as i16;
+
+    verify_window(password, nonce, -drift..=drift, |n| {
+        otp_domain::<H>(secret, n, digits, domain)
+    })
 }
 
 /// Uses the provided algorithm, digit length and lookahead to generate `lookahead + 1` passwords
@@ -147,6 +3283,9 @@ where
 ///
 /// If verification is successful the counter is incremented, otherwise it is left as is.
 ///
+/// The counter never wraps around. If the lookahead reaches past `u64::MAX` before a match is found, or the
+/// counter can not be incremented after a match at `u64::MAX`, a [ThotpError::CounterOverflow] is returned.
+///
 /// ## Example
 /// ```
 /// use thotp::custom::{otp_custom, verify_hotp_custom};
@@ -160,35 +3299,306 @@ where
 /// let (result, counter) = verify_hotp_custom::<Sha256>(&password, key, u64::MAX - 18, 20, DIGITS_DEFAULT).unwrap();
 ///
 /// assert_eq!(result, true);
-/// assert_eq!(counter, u64::MAX);
+/// assert_eq!(counter.as_u64(), u64::MAX);
 /// ```
+#[must_use = "the verification result must be checked"]
 pub fn verify_hotp_custom<H>(
     password: &str,
     secret: &[u8],
-    counter: u64,
+    counter: impl Into<Counter>,
+    lookahead: u8,
+    digits: u8,
+) -> Result<(bool, Counter), ThotpError>
+where
+    H: OtpHash,
+{
+    verify_hotp_endian::<H>(
+        password,
+        secret,
+        counter,
+        lookahead,
+        digits,
+        CounterEndian::Big,
+    )
+}
+
+/// Verifies a HOTP the same way [verify_hotp_custom] does, except the 8 byte counter is encoded in the
+/// given byte order. Use this only to interoperate with tokens that do not follow RFC 4226.
+#[must_use = "the verification result must be checked"]
+pub fn verify_hotp_endian<H>(
+    password: &str,
+    secret: &[u8],
+    counter: impl Into<Counter>,
     lookahead: u8,
     digits: u8,
-) -> Result<(bool, u64), ThotpError>
+    endian: CounterEndian,
+) -> Result<(bool, Counter), ThotpError>
 where
-    H: Update + FixedOutput + CoreProxy,
-    H::Core: HashMarker
-        + UpdateCore
-        + FixedOutputCore
-        + BufferKindUser<BufferKind = Eager>
-        + Default
-        + Clone,
-    <H::Core as BlockSizeUser>::BlockSize: IsLess<U256>,
-    Le<<H::Core as BlockSizeUser>::BlockSize, U256>: NonZero,
+    H: OtpHash,
 {
-    for current in 0..lookahead + 1 {
-        let current = (counter as u128 + current as u128) as u64;
+    let counter = counter.into();
+
+    // Passwords of the wrong length can never match, so skip generating any
+    if password.len() != digits as usize {
+        return Ok((false, counter));
+    }
+
+    for current in 0..=lookahead as u64 {
+        let current = counter
+            .as_u64()
+            .checked_add(current)
+            .ok_or(ThotpError::CounterOverflow)?;
 
-        let pass = otp_custom::<H>(secret, current, digits)?;
+        let pass = otp_custom_endian::<H>(secret, current, digits, endian)?;
 
-        if pass.eq(password) {
-            return Ok((true, (current as u128 + 1) as u64));
+        if ct_eq(pass.as_bytes(), password.as_bytes()) {
+            let next = Counter::new(current)
+                .checked_next()
+                .ok_or(ThotpError::CounterOverflow)?;
+            return Ok((true, next));
         }
     }
 
     Ok((false, counter))
 }
+
+/// Verifies a HOTP the same way [verify_hotp_custom] does, except all `lookahead + 1` passwords are
+/// always generated and compared, instead of returning as soon as one matches.
+///
+/// The time [verify_hotp_custom] takes reveals roughly how far ahead of the counter the matched
+/// password was, since it stops at the match. This function takes the same time wherever the match is,
+/// or whether there is one at all, at the cost of always generating the whole lookahead. Only a
+/// password of the wrong length is rejected right away, which reveals nothing about the counter.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, verify_hotp_ct, Sha1};
+/// use thotp::Counter;
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 12, 6).unwrap();
+///
+/// assert_eq!(verify_hotp_ct::<Sha1>(&password, secret, 10, 5, 6).unwrap(), (true, Counter::new(13)));
+/// assert_eq!(verify_hotp_ct::<Sha1>(&password, secret, 13, 5, 6).unwrap(), (false, Counter::new(13)));
+/// ```
+#[must_use = "the verification result must be checked"]
+pub fn verify_hotp_ct<H>(
+    password: &str,
+    secret: &[u8],
+    counter: impl Into<Counter>,
+    lookahead: u8,
+    digits: u8,
+) -> Result<(bool, Counter), ThotpError>
+where
+    H: OtpHash,
+{
+    let counter = counter.into();
+
+    if password.len() != digits as usize {
+        return Ok((false, counter));
+    }
+
+    let key = HmacKey::<H>::new(secret)?;
+    let mut matched: Option<u64> = None;
+    let mut overflowed = false;
+
+    for offset in 0..=lookahead as u64 {
+        let current = match counter.as_u64().checked_add(offset) {
+            Some(current) => current,
+            None => {
+                overflowed = true;
+                continue;
+            }
+        };
+
+        let equal = ct_eq(key.code(current, digits)?.as_bytes(), password.as_bytes());
+        matched = matched.or(Some(current).filter(|_| equal));
+    }
+
+    // The same results as stopping at the first match, counters past u64::MAX come after all others
+    match matched {
+        Some(current) => {
+            let next = Counter::new(current)
+                .checked_next()
+                .ok_or(ThotpError::CounterOverflow)?;
+            Ok((true, next))
+        }
+        None if overflowed => Err(ThotpError::CounterOverflow),
+        None => Ok((false, counter)),
+    }
+}
+
+/// Resynchronizes a HOTP counter from several consecutive passwords, e.g. when the counter of a device
+/// is unknown and the user reads out the next few passwords it shows. Searches the counters in the range
+/// of `[search_start, search_start + search_window]` for one where `codes[i]` matches the counter
+/// `c + i` for every password and returns `c`, the counter of the first password. The counter the next
+/// password is expected for is therefore `c + codes.len()`.
+///
+/// Returns `None` if no counter in the window matches all of the passwords or if no passwords are
+/// given. The window stops at `u64::MAX`.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, resync_hotp, Sha1};
+///
+/// let secret = b"super secret";
+/// let first = otp_custom::<Sha1>(secret, 1234, 6).unwrap();
+/// let second = otp_custom::<Sha1>(secret, 1235, 6).unwrap();
+///
+/// let counter = resync_hotp::<Sha1>(&[&first, &second], secret, 1000, 500, 6).unwrap();
+///
+/// assert_eq!(counter, Some(1234));
+/// ```
+pub fn resync_hotp<H>(
+    codes: &[&str],
+    secret: &[u8],
+    search_start: u64,
+    search_window: u64,
+    digits: u8,
+) -> Result<Option<u64>, ThotpError>
+where
+    H: OtpHash,
+{
+    let (first, rest) = match codes.split_first() {
+        Some(split) => split,
+        None => return Ok(None),
+    };
+
+    if codes.iter().any(|code| code.len() != digits as usize) {
+        return Ok(None);
+    }
+
+    let key = HmacKey::<H>::new(secret)?;
+
+    for counter in search_start..=search_start.saturating_add(search_window) {
+        if !ct_eq(key.code(counter, digits)?.as_bytes(), first.as_bytes()) {
+            continue;
+        }
+
+        let mut consecutive = true;
+        for (offset, code) in (1..).zip(rest) {
+            let matched = match counter.checked_add(offset) {
+                Some(next) => ct_eq(key.code(next, digits)?.as_bytes(), code.as_bytes()),
+                None => false,
+            };
+
+            if !matched {
+                consecutive = false;
+                break;
+            }
+        }
+
+        if consecutive {
+            return Ok(Some(counter));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Generates HOTP passwords for `count` consecutive counters starting at `start_counter`, e.g. for
+/// printing them on a voucher for offline use.
+///
+/// Returns pairs of the counter and its password. The counter does not wrap around, so if the
+/// range would overflow `u64::MAX` the list stops at it and contains fewer than `count` pairs.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{print_codes, otp_custom, Sha1};
+///
+/// let secret = b"super secret";
+/// let codes = print_codes::<Sha1>(secret, 10, 3, 6).unwrap();
+///
+/// assert_eq!(codes.len(), 3);
+/// assert_eq!(codes[0], (10, otp_custom::<Sha1>(secret, 10, 6).unwrap()));
+/// assert_eq!(codes[2].0, 12);
+/// ```
+pub fn print_codes<H>(
+    secret: &[u8],
+    start_counter: u64,
+    count: usize,
+    digits: u8,
+) -> Result<Vec<(u64, String)>, ThotpError>
+where
+    H: OtpHash,
+{
+    // The number of counters left until overflow, including the starting one
+    let available = (u64::MAX - start_counter) as u128 + 1;
+    let count = (count as u128).min(available) as u64;
+
+    (0..count)
+        .map(|offset| {
+            let counter = start_counter + offset;
+            Ok((counter, otp_custom::<H>(secret, counter, digits)?))
+        })
+        .collect()
+}
+
+/// The constant prepended to the payload by [sign_response], so response tags never equal the HMAC
+/// a password was generated from.
+pub const RESPONSE_TAG: &[u8] = b"thotp-response";
+
+/// Signs a response payload with the secret of a verified credential, e.g. an acknowledgment the
+/// server returns after verifying a password. The tag is the HMAC of [RESPONSE_TAG] followed by the
+/// payload, using the same hashing algorithm as the passwords.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{sign_response, verify_response, Sha256};
+///
+/// let secret = b"super secret";
+/// let tag = sign_response::<Sha256>(secret, b"verified").unwrap();
+///
+/// assert!(verify_response::<Sha256>(secret, b"verified", &tag).unwrap());
+/// assert!(!verify_response::<Sha256>(secret, b"rejected", &tag).unwrap());
+/// ```
+pub fn sign_response<H>(secret: &[u8], payload: &[u8]) -> Result<Vec<u8>, ThotpError>
+where
+    H: OtpHash,
+{
+    let mut message = RESPONSE_TAG.to_vec();
+    message.extend_from_slice(payload);
+
+    Ok(hmac_digest::<H>(secret, &message)?.to_vec())
+}
+
+/// Verifies a tag created with [sign_response] for the given payload. The tags are compared in
+/// constant time.
+#[must_use = "the verification result must be checked"]
+pub fn verify_response<H>(secret: &[u8], payload: &[u8], tag: &[u8]) -> Result<bool, ThotpError>
+where
+    H: OtpHash,
+{
+    let expected = sign_response::<H>(secret, payload)?;
+    Ok(ct_eq(&expected, tag))
+}
+
+/// The HKDF salt used by [derive_secret], so the derived secrets are specific to it even if the
+/// master key is used with HKDF elsewhere.
+pub const DERIVE_SALT: &[u8] = b"thotp-derive-secret";
+
+/// Deterministically derives a secret of `len` bytes for the given label from a master key, e.g. to
+/// derive the secret of every service from a single stored master key instead of storing each of them.
+/// The same master key and label always result in the same secret, so a secret can be derived again
+/// whenever it is needed.
+///
+/// The secret is derived with HKDF-SHA256, with [DERIVE_SALT] as the salt and the label as the info.
+/// Anyone who knows the master key can derive every secret, so it has to be kept at least as safe as
+/// all of them together. Returns a [ThotpError::InvalidSecretLength] if `len` is larger than 8160.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::derive_secret;
+/// use thotp::generate_secret;
+///
+/// let master = generate_secret(32);
+///
+/// let mail = derive_secret(&master, "mail.example.com", 20).unwrap();
+/// let vpn = derive_secret(&master, "vpn.example.com", 20).unwrap();
+///
+/// assert_eq!(mail, derive_secret(&master, "mail.example.com", 20).unwrap());
+/// assert_ne!(mail, vpn);
+/// ```
+pub fn derive_secret(master: &[u8], label: &str, len: usize) -> Result<Vec<u8>, ThotpError> {
+    hkdf::<Sha256>(DERIVE_SALT, master, label.as_bytes(), len)
+}
@@ -1,4 +1,4 @@
-//! A simple module containing 2 functions to encode and decode the secrets generated by this crate's
+//! A simple module containing functions to encode and decode the secrets generated by this crate's
 //! `generate_secret` function to and from the given encoding available in the
 //! [data_encoding](https://docs.rs/data-encoding/latest/data_encoding/index.html) crate.
 //!
@@ -38,10 +38,131 @@ pub fn decode(key: &str, encoding: Encoding) -> Result<Vec<u8>, ThotpError> {
     Ok(decoded)
 }
 
+/// Decodes a Base32 encoded secret the way authenticator apps accept them, i.e. the padding is
+/// optional and the letters are case insensitive.
+///
+/// ## Example
+/// ```
+/// use thotp::encoding::decode_base32;
+///
+/// assert_eq!(decode_base32("GEZDGNBV").unwrap(), b"12345");
+/// assert_eq!(decode_base32("gezdgnbvgy").unwrap(), b"123456");
+/// assert_eq!(decode_base32("GEZDGNBVGY======").unwrap(), b"123456");
+/// ```
+pub fn decode_base32(secret: &str) -> Result<Vec<u8>, ThotpError> {
+    let secret = secret.trim_end_matches('=').to_uppercase();
+    let decoded = data_encoding::BASE32_NOPAD.decode(secret.as_bytes())?;
+    Ok(decoded)
+}
+
+/// Decodes a Base32 encoded secret the same way [decode_base32] does and checks that it is exactly
+/// `expected_len` bytes long, e.g. to catch secrets that were truncated while copying them when
+/// importing them. Returns a [ThotpError::UnexpectedSecretLength] if it is not.
+///
+/// ## Example
+/// ```
+/// use thotp::encoding::decode_secret_exact;
+/// use thotp::ThotpError;
+///
+/// let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+/// assert_eq!(decode_secret_exact(secret, 20).unwrap(), b"12345678901234567890");
+///
+/// assert!(matches!(
+///     decode_secret_exact(&secret[..24], 20),
+///     Err(ThotpError::UnexpectedSecretLength { expected: 20, got: 15 })
+/// ));
+/// ```
+pub fn decode_secret_exact(secret: &str, expected_len: usize) -> Result<Vec<u8>, ThotpError> {
+    let decoded = decode_base32(secret)?;
+
+    if decoded.len() != expected_len {
+        return Err(ThotpError::UnexpectedSecretLength {
+            expected: expected_len,
+            got: decoded.len(),
+        });
+    }
+
+    Ok(decoded)
+}
+
+/// The secret encodings told apart by [detect_encoding].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SecretEncoding {
+    Base32,
+    Hex,
+    Base64,
+}
+
+impl SecretEncoding {
+    /// Returns the name of the encoding.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SecretEncoding::Base32 => "Base32",
+            SecretEncoding::Hex => "hex",
+            SecretEncoding::Base64 => "Base64",
+        }
+    }
+
+    /// Decodes the secret from the encoding. Base32 is decoded with [decode_base32], hex may be
+    /// prefixed with `0x` and is case insensitive and Base64 may be unpadded.
+    pub fn decode(&self, secret: &str) -> Result<Vec<u8>, ThotpError> {
+        match self {
+            SecretEncoding::Base32 => decode_base32(secret),
+            SecretEncoding::Hex => {
+                let secret = secret.strip_prefix("0x").unwrap_or(secret);
+                Ok(data_encoding::HEXLOWER_PERMISSIVE.decode(secret.as_bytes())?)
+            }
+            SecretEncoding::Base64 => Ok(data_encoding::BASE64
+                .decode(secret.as_bytes())
+                .or_else(|_| data_encoding::BASE64_NOPAD.decode(secret.as_bytes()))?),
+        }
+    }
+}
+
+/// Guesses the encoding of the given secret from the characters it contains. A `0x` prefix means
+/// hex, otherwise Base32 is preferred over hex and hex over Base64, so an unprefixed secret such as
+/// `DEADBEEF` is detected as Base32. Returns `None` if the secret contains characters none of the
+/// encodings use.
+///
+/// This is only a heuristic meant for convenience, secrets of a known encoding should be decoded
+/// with it explicitly.
+///
+/// ## Example
+/// ```
+/// use thotp::encoding::{detect_encoding, SecretEncoding};
+///
+/// assert_eq!(detect_encoding("JBSWY3DPEHPK3PXP"), Some(SecretEncoding::Base32));
+/// assert_eq!(detect_encoding("0x48656c6c6f"), Some(SecretEncoding::Hex));
+/// assert_eq!(detect_encoding("SGVsbG8h+w=="), Some(SecretEncoding::Base64));
+/// assert_eq!(detect_encoding("not a secret"), None);
+/// ```
+pub fn detect_encoding(secret: &str) -> Option<SecretEncoding> {
+    if secret.starts_with("0x") {
+        return Some(SecretEncoding::Hex);
+    }
+
+    let is_base32 = |c: char| matches!(c.to_ascii_uppercase(), 'A'..='Z' | '2'..='7' | '=');
+    let is_base64 = |c: char| c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=');
+
+    if secret.is_empty() {
+        None
+    } else if secret.chars().all(is_base32) {
+        Some(SecretEncoding::Base32)
+    } else if secret.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(SecretEncoding::Hex)
+    } else if secret.chars().all(is_base64) {
+        Some(SecretEncoding::Base64)
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::{generate_secret, ThotpError};
-    use super::{decode, encode};
+    use super::{
+        decode, decode_base32, decode_secret_exact, detect_encoding, encode, SecretEncoding,
+    };
 
     #[test]
     fn encode_decode() -> Result<(), ThotpError> {
@@ -51,4 +172,66 @@ mod tests {
         assert_eq!(buffer, decoded);
         Ok(())
     }
+
+    #[test]
+    fn decode_base32_lenient() -> Result<(), ThotpError> {
+        let buffer = generate_secret(21);
+        let encoded = encode(&buffer, data_encoding::BASE32);
+        assert!(encoded.ends_with('='));
+
+        assert_eq!(decode_base32(&encoded)?, buffer);
+        assert_eq!(decode_base32(encoded.trim_end_matches('='))?, buffer);
+        assert_eq!(decode_base32(&encoded.to_lowercase())?, buffer);
+
+        assert!(decode_base32("GEZDGNB1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_exact_length() -> Result<(), ThotpError> {
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        assert_eq!(decode_secret_exact(secret, 20)?, b"12345678901234567890");
+        assert_eq!(
+            decode_secret_exact(&secret.to_lowercase(), 20)?,
+            decode_base32(secret)?
+        );
+
+        // A truncated secret still decodes, just to fewer bytes
+        assert!(matches!(
+            decode_secret_exact(&secret[..16], 20),
+            Err(ThotpError::UnexpectedSecretLength {
+                expected: 20,
+                got: 10
+            })
+        ));
+        assert!(matches!(
+            decode_secret_exact(secret, 32),
+            Err(ThotpError::UnexpectedSecretLength {
+                expected: 32,
+                got: 20
+            })
+        ));
+        assert!(matches!(
+            decode_secret_exact("GEZDGNB!", 5),
+            Err(ThotpError::Encoding(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn detect_secret_encoding() -> Result<(), ThotpError> {
+        assert_eq!(detect_encoding("gezdgnbv"), Some(SecretEncoding::Base32));
+        assert_eq!(detect_encoding("3132333435"), Some(SecretEncoding::Hex));
+        assert_eq!(detect_encoding("0xDEADBEEF"), Some(SecretEncoding::Hex));
+        assert_eq!(detect_encoding("MTIzNDU/"), Some(SecretEncoding::Base64));
+        // Base64 without digits outside of Base32 looks just like Base32
+        assert_eq!(detect_encoding("MTIzNDU2"), Some(SecretEncoding::Base32));
+        assert_eq!(detect_encoding(""), None);
+        assert_eq!(detect_encoding("12-34"), None);
+
+        assert_eq!(SecretEncoding::Hex.decode("0x3132333435")?, b"12345");
+        assert_eq!(SecretEncoding::Base64.decode("MTIzNDU")?, b"12345");
+        assert!(SecretEncoding::Hex.decode("313").is_err());
+        Ok(())
+    }
 }
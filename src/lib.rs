@@ -18,6 +18,11 @@
 //! The `qr` feature flag gives access to the [qr] module and enables QR code generation of the
 //! generated secret keys ready to be used by authenticator apps.
 //!
+//! The `custom` feature flag also gives access to the [server] module which contains stateful helpers,
+//! such as replay protection, for servers verifying OTPs and the [totp] module which contains a builder
+//! holding a secret along with its TOTP parameters. The [presets] module contains the parameters
+//! used by known providers.
+//!
 //! ## Example usage
 //!
 //! *Generate a secret and qr code, and verify a password generated with said secret:*
@@ -184,11 +189,25 @@ pub mod encoding;
 #[cfg(feature = "qr")]
 pub mod qr;
 
+#[cfg(feature = "qr")]
+pub mod migration;
+
+#[cfg(feature = "custom")]
+pub mod presets;
+
+#[cfg(feature = "custom")]
+pub mod server;
+
+#[cfg(feature = "custom")]
+pub mod totp;
+
 use otp_core::{
-    dynamic_trunc, hmac_digest, time_step_now, ALLOWED_DRIFT, DIGITS_DEFAULT, TIME_STEP,
+    ct_eq, dynamic_trunc, hmac_digest, time_step_now, to_password, ALLOWED_DRIFT, DIGITS_DEFAULT,
+    TIME_STEP,
 };
 use rand::{rngs::StdRng, RngCore, SeedableRng};
 use sha1::Sha1;
+#[cfg(feature = "custom")]
 use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
@@ -209,20 +228,13 @@ pub fn otp(secret: &[u8], nonce: u64) -> Result<String, ThotpError> {
     let nonce = &nonce.to_be_bytes();
 
     // Create an HMAC digest with the given key, nonce and algorithm
-    let mut hmac = hmac_digest::<Sha1>(secret, nonce)?;
+    let hmac = hmac_digest::<Sha1>(secret, nonce)?;
 
     // Truncate to 4 bytes
-    let trunc = dynamic_trunc(&mut hmac);
-
-    // Mod it with the default number of digits to get the password
-    let mut result = (trunc % 10_u32.pow(DIGITS_DEFAULT as u32)).to_string();
-
-    // Pad with 0s if the number is shorter than the necessary digits
-    for i in 0..(DIGITS_DEFAULT as usize - result.len()) {
-        result.insert(i, '0');
-    }
+    let trunc = dynamic_trunc(&hmac);
 
-    Ok(result)
+    // Mod it with the number of digits and pad it with 0s to get the password
+    Ok(to_password(trunc, DIGITS_DEFAULT))
 }
 
 /// Verifies the given password for the given timestamp and secret.
@@ -268,11 +280,17 @@ pub fn otp(secret: &[u8], nonce: u64) -> Result<String, ThotpError> {
 /// assert_eq!((true, 0),(result, discrepancy));
 ///
 /// ```
+#[must_use = "the verification result must be checked"]
 pub fn verify_totp(
     password: &str,
     secret: &[u8],
     timestamp: u64,
 ) -> Result<(bool, i16), ThotpError> {
+    // Passwords of the wrong length can never match, so skip generating any
+    if password.len() != DIGITS_DEFAULT as usize {
+        return Ok((false, 0));
+    }
+
     let nonce = if timestamp == 0 {
         time_step_now()?
     } else {
@@ -283,23 +301,50 @@ pub fn verify_totp(
     let end = nonce.saturating_add(ALLOWED_DRIFT as u64);
 
     // Keeps track of how large the deicrepancy is
-    let mut i = -(ALLOWED_DRIFT as i16);
-
-    for n in start..=end {
+    for (i, n) in (-(ALLOWED_DRIFT as i16)..).zip(start..=end) {
         let pass = otp(secret, n)?;
-        if pass.eq(password) {
+        if ct_eq(pass.as_bytes(), password.as_bytes()) {
             return Ok((true, i));
         }
-        i += 1;
     }
 
     Ok((false, 0))
 }
 
+/// Verifies the given password for the current time and the Base32 encoded secret with the RFC
+/// defaults, i.e. SHA1, 6 digits, a time step of 30 and an allowed drift of 1, the way most
+/// authenticator apps generate them. The secret is decoded the same way [totp::Totp::from_base32]
+/// decodes it.
+///
+/// Use [verify_totp] to also get the discrepancy or to verify for a different timestamp.
+///
+/// ## Example
+/// ```
+/// use thotp::{encoding::decode_base32, otp, verify_totp_default};
+/// use std::time::{SystemTime, UNIX_EPOCH};
+///
+/// let secret = decode_base32("JBSWY3DPEHPK3PXP").unwrap();
+/// let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+/// let password = otp(&secret, now / 30).unwrap();
+///
+/// assert!(verify_totp_default(&password, "JBSWY3DPEHPK3PXP").unwrap());
+/// ```
+#[cfg(feature = "encoding")]
+#[must_use = "the verification result must be checked"]
+pub fn verify_totp_default(password: &str, secret_b32: &str) -> Result<bool, ThotpError> {
+    let secret: String = secret_b32.split_whitespace().collect();
+    let secret = encoding::decode_base32(&secret)?;
+
+    let (valid, _) = verify_totp(password, &secret, 0)?;
+    Ok(valid)
+}
+
 /// Generates multiple hotp passwords in the range of `lookahead + 1` and compares them to the input.
-/// The counter wraps around on overflow.
 /// A lookahead of 0 means only the current counter will be used in the verification.
 ///
+/// The counter never wraps around. If the lookahead reaches past `u64::MAX` before a match is found, or the
+/// counter can not be incremented after a match at `u64::MAX`, a [ThotpError::CounterOverflow] is returned.
+///
 /// Uses SHA1 and the default digit length of 6.
 /// If you need finer control of the verification parameters, use the `custom_otp` feature flag.
 ///
@@ -307,7 +352,7 @@ pub fn verify_totp(
 ///
 /// ## Example
 /// ```
-/// use thotp::{otp, verify_hotp};
+/// use thotp::{otp, verify_hotp, Counter};
 ///
 /// let counter = 1;
 /// let secret = b"super secret";
@@ -315,33 +360,208 @@ pub fn verify_totp(
 ///
 /// let (result, counter) = verify_hotp(&password, secret, counter, 0).unwrap();
 ///
-/// assert_eq!(counter, 2);
+/// assert_eq!(counter, Counter::new(2));
 /// assert!(result);
 ///
 /// let (result, counter) = verify_hotp("fail", secret, counter, 0).unwrap();
 ///
-/// assert_eq!(counter, 2);
+/// assert_eq!(counter, Counter::new(2));
 /// assert!(!result);
 /// ```
+#[must_use = "the verification result must be checked"]
 pub fn verify_hotp(
     password: &str,
     secret: &[u8],
-    counter: u64,
+    counter: impl Into<Counter>,
     lookahead: usize,
-) -> Result<(bool, u64), ThotpError> {
-    for current in 0..lookahead + 1 {
-        let curr = (counter as u128 + current as u128) as u64;
+) -> Result<(bool, Counter), ThotpError> {
+    let counter = counter.into();
+
+    // Passwords of the wrong length can never match, so skip generating any
+    if password.len() != DIGITS_DEFAULT as usize {
+        return Ok((false, counter));
+    }
+
+    for current in 0..=lookahead as u64 {
+        let curr = counter
+            .as_u64()
+            .checked_add(current)
+            .ok_or(ThotpError::CounterOverflow)?;
 
         let pass = otp(secret, curr)?;
 
-        if pass.eq(password) {
-            return Ok((true, (curr as u128 + 1) as u64));
+        if ct_eq(pass.as_bytes(), password.as_bytes()) {
+            let next = Counter::new(curr)
+                .checked_next()
+                .ok_or(ThotpError::CounterOverflow)?;
+            return Ok((true, next));
         }
     }
 
     Ok((false, counter))
 }
 
+/// A HOTP counter. Wraps a `u64` so incrementing it has a defined outcome on overflow instead of
+/// silently wrapping around.
+///
+/// ## Example
+/// ```
+/// use thotp::Counter;
+///
+/// let counter = Counter::from(1);
+///
+/// assert_eq!(counter.checked_next(), Some(Counter::from(2)));
+/// assert_eq!(Counter::from(u64::MAX).checked_next(), None);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Counter(u64);
+
+impl Counter {
+    /// Creates a counter with the given value.
+    pub const fn new(counter: u64) -> Self {
+        Self(counter)
+    }
+
+    /// Returns the counter incremented by 1, or `None` if it would overflow.
+    pub const fn checked_next(&self) -> Option<Counter> {
+        match self.0.checked_add(1) {
+            Some(next) => Some(Self(next)),
+            None => None,
+        }
+    }
+
+    /// Returns the value of the counter.
+    pub const fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for Counter {
+    fn from(counter: u64) -> Self {
+        Self(counter)
+    }
+}
+
+impl From<Counter> for u64 {
+    fn from(counter: Counter) -> Self {
+        counter.0
+    }
+}
+
+impl std::fmt::Display for Counter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A generated password. Passwords may start with 0s, so they are kept as a string of a fixed width
+/// and only converted to a number explicitly with [Code::value], which loses the leading 0s.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, otp_custom_code, Sha1};
+///
+/// let secret = b"12345678901234567890";
+/// let code = otp_custom_code::<Sha1>(secret, 1, 6).unwrap();
+///
+/// assert_eq!(code.to_string(), otp_custom::<Sha1>(secret, 1, 6).unwrap());
+/// assert_eq!(code.as_str(), "287082");
+/// assert_eq!(code.digits(), 6);
+/// assert_eq!(code.value(), 287082);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Code(String);
+
+impl Code {
+    /// Returns the password as a string, including any leading 0s.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the number of digits of the password.
+    pub fn digits(&self) -> u8 {
+        self.0.len() as u8
+    }
+
+    /// Returns the numeric value of the password. Leading 0s are lost, use [Code::digits] to
+    /// restore them when formatting the value again.
+    pub fn value(&self) -> u64 {
+        self.0
+            .bytes()
+            .fold(0, |value, digit| value * 10 + (digit - b'0') as u64)
+    }
+}
+
+impl AsRef<str> for Code {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<Code> for String {
+    fn from(code: Code) -> Self {
+        code.0
+    }
+}
+
+impl PartialEq<str> for Code {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl std::fmt::Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(&self.0)
+    }
+}
+
+/// The outcome of verifying a password with [server::verify_submission],
+/// [custom::verify_totp_batch] or [custom::verify_totp_instrumented].
+#[cfg(feature = "custom")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use = "the verification result must be checked"]
+pub enum VerifyOutcome {
+    /// The password was valid. The `drift` is the number of time slices the password deviates from
+    /// the current one.
+    Accepted { drift: i16 },
+
+    /// The password was well formed but did not match any time slice in the allowed drift.
+    Rejected,
+
+    /// The password contained something other than digits, spaces and dashes or did not have the
+    /// configured number of digits, so nothing was verified.
+    Malformed,
+}
+
+/// Formats a password to be read out by text to speech, e.g. in a phone call. Every digit is separated
+/// by a space so it is read individually, including leading 0s, and every `group` digits are separated
+/// by a comma so the reader pauses between them. A `group` of 0 does not group the digits.
+///
+/// ## Example
+/// ```
+/// use thotp::format_for_speech;
+///
+/// assert_eq!(format_for_speech("012345", 3), "0 1 2, 3 4 5");
+/// assert_eq!(format_for_speech("01234567", 0), "0 1 2 3 4 5 6 7");
+/// ```
+pub fn format_for_speech(code: &str, group: usize) -> String {
+    let mut spoken = String::with_capacity(code.len() * 3);
+
+    for (i, digit) in code.chars().enumerate() {
+        if i > 0 {
+            if group > 0 && i % group == 0 {
+                spoken.push_str(", ");
+            } else {
+                spoken.push(' ');
+            }
+        }
+        spoken.push(digit);
+    }
+
+    spoken
+}
+
 /// Generates a secret key, i.e. a buffer filled with random bytes. The RFC recommended buffer
 /// size is 160.
 ///
@@ -384,6 +604,53 @@ pub enum ThotpError {
     #[error("{0}")]
     InvalidUri(String),
 
+    #[error("Invalid value `{value}` provided for the uri parameter `{key}`")]
+    InvalidParameter { key: String, value: String },
+
+    #[error(
+        "The issuer `{label}` in the uri label does not match the issuer parameter `{parameter}`"
+    )]
+    IssuerMismatch { label: String, parameter: String },
+
+    #[error("The time step must be greater than 0")]
+    InvalidStep,
+
+    #[error("Invalid packed policy `{0}`, only the lower 16 bits may be set")]
+    InvalidPolicy(u32),
+
+    #[error("Derived secrets can be at most {max} bytes long")]
+    InvalidSecretLength { max: usize },
+
+    #[error("The secret is {got} bytes long, expected {expected}")]
+    UnexpectedSecretLength { expected: usize, got: usize },
+
+    #[error("The buffer is too small to hold a password of {0} digits")]
+    BufferTooSmall(u8),
+
+    #[error("The alphabet must contain at least 2 characters")]
+    InvalidAlphabet,
+
+    #[error("The code contains characters other than digits, spaces and dashes")]
+    InvalidCode,
+
+    #[error("The check digit of the password does not match its other digits")]
+    ChecksumMismatch,
+
+    #[error("Invalid verifier state provided")]
+    InvalidState,
+
+    #[error("Unsupported format version `{0}`")]
+    UnsupportedVersion(u8),
+
+    #[error("The HOTP counter can not be incremented any further")]
+    CounterOverflow,
+
+    #[error("The timestamp is too far from the server time")]
+    TimestampOutOfRange,
+
+    #[error("An arithmetic overflow occurred while calculating the counter or timestamp")]
+    Conversion,
+
     #[error("An error occurred while trying to calculate system time: `{0}`")]
     SystemTime(#[from] std::time::SystemTimeError),
 
@@ -391,10 +658,29 @@ pub enum ThotpError {
     #[error("An error occurred while trying to decode string: `{0}`")]
     Encoding(#[from] data_encoding::DecodeError),
 
+    #[cfg(feature = "encoding")]
+    #[error("The secret could not be decoded, attempted: {0}")]
+    UnknownEncoding(String),
+
     #[cfg(feature = "qr")]
     #[error("An error occurred while generating QR code: `{0}`")]
     QR(#[from] qrcode::types::QrError),
 
+    #[cfg(feature = "qr")]
+    #[error("{0}")]
+    InvalidLogo(String),
+
+    #[cfg(feature = "qr")]
+    #[error("An error occurred while reading the otp uri: `{0}`")]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "qr")]
+    #[error("Could not read the environment variable `{name}`: `{source}`")]
+    Env {
+        name: String,
+        source: std::env::VarError,
+    },
+
     #[cfg(feature = "qr")]
     #[error("Formatting error: {0}")]
     Format(#[from] std::fmt::Error),
@@ -412,96 +698,1698 @@ mod tests {
         let counter = 1;
         let password = otp(TEST_KEY, counter)?;
         let (result, counter) = verify_hotp(&password, TEST_KEY, counter, 0)?;
-        assert_eq!(counter, 2);
+        assert_eq!(counter, Counter::new(2));
         assert!(result);
 
-        let password = otp_custom::<Sha1>(TEST_KEY, counter, DIGITS_DEFAULT)?;
+        let password = otp_custom::<Sha1>(TEST_KEY, counter.as_u64(), DIGITS_DEFAULT)?;
         let (result, counter) = verify_hotp(&password, TEST_KEY, counter, 0)?;
-        assert_eq!(counter, 3);
+        assert_eq!(counter, Counter::new(3));
         assert!(result);
 
         let (result, counter) = verify_hotp("fail", TEST_KEY, counter, 0)?;
         assert!(!result);
-        assert_eq!(counter, 3);
+        assert_eq!(counter, Counter::new(3));
 
-        // Test with lookahead and overflow
-        let password = otp_custom::<Sha1>(TEST_KEY, counter, DIGITS_DEFAULT)?;
-        let (result, counter) = verify_hotp(&password, TEST_KEY, u64::MAX, 20)?;
-        assert!(result);
-        assert_eq!(counter, 4);
+        // Test with lookahead and overflow, the lookahead does not wrap around
+        let password = otp_custom::<Sha1>(TEST_KEY, counter.as_u64(), DIGITS_DEFAULT)?;
+        assert!(matches!(
+            verify_hotp(&password, TEST_KEY, u64::MAX, 20),
+            Err(ThotpError::CounterOverflow)
+        ));
 
         let password = otp_custom::<Sha1>(TEST_KEY, u64::MAX - 1, DIGITS_DEFAULT)?;
         let (result, counter) = verify_hotp(&password, TEST_KEY, u64::MAX - 18, 20)?;
         assert!(result);
-        assert_eq!(counter, u64::MAX);
+        assert_eq!(counter, Counter::new(u64::MAX));
 
-        // Sha1
-        let password = otp_custom::<Sha1>(TEST_KEY, counter, DIGITS_DEFAULT)?;
-        let (result, counter) = verify_hotp(&password, TEST_KEY, u64::MAX, 0)?;
-        assert!(result);
-        assert_eq!(counter, 0);
+        // Sha1, the counter can not be incremented after a match at the ceiling
+        let password = otp_custom::<Sha1>(TEST_KEY, counter.as_u64(), DIGITS_DEFAULT)?;
+        assert!(matches!(
+            verify_hotp(&password, TEST_KEY, u64::MAX, 0),
+            Err(ThotpError::CounterOverflow)
+        ));
 
         // Sha256
         let password = otp_custom::<Sha256>(TEST_KEY, u64::MAX - 1, DIGITS_DEFAULT)?;
         let (result, counter) =
             verify_hotp_custom::<Sha256>(&password, TEST_KEY, u64::MAX - 18, 20, DIGITS_DEFAULT)?;
         assert!(result);
-        assert_eq!(counter, u64::MAX);
+        assert_eq!(counter, Counter::new(u64::MAX));
 
         // Sha512
         let password = otp_custom::<Sha512>(TEST_KEY, u64::MAX - 1, DIGITS_DEFAULT)?;
         let (result, counter) =
             verify_hotp_custom::<Sha512>(&password, TEST_KEY, u64::MAX - 18, 20, DIGITS_DEFAULT)?;
         assert!(result);
-        assert_eq!(counter, u64::MAX);
+        assert_eq!(counter, Counter::new(u64::MAX));
 
         Ok(())
     }
 
-    // The values in the next 3 tests come from the RFC
     #[test]
-    fn totp_sha1() {
-        let secret: &[u8] = b"12345678901234567890";
-        assert_eq!(20, secret.len());
+    fn hotp_counter_ceiling() -> Result<(), ThotpError> {
+        // Matches before the ceiling are still accepted
+        let password = otp_custom::<Sha1>(TEST_KEY, u64::MAX - 1, 6)?;
+        assert_eq!(
+            (true, Counter::new(u64::MAX)),
+            verify_hotp_custom::<Sha1>(&password, TEST_KEY, u64::MAX - 2, 5, 6)?
+        );
 
-        let pairs = vec![
+        // The counter can not be incremented after a match at the ceiling
+        let password = otp_custom::<Sha1>(TEST_KEY, u64::MAX, 6)?;
+        assert!(matches!(
+            verify_hotp_custom::<Sha1>(&password, TEST_KEY, u64::MAX - 2, 5, 6),
+            Err(ThotpError::CounterOverflow)
+        ));
+
+        // The lookahead does not wrap around
+        let password = otp_custom::<Sha1>(TEST_KEY, 0, 6)?;
+        assert!(matches!(
+            verify_hotp_custom::<Sha1>(&password, TEST_KEY, u64::MAX, 1, 6),
+            Err(ThotpError::CounterOverflow)
+        ));
+
+        // The maximum lookahead does not overflow
+        let password = otp_custom::<Sha1>(TEST_KEY, 255, 6)?;
+        assert_eq!(
+            (true, Counter::new(256)),
+            verify_hotp_custom::<Sha1>(&password, TEST_KEY, 0, u8::MAX, 6)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hotp_constant_time() -> Result<(), ThotpError> {
+        let custom = |password: &str, counter, lookahead| {
+            verify_hotp_custom::<Sha1>(password, TEST_KEY, counter, lookahead, 6)
+        };
+        let ct = |password: &str, counter, lookahead| {
+            verify_hotp_ct::<Sha1>(password, TEST_KEY, counter, lookahead, 6)
+        };
+
+        for nonce in 0..30 {
+            let password = otp_custom::<Sha1>(TEST_KEY, nonce, 6)?;
+            for (counter, lookahead) in [(0, 0), (0, 10), (5, 5), (10, 20), (nonce, 0)] {
+                assert_eq!(
+                    ct(&password, counter, lookahead)?,
+                    custom(&password, counter, lookahead)?
+                );
+            }
+        }
+        assert_eq!(ct("00000", 0, 10)?, (false, Counter::new(0)));
+
+        // The same results around the ceiling
+        for nonce in [u64::MAX - 1, u64::MAX, 0] {
+            let password = otp_custom::<Sha1>(TEST_KEY, nonce, 6)?;
+            for counter in [u64::MAX - 2, u64::MAX] {
+                match (ct(&password, counter, 5), custom(&password, counter, 5)) {
+                    (Ok(ct), Ok(custom)) => assert_eq!(ct, custom),
+                    (Err(ThotpError::CounterOverflow), Err(ThotpError::CounterOverflow)) => {}
+                    results => panic!("different results {:?}", results),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn totp_extended_window() -> Result<(), ThotpError> {
+        let issued = 1111111109;
+        let token = generate_extended::<Sha1>(TEST_KEY, issued, 30, 8, 9)?;
+        assert_eq!(token.code, "07081804");
+        assert_eq!(token.counter, issued / 30);
+        assert_eq!(token.valid_from, issued / 30 * 30);
+        assert_eq!(token.valid_until, (issued / 30 + 10) * 30);
+
+        let verify =
+            |timestamp| verify_extended::<Sha1>(&token.code, TEST_KEY, timestamp, 8, 30, 9);
+
+        // Accepted from the slice it was issued in until the end of the window
+        assert_eq!(verify(issued)?, (true, 0));
+        assert_eq!(verify(issued + 150)?, (true, -5));
+        assert_eq!(verify(token.valid_until - 1)?, (true, -9));
+
+        // Rejected after the window and before it was issued
+        assert_eq!(verify(token.valid_until)?, (false, 0));
+        assert_eq!(verify(token.valid_from - 1)?, (false, 0));
+
+        // Without extra slices it is just the current slice
+        let token = generate_extended::<Sha1>(TEST_KEY, issued, 30, 8, 0)?;
+        assert_eq!(token, current_totp::<Sha1>(TEST_KEY, issued, 30, 8)?);
+        assert_eq!(
+            generate_extended::<Sha1>(TEST_KEY, u64::MAX - 100, 30, 8, 9)?.valid_until,
+            u64::MAX
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_no_future() -> Result<(), ThotpError> {
+        let timestamp = 1111111109;
+        let nonce = timestamp / 30;
+        let verify = |password: &str, back_drift| {
+            verify_totp_no_future::<Sha1>(password, TEST_KEY, timestamp, 8, 30, back_drift)
+        };
+
+        assert_eq!(verify("07081804", 0)?, (true, 0));
+        for age in 1..=3 {
+            let past = otp_custom::<Sha1>(TEST_KEY, nonce - age, 8)?;
+            assert_eq!(verify(&past, 3)?, (true, -(age as i16)));
+            assert_eq!(verify(&past, age as u8 - 1)?, (false, 0));
+        }
+
+        // Future slices are rejected, even though the regular drift accepts them
+        for ahead in 1..=3 {
+            let future = otp_custom::<Sha1>(TEST_KEY, nonce + ahead, 8)?;
+            assert_eq!(verify(&future, 3)?, (false, 0));
+            assert_eq!(
+                verify_totp_custom::<Sha1>(&future, TEST_KEY, timestamp, 8, 30, 3)?,
+                (true, ahead as i16)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "rfc3339")]
+    fn totp_rfc3339_window() -> Result<(), ThotpError> {
+        assert_eq!(to_rfc3339(951_782_400)?, "2000-02-29T00:00:00Z");
+        assert_eq!(to_rfc3339(4_107_542_399)?, "2100-02-28T23:59:59Z");
+        assert_eq!(to_rfc3339(253_402_300_799)?, "9999-12-31T23:59:59Z");
+        assert!(matches!(
+            to_rfc3339(253_402_300_800),
+            Err(ThotpError::Conversion)
+        ));
+
+        // RFC 6238 appendix B with SHA1, where 1111111109 is 2005-03-18T01:58:29Z
+        let window = verify_totp_rfc3339::<Sha1>("07081804", TEST_KEY, 1111111109, 8, 30, 1)?;
+        let (valid_from, valid_until) = window.expect("the password matches");
+        assert_eq!(valid_from, "2005-03-18T01:58:00Z");
+        assert_eq!(valid_until, "2005-03-18T01:58:30Z");
+
+        // A match in the previous slice returns the window of that slice
+        let window = verify_totp_rfc3339::<Sha1>("07081804", TEST_KEY, 1111111139, 8, 30, 1)?;
+        assert_eq!(
+            window,
+            Some((
+                "2005-03-18T01:58:00Z".to_string(),
+                "2005-03-18T01:58:30Z".to_string()
+            ))
+        );
+
+        assert_eq!(
+            verify_totp_rfc3339::<Sha1>("07081804", TEST_KEY, 1111111109 + 90, 8, 30, 1)?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_timestamp_ceiling() -> Result<(), ThotpError> {
+        assert_eq!(totp_counter(59, 30)?, 1);
+        assert_eq!(next_boundary_at(59, 30)?, 60);
+        assert!(matches!(
+            next_boundary_at(u64::MAX, 30),
+            Err(ThotpError::Conversion)
+        ));
+        assert!(matches!(
+            current_totp::<Sha1>(TEST_KEY, u64::MAX, 30, 6),
+            Err(ThotpError::Conversion)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "encoding")]
+    fn totp_default_verification() -> Result<(), ThotpError> {
+        let encoded = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        assert_eq!(encoding::decode_base32(encoded)?, TEST_KEY);
+
+        let password = otp(TEST_KEY, time_step_now()?)?;
+        assert!(verify_totp_default(&password, encoded)?);
+        assert!(verify_totp_default(
+            &password,
+            "gezd gnbv gy3t qojq gezd gnbv gy3t qojq"
+        )?);
+
+        let wrong = format!("{:06}", (password.parse::<u32>().unwrap() + 1) % 1_000_000);
+        let (expected, _) = verify_totp(&wrong, TEST_KEY, 0)?;
+        assert_eq!(verify_totp_default(&wrong, encoded)?, expected);
+        assert!(!verify_totp_default("12345", encoded)?);
+
+        assert!(matches!(
+            verify_totp_default(&password, "not base32!"),
+            Err(ThotpError::Encoding(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn speech_format() {
+        assert_eq!(format_for_speech("007123", 3), "0 0 7, 1 2 3");
+        assert_eq!(format_for_speech("007123", 2), "0 0, 7 1, 2 3");
+        assert_eq!(format_for_speech("0071234", 3), "0 0 7, 1 2 3, 4");
+        assert_eq!(format_for_speech("007123", 6), "0 0 7 1 2 3");
+        assert_eq!(format_for_speech("007123", 10), "0 0 7 1 2 3");
+        assert_eq!(format_for_speech("007123", 0), "0 0 7 1 2 3");
+        assert_eq!(format_for_speech("", 3), "");
+    }
+
+    #[test]
+    fn counter() {
+        let counter = Counter::new(41);
+        assert_eq!(counter.checked_next(), Some(Counter::from(42)));
+        assert_eq!(counter.checked_next().unwrap().as_u64(), 42);
+        assert_eq!(u64::from(counter), 41);
+        assert_eq!(counter.to_string(), "41");
+
+        assert_eq!(
+            Counter::from(u64::MAX - 1).checked_next(),
+            Some(Counter::from(u64::MAX))
+        );
+        assert_eq!(Counter::from(u64::MAX).checked_next(), None);
+    }
+
+    #[test]
+    fn code_leading_zeros() -> Result<(), ThotpError> {
+        let code = Code(String::from("012345"));
+        assert_eq!(code.to_string(), "012345");
+        assert_eq!(format!("{code:>8}"), "  012345");
+        assert_eq!(code.as_str(), "012345");
+        assert_eq!(code.digits(), 6);
+        assert_eq!(code.value(), 12345);
+        assert_eq!(
+            format!("{:0width$}", code.value(), width = code.digits() as usize),
+            "012345"
+        );
+
+        assert_eq!(&otp_custom_code::<Sha1>(TEST_KEY, 4, 6)?, "338314");
+        for nonce in 0..200 {
+            let code = otp_custom_code::<Sha1>(TEST_KEY, nonce, 6)?;
+            assert_eq!(code.digits(), 6);
+            assert_eq!(String::from(code), otp_custom::<Sha1>(TEST_KEY, nonce, 6)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn hotp_resync() -> Result<(), ThotpError> {
+        // RFC 4226 appendix D, counters 5 to 7
+        let codes = ["254676", "287922", "162583"];
+        assert_eq!(resync_hotp::<Sha1>(&codes, TEST_KEY, 0, 10, 6)?, Some(5));
+        assert_eq!(resync_hotp::<Sha1>(&codes, TEST_KEY, 5, 0, 6)?, Some(5));
+
+        // The window ends before the first counter
+        assert_eq!(resync_hotp::<Sha1>(&codes, TEST_KEY, 0, 4, 6)?, None);
+        assert_eq!(resync_hotp::<Sha1>(&codes, TEST_KEY, 6, 100, 6)?, None);
+
+        // Out of order passwords never line up
+        let codes = ["254676", "162583", "287922"];
+        assert_eq!(resync_hotp::<Sha1>(&codes, TEST_KEY, 0, 10, 6)?, None);
+
+        assert_eq!(resync_hotp::<Sha1>(&[], TEST_KEY, 0, 10, 6)?, None);
+
+        let codes = [
+            otp_custom::<Sha1>(TEST_KEY, u64::MAX - 1, 6)?,
+            otp_custom::<Sha1>(TEST_KEY, u64::MAX, 6)?,
+        ];
+        let codes = [codes[0].as_str(), codes[1].as_str()];
+        assert_eq!(
+            resync_hotp::<Sha1>(&codes, TEST_KEY, u64::MAX - 10, 100, 6)?,
+            Some(u64::MAX - 1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hotp_print_codes() -> Result<(), ThotpError> {
+        let codes = print_codes::<Sha1>(TEST_KEY, 0, 10, 6)?;
+        assert_eq!(codes.len(), 10);
+
+        // The passwords come from RFC 4226 appendix D
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (i, (counter, code)) in codes.iter().enumerate() {
+            assert_eq!(*counter, i as u64);
+            assert_eq!(code, expected[i]);
+        }
+
+        assert!(print_codes::<Sha1>(TEST_KEY, 0, 0, 6)?.is_empty());
+
+        // Stops at the maximum counter instead of wrapping around
+        let codes = print_codes::<Sha1>(TEST_KEY, u64::MAX - 2, 10, 6)?;
+        let counters: Vec<u64> = codes.iter().map(|(counter, _)| *counter).collect();
+        assert_eq!(counters, [u64::MAX - 2, u64::MAX - 1, u64::MAX]);
+        assert_eq!(codes[2].1, otp_custom::<Sha1>(TEST_KEY, u64::MAX, 6)?);
+        Ok(())
+    }
+
+    #[test]
+    fn secret_entropy() {
+        let short = Secret::new(generate_secret(10));
+        assert_eq!(short.entropy_bits(), 80);
+        assert!(!short.is_recommended_for(Algorithm::Sha1));
+
+        let secret = Secret::new(TEST_KEY.to_vec());
+        assert_eq!(secret.len(), 20);
+        assert_eq!(secret.entropy_bits(), 160);
+        assert!(secret.is_recommended_for(Algorithm::Sha1));
+        assert!(!secret.is_recommended_for(Algorithm::Sha256));
+        assert!(!secret.is_recommended_for(Algorithm::Sha512));
+        assert!(Secret::new(generate_secret(64)).is_recommended_for(Algorithm::Sha512));
+
+        assert_eq!(secret, Secret::from(TEST_KEY.to_vec()));
+        assert_ne!(secret, short);
+        assert_eq!(format!("{:?}", secret), "Secret(20 bytes)");
+    }
+
+    #[test]
+    #[cfg(feature = "encoding")]
+    fn secret_parse() -> Result<(), ThotpError> {
+        assert_eq!(
+            Secret::parse("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ")?.as_bytes(),
+            TEST_KEY
+        );
+        assert_eq!(
+            Secret::parse(" gezdgnbvgy3tqojq ")?.as_bytes(),
+            b"1234567890"
+        );
+        assert_eq!(Secret::parse("0x3132333435")?.as_bytes(), b"12345");
+        assert_eq!(Secret::parse("3132333435")?.as_bytes(), b"12345");
+        assert_eq!(Secret::parse("MTIzNDU2Nzg5MA==")?.as_bytes(), b"1234567890");
+
+        // Valid Base32 characters, but not a valid length for Base32, hex or Base64
+        match Secret::parse("ABC") {
+            Err(ThotpError::UnknownEncoding(attempted)) => {
+                assert_eq!(attempted, "Base32, hex, Base64")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        match Secret::parse("not a secret") {
+            Err(ThotpError::UnknownEncoding(attempted)) => {
+                assert_eq!(attempted, "Base32, hex, Base64")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn counter_encoding() -> Result<(), ThotpError> {
+        assert_eq!(CounterEncoding::default(), CounterEncoding::Rfc8Byte);
+        assert_eq!(CounterEncoding::MinimalBe.encode(0), [0]);
+        assert_eq!(CounterEncoding::MinimalBe.encode(1), [1]);
+        assert_eq!(CounterEncoding::MinimalBe.encode(0x1_00), [1, 0]);
+        assert_eq!(CounterEncoding::MinimalBe.encode(u64::MAX), [0xff; 8]);
+
+        // RFC 4226 appendix D
+        let expected = ["755224", "287082", "359152", "969429", "338314"];
+        for (counter, expected) in (0..).zip(expected) {
+            let rfc = otp_custom_encoded::<Sha1>(TEST_KEY, counter, 6, CounterEncoding::Rfc8Byte)?;
+            let minimal =
+                otp_custom_encoded::<Sha1>(TEST_KEY, counter, 6, CounterEncoding::MinimalBe)?;
+            assert_eq!(rfc, expected);
+            assert_ne!(minimal, expected);
+        }
+
+        // Counters without leading 0 bytes are encoded the same way
+        let counter = u64::MAX - 1;
+        assert_eq!(
+            otp_custom_encoded::<Sha1>(TEST_KEY, counter, 6, CounterEncoding::MinimalBe)?,
+            otp_custom::<Sha1>(TEST_KEY, counter, 6)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn counter_truncated() -> Result<(), ThotpError> {
+        assert_eq!(CounterEncoding::Truncated4Byte.encode(1), [0, 0, 0, 1]);
+        assert_eq!(
+            CounterEncoding::Truncated4Byte.encode(0x1_0000_0002),
+            [0, 0, 0, 2]
+        );
+
+        // RFC 6238 appendix B with SHA1
+        let pairs = [
             ("94287082", 59),
             ("07081804", 1111111109),
             ("14050471", 1111111111),
             ("89005924", 1234567890),
             ("69279037", 2000000000),
-            ("65353130", 20000000000),
         ];
+        for (expected, timestamp) in pairs {
+            let rfc = CounterEncoding::Rfc8Byte;
+            let legacy = CounterEncoding::Truncated4Byte;
+            let nonce = timestamp / 30;
 
-        pairs.into_iter().for_each(|(expected, timestamp)| {
             assert_eq!(
-                expected,
-                otp_custom::<Sha1>(secret, timestamp / TIME_STEP as u64, 8).unwrap()
+                otp_custom_encoded::<Sha1>(TEST_KEY, nonce, 8, rfc)?,
+                expected
             );
             assert_eq!(
-                (true, 0),
-                verify_totp_custom::<Sha1>(expected, secret, timestamp, 8, TIME_STEP, 1).unwrap()
+                verify_totp_encoded::<Sha1>(expected, TEST_KEY, timestamp, 8, 30, 0, rfc)?,
+                (true, 0)
             );
-        });
-    }
-    #[test]
-    fn totp_sha256() {
-        let secret: &[u8] = b"12345678901234567890123456789012";
-        assert_eq!(32, secret.len());
-
-        let pairs = vec![
-            ("46119246", 59),
-            ("68084774", 1111111109),
-            ("67062674", 1111111111),
-            ("91819424", 1234567890),
-            ("90698825", 2000000000),
-            ("77737706", 20000000000),
-        ];
 
-        pairs.into_iter().for_each(|(expected, timestamp)| {
+            let truncated = otp_custom_encoded::<Sha1>(TEST_KEY, nonce, 8, legacy)?;
+            assert_ne!(truncated, expected);
             assert_eq!(
-                expected,
-                otp_custom::<Sha256>(secret, timestamp / TIME_STEP as u64, 8).unwrap()
+                verify_totp_encoded::<Sha1>(expected, TEST_KEY, timestamp, 8, 30, 0, legacy)?,
+                (false, 0)
+            );
+            assert_eq!(
+                verify_totp_encoded::<Sha1>(&truncated, TEST_KEY, timestamp, 8, 30, 0, legacy)?,
+                (true, 0)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn counter_endian() -> Result<(), ThotpError> {
+        assert_eq!(CounterEndian::default(), CounterEndian::Big);
+        assert_eq!(CounterEndian::Little.to_bytes(1), [1, 0, 0, 0, 0, 0, 0, 0]);
+
+        // The big endian path still matches RFC 4226
+        let expected = ["755224", "287082", "359152", "969429", "338314"];
+        for (counter, password) in expected.iter().enumerate() {
+            let counter = counter as u64;
+            assert_eq!(
+                otp_custom_endian::<Sha1>(TEST_KEY, counter, 6, CounterEndian::Big)?,
+                *password
+            );
+            assert_eq!(
+                verify_hotp_endian::<Sha1>(password, TEST_KEY, counter, 0, 6, CounterEndian::Big)?,
+                (true, Counter::new(counter + 1))
+            );
+        }
+
+        // Little endian encodes nonzero counters differently
+        let little = otp_custom_endian::<Sha1>(TEST_KEY, 1, 6, CounterEndian::Little)?;
+        assert_ne!(little, "287082");
+        assert_eq!(
+            verify_hotp_endian::<Sha1>(&little, TEST_KEY, 0, 2, 6, CounterEndian::Little)?,
+            (true, Counter::new(2))
+        );
+        assert!(!verify_hotp_custom::<Sha1>(&little, TEST_KEY, 0, 2, 6)?.0);
+        Ok(())
+    }
+
+    #[test]
+    fn hmac_key() -> Result<(), ThotpError> {
+        let key = HmacKey::<Sha1>::new(TEST_KEY)?;
+        for nonce in [0, 1, 37037036, u64::MAX] {
+            assert_eq!(key.code(nonce, 6)?, otp_custom::<Sha1>(TEST_KEY, nonce, 6)?);
+        }
+
+        let secret: &[u8] = b"12345678901234567890123456789012";
+        let key = HmacKey::<Sha256>::new(secret)?;
+        assert_eq!(key.code(1111111109 / 30, 8)?, "68084774");
+
+        let secret: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+        let key = HmacKey::<Sha512>::new(secret)?;
+        assert_eq!(key.code(1111111109 / 30, 8)?, "25091201");
+        Ok(())
+    }
+
+    #[test]
+    fn otp_into_buffer() -> Result<(), ThotpError> {
+        let mut buffer = String::with_capacity(8);
+        let capacity = buffer.capacity();
+
+        for nonce in 0..100 {
+            otp_custom_into::<Sha1>(TEST_KEY, nonce, 8, &mut buffer)?;
+            assert_eq!(buffer, otp_custom::<Sha1>(TEST_KEY, nonce, 8)?);
+        }
+        // The buffer was never reallocated
+        assert_eq!(buffer.capacity(), capacity);
+
+        otp_custom_into::<Sha1>(TEST_KEY, 1, 6, &mut buffer)?;
+        assert_eq!(buffer, "287082");
+        Ok(())
+    }
+
+    #[test]
+    fn otp_ascii_digits() -> Result<(), ThotpError> {
+        for nonce in 0..100 {
+            for digits in [6, 8] {
+                let bytes: Vec<u8> = otp_custom_digits::<Sha1>(TEST_KEY, nonce, digits)?.collect();
+                assert_eq!(
+                    bytes,
+                    otp_custom::<Sha1>(TEST_KEY, nonce, digits)?.as_bytes()
+                );
+            }
+        }
+
+        // Leading 0s are included
+        let bytes: Vec<u8> = otp_custom_digits::<Sha1>(TEST_KEY, 1111111109 / 30, 8)?.collect();
+        assert_eq!(bytes, b"07081804");
+
+        // More digits than the truncated HMAC has are padded
+        // The truncated HMAC of counter 1 from RFC 4226 is 1094287082
+        let bytes: Vec<u8> = otp_custom_digits::<Sha1>(TEST_KEY, 1, 12)?.collect();
+        assert_eq!(bytes, b"001094287082");
+        Ok(())
+    }
+
+    #[test]
+    fn otp_written_to_buffer() -> Result<(), ThotpError> {
+        let mut buffer = [b'x'; 10];
+        for nonce in 0..100 {
+            for digits in [6, 8, 10] {
+                let password = otp_custom_write::<Sha1>(TEST_KEY, nonce, digits, &mut buffer)?;
+                assert_eq!(password, otp_custom::<Sha1>(TEST_KEY, nonce, digits)?);
+            }
+        }
+
+        // Only the start of the buffer is written to
+        let mut buffer = [b'x'; 10];
+        assert_eq!(
+            otp_custom_write::<Sha1>(TEST_KEY, 1111111109 / 30, 8, &mut buffer)?,
+            "07081804"
+        );
+        assert_eq!(&buffer, b"07081804xx");
+
+        assert!(matches!(
+            otp_custom_write::<Sha1>(TEST_KEY, 1, 8, &mut [0; 7]),
+            Err(ThotpError::BufferTooSmall(8))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn totp_digit_sum_check() -> Result<(), ThotpError> {
+        // RFC 6238 appendix B with SHA1, 0 + 7 + 0 + 8 + 1 + 8 + 0 + 4 = 28
+        let password = otp_with_digit_sum_check::<Sha1>(TEST_KEY, 1111111109 / 30, 8)?;
+        assert_eq!(password, "070818048");
+
+        let verify = |password: &str| {
+            verify_totp_digit_sum_check::<Sha1>(password, TEST_KEY, 1111111109, 8, 30, 1)
+        };
+        assert_eq!(verify("070818048")?, (true, 0));
+        assert_eq!(verify("140504712")?, (true, 1));
+
+        // A corrupted digit is caught before the password is compared
+        assert!(matches!(
+            verify("070818049"),
+            Err(ThotpError::ChecksumMismatch)
+        ));
+        assert!(matches!(
+            verify("170818048"),
+            Err(ThotpError::ChecksumMismatch)
+        ));
+
+        // Swapping two digits keeps the sum, so only the comparison rejects it
+        assert_eq!(verify("700818048")?, (false, 0));
+
+        // A matching check digit says nothing about the password
+        assert_eq!(verify("000000000")?, (false, 0));
+
+        assert_eq!(verify("07081804")?, (false, 0));
+        assert_eq!(verify("07081804a")?, (false, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn code_kinds() -> Result<(), ThotpError> {
+        for nonce in 0..50 {
+            let decimal = otp_custom::<Sha1>(TEST_KEY, nonce, 6)?;
+            assert_eq!(classify_code(&decimal), CodeKind::Decimal);
+            let steam = otp_custom_formatted::<Sha1>(TEST_KEY, nonce, 5, &SteamFormatter)?;
+            assert_eq!(classify_code(&steam), CodeKind::SteamAlphabet);
+        }
+        assert_eq!(classify_code("07081804"), CodeKind::Decimal);
+
+        // 5 digits of the Steam alphabet are ambiguous and classified as Steam passwords
+        assert_eq!(classify_code("23456"), CodeKind::SteamAlphabet);
+        assert_eq!(classify_code("10234"), CodeKind::Unknown);
+
+        for code in [
+            "",
+            "12345",
+            "123456789",
+            "2kc9w",
+            "2KC9A",
+            "2KC9WB",
+            "12 456",
+            "١٢٣٤٥٦",
+        ] {
+            assert_eq!(classify_code(code), CodeKind::Unknown, "{:?}", code);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn otp_formatters() -> Result<(), ThotpError> {
+        for nonce in 0..100 {
+            assert_eq!(
+                otp_custom_formatted::<Sha1>(TEST_KEY, nonce, 8, &DecimalFormatter)?,
+                otp_custom::<Sha1>(TEST_KEY, nonce, 8)?
+            );
+        }
+
+        // The truncated HMAC of counter 1 from RFC 4226 is 1094287082
+        let binary = AlphabetFormatter::new("01")?;
+        assert_eq!(
+            otp_custom_formatted::<Sha1>(TEST_KEY, 1, 8, &binary)?,
+            "01010111"
+        );
+        let letters = AlphabetFormatter::new("ABCDEFGHIJ")?;
+        assert_eq!(
+            otp_custom_formatted::<Sha1>(TEST_KEY, 1, 6, &letters)?,
+            "CIAHIC"
+        );
+        assert_eq!(
+            otp_custom_formatted::<Sha1>(TEST_KEY, 1, 5, &SteamFormatter)?,
+            "PV9M4"
+        );
+
+        assert!(matches!(
+            AlphabetFormatter::new("0"),
+            Err(ThotpError::InvalidAlphabet)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn otp_dynamic_dispatch() -> Result<(), ThotpError> {
+        let secrets = [
+            TEST_KEY.to_vec(),
+            b"12345678901234567890123456789012".to_vec(),
+            b"1234567890123456789012345678901234567890123456789012345678901234".to_vec(),
+            // Longer than any of the block sizes
+            generate_secret(200),
+        ];
+
+        for secret in secrets.iter() {
+            for nonce in [0, 1, 37037036, u64::MAX] {
+                assert_eq!(
+                    otp_dyn(Algorithm::Sha1, secret, nonce, 8)?,
+                    otp_custom::<Sha1>(secret, nonce, 8)?
+                );
+                assert_eq!(
+                    otp_dyn(Algorithm::Sha256, secret, nonce, 8)?,
+                    otp_custom::<Sha256>(secret, nonce, 8)?
+                );
+                assert_eq!(
+                    otp_dyn(Algorithm::Sha512, secret, nonce, 8)?,
+                    otp_custom::<Sha512>(secret, nonce, 8)?
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn supported_algorithms() {
+        assert_eq!(
+            Algorithm::all(),
+            [Algorithm::Sha1, Algorithm::Sha256, Algorithm::Sha512]
+        );
+        assert!(Algorithm::all().contains(&Algorithm::default()));
+        assert_eq!(Algorithm::Sha256.label(), "SHA-256");
+    }
+
+    #[test]
+    fn algorithm_lengths() -> Result<(), ThotpError> {
+        let lengths: Vec<(usize, usize)> = Algorithm::all()
+            .iter()
+            .map(|algorithm| (algorithm.output_len(), algorithm.block_len()))
+            .collect();
+        assert_eq!(lengths, [(20, 64), (32, 64), (64, 128)]);
+
+        // The output lengths are those of the computed HMACs
+        assert_eq!(otp_core::hmac_digest::<Sha1>(TEST_KEY, b"1")?.len(), 20);
+        assert_eq!(otp_core::hmac_digest::<Sha256>(TEST_KEY, b"1")?.len(), 32);
+        assert_eq!(otp_core::hmac_digest::<Sha512>(TEST_KEY, b"1")?.len(), 64);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_token() -> Result<(), ThotpError> {
+        for timestamp in [1111111109, 1111111110, 1234567890] {
+            let token = current_totp::<Sha1>(TEST_KEY, timestamp, 30, 8)?;
+            assert_eq!(token.valid_until - token.valid_from, 30);
+            assert!((token.valid_from..token.valid_until).contains(&timestamp));
+            assert_eq!(token.counter, timestamp / 30);
+            assert_eq!(token.code, otp_custom::<Sha1>(TEST_KEY, token.counter, 8)?);
+        }
+
+        let token = current_totp::<Sha1>(TEST_KEY, 1111111109, 30, 8)?;
+        assert_eq!(token.code, "07081804");
+
+        let token = current_totp::<Sha1>(TEST_KEY, 0, 30, 6)?;
+        assert_eq!(token.valid_until - token.valid_from, 30);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_code_time_range() -> Result<(), ThotpError> {
+        let from = 1111111109 - 1800;
+        let to = 1111111109 + 1800;
+
+        let windows = code_time_range::<Sha1>("07081804", TEST_KEY, from, to, 30, 8)?;
+        assert_eq!(windows, [(1111111080, 1111111110)]);
+
+        // A 6 digit password, every window in the range must generate it
+        let password = otp_custom::<Sha1>(TEST_KEY, 1111111109 / 30, 6)?;
+        let windows = code_time_range::<Sha1>(&password, TEST_KEY, from, to, 30, 6)?;
+        assert!(windows.contains(&(1111111080, 1111111110)));
+        for (valid_from, valid_until) in windows {
+            assert_eq!(valid_until - valid_from, 30);
+            assert_eq!(otp_custom::<Sha1>(TEST_KEY, valid_from / 30, 6)?, password);
+        }
+
+        assert!(code_time_range::<Sha1>("07081804", TEST_KEY, to, from, 30, 8)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn totp_closest_collision() -> Result<(), ThotpError> {
+        // With a single digit, find a slice whose password collides with one two slices earlier but
+        // with none closer to the current slice
+        let code = |n: u64| otp_custom::<Sha1>(TEST_KEY, n, 1);
+        let mut nonce = 1111111109 / 30;
+        while code(nonce - 2)? != code(nonce + 1)?
+            || code(nonce + 1)? == code(nonce)?
+            || code(nonce + 1)? == code(nonce - 1)?
+        {
+            nonce += 1;
+        }
+
+        let password = code(nonce + 1)?;
+        let timestamp = nonce * 30;
+        assert_eq!(
+            verify_totp_custom::<Sha1>(&password, TEST_KEY, timestamp, 1, 30, 2)?,
+            (true, 1)
+        );
+        assert_eq!(
+            verify_totp_period::<Sha1>(&password, TEST_KEY, timestamp, 1, 30, 2)?,
+            (true, 1)
+        );
+        assert_eq!(
+            verify_totp_slice::<Sha1>(&password, TEST_KEY, timestamp, 1, 30, 2)?,
+            Some(nonce + 1)
+        );
+        assert_eq!(
+            verify_totp_tolerance::<Sha1>(&password, TEST_KEY, timestamp, 1, 30, 2, 0)?,
+            (true, 1)
+        );
+
+        // An empty challenge results in the regular passwords
+        assert_eq!(
+            verify_totp_challenge::<Sha1>(&password, TEST_KEY, timestamp, b"", 30, 1, 2)?,
+            (true, 1)
+        );
+
+        // The same for the domain separated passwords, which collide in other slices
+        let code = |n: u64| otp_domain::<Sha1>(TEST_KEY, n, 1, b"billing");
+        let mut nonce = 1111111109 / 30;
+        while code(nonce - 2)? != code(nonce + 1)?
+            || code(nonce + 1)? == code(nonce)?
+            || code(nonce + 1)? == code(nonce - 1)?
+        {
+            nonce += 1;
+        }
+
+        let password = code(nonce + 1)?;
+        assert_eq!(
+            verify_totp_domain::<Sha1>(&password, TEST_KEY, nonce * 30, 1, 30, 2, b"billing")?,
+            (true, 1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_transposed_digits() -> Result<(), ThotpError> {
+        // The password of the previous slice is 07081804
+        let timestamp = 1111111109 + 30;
+        for transposed in ["70081804", "00781804", "07018804", "07081840"] {
+            assert_eq!(
+                verify_totp_transposition_tolerant::<Sha1>(
+                    transposed, TEST_KEY, timestamp, 8, 30, 1
+                )?,
+                (true, -1)
+            );
+            assert!(!verify_totp_custom::<Sha1>(transposed, TEST_KEY, timestamp, 8, 30, 1)?.0);
+            assert!(!verify_totp_strict::<Sha1>(
+                transposed, TEST_KEY, timestamp, 8, 30
+            )?);
+        }
+        assert_eq!(
+            verify_totp_transposition_tolerant::<Sha1>("07081804", TEST_KEY, timestamp, 8, 30, 1)?,
+            (true, -1)
+        );
+
+        // Two transpositions, swaps of digits further apart and other lengths are rejected
+        for rejected in ["70018804", "47081800", "7081804", "070818044"] {
+            assert!(
+                !verify_totp_transposition_tolerant::<Sha1>(
+                    rejected, TEST_KEY, timestamp, 8, 30, 1
+                )?
+                .0
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn totp_t9_lenient() -> Result<(), ThotpError> {
+        let timestamp = 1111111109;
+        let verify = |password: &str| {
+            verify_totp_t9_lenient::<Sha1>(password, TEST_KEY, timestamp, 8, 30, 1)
+        };
+
+        // 07081804, where 7 is P to S and 8 is T to V
+        assert_eq!(verify("0P0T1T04")?, (true, 0));
+        assert_eq!(verify("0s0v1u04")?, (true, 0));
+        assert_eq!(verify("07081T04")?, (true, 0));
+
+        // Pure digit passwords are verified as they are
+        for password in ["07081804", "14050471", "07081805", "0708180"] {
+            assert_eq!(
+                verify(password)?,
+                verify_totp_custom::<Sha1>(password, TEST_KEY, timestamp, 8, 30, 1)?
+            );
+        }
+
+        // 0 and 1 have no letters, and letters of the wrong key do not match
+        assert_eq!(verify("0A0T1T04")?, (false, 0));
+        assert_eq!(verify("0P0T1T0-")?, (false, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn totp_diagnostics() -> Result<(), ThotpError> {
+        let timestamp = 1111111109;
+        let diagnose = |password: &str, diagnostic_drift: u8| {
+            verify_totp_diagnostic::<Sha1>(
+                password,
+                TEST_KEY,
+                timestamp,
+                8,
+                30,
+                1,
+                diagnostic_drift,
+            )
+        };
+
+        assert_eq!(
+            diagnose("07081804", 10)?,
+            VerifyDiagnostic::Accepted { drift: 0 }
+        );
+        assert_eq!(
+            diagnose("14050471", 10)?,
+            VerifyDiagnostic::Accepted { drift: 1 }
+        );
+        assert_eq!(diagnose("0708180", 10)?, VerifyDiagnostic::WrongLength);
+        assert_eq!(diagnose("0708-804", 10)?, VerifyDiagnostic::NonDigit);
+
+        let late = otp_custom::<Sha1>(TEST_KEY, timestamp / 30 + 5, 8)?;
+        assert_eq!(
+            diagnose(&late, 10)?,
+            VerifyDiagnostic::OutsideWindow { drift: 5 }
+        );
+        // Without the diagnostic scan it can not be told apart from a wrong password
+        assert_eq!(diagnose(&late, 1)?, VerifyDiagnostic::Incorrect);
+        assert_eq!(diagnose(&late, 4)?, VerifyDiagnostic::Incorrect);
+        assert_eq!(diagnose("00000000", 10)?, VerifyDiagnostic::Incorrect);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_strict() -> Result<(), ThotpError> {
+        let nonce = 1111111109 / 30;
+        for n in [nonce - 1, nonce, nonce + 1] {
+            let password = otp_custom::<Sha1>(TEST_KEY, n, 8)?;
+            assert_eq!(
+                verify_totp_strict::<Sha1>(&password, TEST_KEY, 1111111109, 8, 30)?,
+                n == nonce
+            );
+            assert_eq!(
+                verify_totp_custom::<Sha1>(&password, TEST_KEY, 1111111109, 8, 30, 0)?,
+                (n == nonce, 0)
+            );
+        }
+
+        // The window is a single slice at the edges of the u64 range as well
+        let password = otp_custom::<Sha1>(TEST_KEY, 0, 8)?;
+        assert!(verify_totp_strict::<Sha1>(&password, TEST_KEY, 1, 8, 30)?);
+        assert!(!verify_totp_strict::<Sha1>(&password, TEST_KEY, 30, 8, 30)?);
+        let password = otp_custom::<Sha1>(TEST_KEY, u64::MAX, 8)?;
+        assert!(verify_totp_strict::<Sha1>(
+            &password,
+            TEST_KEY,
+            u64::MAX,
+            8,
+            1
+        )?);
+        assert!(!verify_totp_strict::<Sha1>(
+            &password,
+            TEST_KEY,
+            u64::MAX - 1,
+            8,
+            1
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn discrepancy_sign() -> Result<(), ThotpError> {
+        let nonce = 1111111109 / 30;
+
+        // A password from a past slice, the client's clock is behind
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce - 2, 8)?;
+        let (valid, discrepancy) =
+            verify_totp_custom::<Sha1>(&password, TEST_KEY, 1111111109, 8, 30, 2)?;
+        let discrepancy = Discrepancy::from(discrepancy);
+        assert!(valid);
+        assert_eq!(discrepancy, Discrepancy(-2));
+        assert!(discrepancy.is_behind() && !discrepancy.is_ahead());
+        assert_eq!(discrepancy.seconds(30), -60);
+
+        // A password from a future slice, the client's clock is ahead
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce + 1, 8)?;
+        let (valid, discrepancy) =
+            verify_totp_custom::<Sha1>(&password, TEST_KEY, 1111111109, 8, 30, 2)?;
+        let discrepancy = Discrepancy::from(discrepancy);
+        assert!(valid);
+        assert_eq!(i16::from(discrepancy), 1);
+        assert!(discrepancy.is_ahead() && !discrepancy.is_behind());
+        assert_eq!(discrepancy.seconds(30), 30);
+
+        // The default verify function and the Totp builder follow the same convention
+        assert_eq!(
+            verify_totp(&otp(TEST_KEY, nonce + 1)?, TEST_KEY, 1111111109)?,
+            (true, 1)
+        );
+        let totp = totp::Totp::new(TEST_KEY.to_vec()).with_digits(8);
+        assert_eq!(totp.verify(&password, 1111111109)?.discrepancy, 1);
+
+        let current = Discrepancy::default();
+        assert!(!current.is_ahead() && !current.is_behind());
+        assert_eq!(current.seconds(30), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_risk_score() -> Result<(), ThotpError> {
+        let timestamp = 1111111109;
+        let nonce = timestamp / 30;
+        let score = |n: u64, weight: fn(u16, u8) -> f64| {
+            let password = otp_custom::<Sha1>(TEST_KEY, n, 8)?;
+            verify_totp_risk::<Sha1>(&password, TEST_KEY, timestamp, 8, 30, 2, weight)
+        };
+
+        let current = score(nonce, RiskScore::linear)?.unwrap();
+        let adjacent = score(nonce + 1, RiskScore::linear)?.unwrap();
+        let edge = score(nonce - 2, RiskScore::linear)?.unwrap();
+
+        assert_eq!(current.value(), 0.0);
+        assert_eq!(adjacent.value(), 0.5);
+        assert_eq!(edge.value(), 1.0);
+        assert!(current < adjacent && adjacent < edge);
+        assert_eq!(score(nonce + 2, RiskScore::linear)?, Some(edge));
+
+        // Outside the window there is no score
+        assert_eq!(score(nonce + 3, RiskScore::linear)?, None);
+
+        // Custom weightings are clamped
+        let squared = |discrepancy: u16, _| (discrepancy * discrepancy) as f64 / 2.0;
+        assert_eq!(score(nonce - 1, squared)?, Some(RiskScore::new(0.5)));
+        assert_eq!(score(nonce - 2, squared)?.unwrap().value(), 1.0);
+        assert_eq!(score(nonce, |_, _| -1.0)?.unwrap().value(), 0.0);
+        assert_eq!(score(nonce, |_, _| f64::NAN)?.unwrap().value(), 1.0);
+
+        assert_eq!(RiskScore::linear(0, 0), 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_previous_grace() -> Result<(), ThotpError> {
+        let nonce = 1111111109 / 30;
+        let expected = [
+            (nonce - 2, (false, 0)),
+            (nonce - 1, (true, -1)),
+            (nonce, (true, 0)),
+        ];
+        for (n, result) in expected {
+            let password = otp_custom::<Sha1>(TEST_KEY, n, 8)?;
+            assert_eq!(
+                verify_totp_prev_grace::<Sha1>(&password, TEST_KEY, 1111111109, 8, 30)?,
+                result
+            );
+        }
+
+        // The next slice is accepted with a drift of 1, but never with the grace
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce + 1, 8)?;
+        assert!(verify_totp_custom::<Sha1>(&password, TEST_KEY, 1111111109, 8, 30, 1)?.0);
+        assert_eq!(
+            verify_totp_prev_grace::<Sha1>(&password, TEST_KEY, 1111111109, 8, 30)?,
+            (false, 0)
+        );
+
+        // There is no previous slice for the first one
+        let password = otp_custom::<Sha1>(TEST_KEY, 0, 8)?;
+        assert_eq!(
+            verify_totp_prev_grace::<Sha1>(&password, TEST_KEY, 1, 8, 30)?,
+            (true, 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_instrumented() -> Result<(), ThotpError> {
+        let nonce = 1111111109 / 30;
+        let verify = |password: &str, drift| {
+            verify_totp_instrumented::<Sha1>(password, TEST_KEY, 1111111109, 8, 30, drift)
+        };
+
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce, 8)?;
+        assert_eq!(
+            verify(&password, 3)?,
+            (VerifyOutcome::Accepted { drift: 0 }, 1)
+        );
+
+        // Center out, the previous slice is checked before the next one
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce - 1, 8)?;
+        assert_eq!(
+            verify(&password, 3)?,
+            (VerifyOutcome::Accepted { drift: -1 }, 2)
+        );
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce + 2, 8)?;
+        assert_eq!(
+            verify(&password, 3)?,
+            (VerifyOutcome::Accepted { drift: 2 }, 5)
+        );
+
+        // A miss checks the whole window
+        for drift in [0, 1, 3] {
+            assert_eq!(
+                verify("00000000", drift)?,
+                (VerifyOutcome::Rejected, 2 * drift as usize + 1)
+            );
+        }
+        assert_eq!(verify("0000000", 1)?, (VerifyOutcome::Malformed, 0));
+
+        // Slices outside of the u64 range are not computed
+        let (outcome, hmacs) = verify_totp_instrumented::<Sha1>("00000000", TEST_KEY, 1, 8, 30, 2)?;
+        assert_eq!((outcome, hmacs), (VerifyOutcome::Rejected, 3));
+        Ok(())
+    }
+
+    #[test]
+    fn totp_batch() -> Result<(), ThotpError> {
+        let timestamp = 1111111109;
+        let secrets: Vec<_> = (0..100u8).map(|i| Secret::new([i; 20])).collect();
+
+        let passwords: Vec<_> = secrets
+            .iter()
+            .enumerate()
+            .map(|(i, secret)| match i % 4 {
+                // Valid in the current and the next time slice
+                0 => otp_custom::<Sha1>(secret.as_bytes(), timestamp / 30, 6),
+                1 => otp_custom::<Sha1>(secret.as_bytes(), timestamp / 30 + 1, 6),
+                // The password of another secret
+                2 => otp_custom::<Sha1>(TEST_KEY, timestamp / 30, 6),
+                _ => Ok("12a456".to_string()),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let items: Vec<_> = secrets
+            .into_iter()
+            .zip(passwords.iter().map(String::as_str))
+            .collect();
+
+        let outcomes = verify_totp_batch::<Sha1>(&items, timestamp, 6, 30, 1);
+        assert_eq!(outcomes.len(), items.len());
+
+        for (i, ((secret, password), outcome)) in items.iter().zip(outcomes).enumerate() {
+            let expected = match i % 4 {
+                0 => VerifyOutcome::Accepted { drift: 0 },
+                1 => VerifyOutcome::Accepted { drift: 1 },
+                2 => match verify_totp_custom::<Sha1>(
+                    password,
+                    secret.as_bytes(),
+                    timestamp,
+                    6,
+                    30,
+                    1,
+                )? {
+                    (true, drift) => VerifyOutcome::Accepted { drift },
+                    (false, _) => VerifyOutcome::Rejected,
+                },
+                _ => VerifyOutcome::Malformed,
+            };
+            assert_eq!(outcome?, expected);
+        }
+
+        assert!(verify_totp_batch::<Sha1>(&[], timestamp, 6, 30, 1).is_empty());
+        let single = [(Secret::new(TEST_KEY.to_vec()), "287082")];
+        assert_eq!(
+            verify_totp_batch::<Sha1>(&single, 89, 6, 30, 1)[0]
+                .as_ref()
+                .ok(),
+            Some(&VerifyOutcome::Accepted { drift: -1 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_transformed_secret() -> Result<(), ThotpError> {
+        // 16 Base32 characters of the secret, i.e. its first 10 bytes
+        let truncate = |secret: &[u8]| secret[..10].to_vec();
+        let password = otp_custom::<Sha1>(&TEST_KEY[..10], 1111111109 / 30, 6)?;
+
+        assert_eq!(
+            verify_totp_transformed::<Sha1>(&password, TEST_KEY, 1111111109, 6, 30, 1, truncate)?,
+            (true, 0)
+        );
+        assert_eq!(
+            verify_totp_custom::<Sha1>(&password, TEST_KEY, 1111111109, 6, 30, 1)?,
+            (false, 0)
+        );
+
+        // A different transform or none at all does not match
+        let wrong = |secret: &[u8]| secret[..16].to_vec();
+        let identity = |secret: &[u8]| secret.to_vec();
+        for transform in [&wrong as &dyn Fn(&[u8]) -> Vec<u8>, &identity] {
+            assert_eq!(
+                verify_totp_transformed::<Sha1>(
+                    &password, TEST_KEY, 1111111109, 6, 30, 1, transform
+                )?,
+                (false, 0)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn totp_async_secret() -> Result<(), ThotpError> {
+        use std::future::{poll_fn, Future};
+        use std::pin::pin;
+        use std::task::{Context, Poll, Waker};
+
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let mut future = pin!(future);
+            let mut cx = Context::from_waker(Waker::noop());
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            }
+        }
+
+        // A fetch that is pending once before returning the secret, like a remote call would
+        let fetch = |secret: Result<Vec<u8>, ThotpError>| {
+            let mut secret = Some(secret);
+            let mut pending = true;
+            poll_fn(move |_| {
+                if std::mem::take(&mut pending) {
+                    return Poll::Pending;
+                }
+                Poll::Ready(secret.take().unwrap())
+            })
+        };
+
+        let verify = |password, secret| {
+            block_on(verify_totp_async_secret::<Sha1>(
+                fetch(secret),
+                password,
+                1111111109,
+                8,
+                30,
+                1,
+            ))
+        };
+
+        assert_eq!(verify("07081804", Ok(TEST_KEY.to_vec()))?, (true, 0));
+        assert_eq!(verify("14050471", Ok(TEST_KEY.to_vec()))?, (true, 1));
+        assert_eq!(verify("07081804", Ok(b"wrong".to_vec()))?, (false, 0));
+        assert!(matches!(
+            verify("07081804", Err(ThotpError::InvalidState)),
+            Err(ThotpError::InvalidState)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn totp_clamped_window() -> Result<(), ThotpError> {
+        // A timestamp of 1 with a step of 30 is the first time slice, the window can not go lower
+        for n in 0..=2 {
+            let password = otp_custom::<Sha1>(TEST_KEY, n, 8)?;
+            assert_eq!(
+                verify_totp_custom::<Sha1>(&password, TEST_KEY, 1, 8, 30, 2)?,
+                (true, n as i16)
+            );
+        }
+        let password = otp_custom::<Sha1>(TEST_KEY, 3, 8)?;
+        assert!(!verify_totp_custom::<Sha1>(&password, TEST_KEY, 1, 8, 30, 2)?.0);
+
+        // With a step of 1 the timestamp u64::MAX is the last time slice
+        for n in 0..=2 {
+            let password = otp_custom::<Sha1>(TEST_KEY, u64::MAX - n, 8)?;
+            assert_eq!(
+                verify_totp_custom::<Sha1>(&password, TEST_KEY, u64::MAX, 8, 1, 2)?,
+                (true, -(n as i16))
+            );
+        }
+        let password = otp_custom::<Sha1>(TEST_KEY, u64::MAX - 3, 8)?;
+        assert!(!verify_totp_custom::<Sha1>(&password, TEST_KEY, u64::MAX, 8, 1, 2)?.0);
+
+        // One slice off the edge the window is only partially clamped
+        let password = otp_custom::<Sha1>(TEST_KEY, 0, 8)?;
+        assert_eq!(
+            verify_totp_custom::<Sha1>(&password, TEST_KEY, 30, 8, 30, 3)?,
+            (true, -1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_boundary_tolerance() -> Result<(), ThotpError> {
+        // 1111111109 and 1111111111 are on either side of a boundary
+        let before = "07081804";
+        let after = "14050471";
+
+        assert_eq!(
+            verify_totp_tolerance::<Sha1>(after, TEST_KEY, 1111111109, 8, 30, 0, 2)?,
+            (true, 1)
+        );
+        assert_eq!(
+            verify_totp_tolerance::<Sha1>(before, TEST_KEY, 1111111111, 8, 30, 0, 2)?,
+            (true, -1)
+        );
+        assert_eq!(
+            verify_totp_tolerance::<Sha1>(after, TEST_KEY, 1111111109, 8, 30, 0, 0)?,
+            (false, 0)
+        );
+        // Two seconds before the boundary is still within the tolerance, three are not
+        assert!(verify_totp_tolerance::<Sha1>(after, TEST_KEY, 1111111108, 8, 30, 0, 2)?.0);
+        assert!(!verify_totp_tolerance::<Sha1>(after, TEST_KEY, 1111111107, 8, 30, 0, 2)?.0);
+        assert_eq!(
+            verify_totp_tolerance::<Sha1>(before, TEST_KEY, 1111111109, 8, 30, 0, 2)?,
+            (true, 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_challenge() -> Result<(), ThotpError> {
+        let timestamp = 1111111109;
+        let first = otp_challenge::<Sha1>(TEST_KEY, timestamp, b"first", 30, 8)?;
+        let second = otp_challenge::<Sha1>(TEST_KEY, timestamp, b"second", 30, 8)?;
+        assert_ne!(first, second);
+
+        // Without a challenge it is the regular TOTP
+        assert_eq!(
+            otp_challenge::<Sha1>(TEST_KEY, timestamp, b"", 30, 8)?,
+            "07081804"
+        );
+
+        assert_eq!(
+            verify_totp_challenge::<Sha1>(&first, TEST_KEY, timestamp, b"first", 30, 8, 1)?,
+            (true, 0)
+        );
+        assert_eq!(
+            verify_totp_challenge::<Sha1>(&first, TEST_KEY, timestamp + 30, b"first", 30, 8, 1)?,
+            (true, -1)
+        );
+        assert_eq!(
+            verify_totp_challenge::<Sha1>(&first, TEST_KEY, timestamp, b"second", 30, 8, 1)?,
+            (false, 0)
+        );
+        assert_eq!(
+            verify_totp_challenge::<Sha1>(&first, TEST_KEY, timestamp, b"", 30, 8, 1)?,
+            (false, 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn derived_secrets() -> Result<(), ThotpError> {
+        let secret = derive_secret(TEST_KEY, "mail.example.com", 20)?;
+        assert_eq!(secret.len(), 20);
+        assert_eq!(secret, derive_secret(TEST_KEY, "mail.example.com", 20)?);
+
+        // Different labels and master keys derive different secrets
+        assert_ne!(secret, derive_secret(TEST_KEY, "vpn.example.com", 20)?);
+        assert_ne!(secret, derive_secret(TEST_KEY, "mail.example.co", 20)?);
+        assert_ne!(
+            secret,
+            derive_secret(b"12345678901234567891", "mail.example.com", 20)?
+        );
+
+        // Longer secrets start with the shorter ones
+        let long = derive_secret(TEST_KEY, "mail.example.com", 64)?;
+        assert_eq!(long[..20], secret);
+        assert!(matches!(
+            derive_secret(TEST_KEY, "mail.example.com", 10_000),
+            Err(ThotpError::InvalidSecretLength { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn totp_domain_separation() -> Result<(), ThotpError> {
+        let nonce = 1111111109 / 30;
+        let billing = otp_domain::<Sha1>(TEST_KEY, nonce, 8, b"billing")?;
+        let support = otp_domain::<Sha1>(TEST_KEY, nonce, 8, b"support")?;
+        assert_ne!(billing, support);
+        assert_ne!(billing, otp_custom::<Sha1>(TEST_KEY, nonce, 8)?);
+        assert_ne!(
+            otp_domain::<Sha1>(TEST_KEY, nonce, 8, b"")?,
+            otp_custom::<Sha1>(TEST_KEY, nonce, 8)?
+        );
+        assert_eq!(billing, otp_domain::<Sha1>(TEST_KEY, nonce, 8, b"billing")?);
+
+        assert_eq!(
+            verify_totp_domain::<Sha1>(&billing, TEST_KEY, 1111111109, 8, 30, 1, b"billing")?,
+            (true, 0)
+        );
+        assert_eq!(
+            verify_totp_domain::<Sha1>(&billing, TEST_KEY, 1111111109, 8, 30, 1, b"support")?,
+            (false, 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn response_signature() -> Result<(), ThotpError> {
+        let payload = b"john verified at 1111111109";
+        let tag = sign_response::<Sha256>(TEST_KEY, payload)?;
+        assert_eq!(tag.len(), 32);
+        assert_eq!(sign_response::<Sha512>(TEST_KEY, payload)?.len(), 64);
+        assert!(verify_response::<Sha256>(TEST_KEY, payload, &tag)?);
+
+        // A tampered payload, tag or another secret is rejected
+        assert!(!verify_response::<Sha256>(
+            TEST_KEY,
+            b"jane verified at 1111111109",
+            &tag
+        )?);
+        let mut tampered = tag.clone();
+        tampered[0] ^= 1;
+        assert!(!verify_response::<Sha256>(TEST_KEY, payload, &tampered)?);
+        assert!(!verify_response::<Sha256>(TEST_KEY, payload, &tag[..31])?);
+        assert!(!verify_response::<Sha256>(
+            b"09876543210987654321",
+            payload,
+            &tag
+        )?);
+        assert!(!verify_response::<Sha1>(TEST_KEY, payload, &tag)?);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_exact_timestamp() -> Result<(), ThotpError> {
+        let epoch = otp_custom::<Sha1>(TEST_KEY, 0, 8)?;
+        assert_eq!(epoch, "84755224");
+        assert_eq!(
+            verify_totp_at_exact::<Sha1>(&epoch, TEST_KEY, 0, 8, 30, 1)?,
+            (true, 0)
+        );
+        assert_eq!(
+            verify_totp_at_exact::<Sha1>(&epoch, TEST_KEY, 29, 8, 30, 0)?,
+            (true, 0)
+        );
+        assert_eq!(
+            verify_totp_at_exact::<Sha1>(&epoch, TEST_KEY, 30, 8, 30, 1)?,
+            (true, -1)
+        );
+        assert_eq!(
+            verify_totp_at_exact::<Sha1>(&epoch, TEST_KEY, 60, 8, 30, 1)?,
+            (false, 0)
+        );
+        assert_eq!(
+            verify_totp_at_exact::<Sha1>("94287082", TEST_KEY, 59, 8, 30, 1)?,
+            (true, 0)
+        );
+
+        // Slices before the epoch do not exist
+        let next = otp_custom::<Sha1>(TEST_KEY, 1, 8)?;
+        assert_eq!(
+            verify_totp_at_exact::<Sha1>(&next, TEST_KEY, 0, 8, 30, 1)?,
+            (true, 1)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_expiry() -> Result<(), ThotpError> {
+        // One second before the boundary at 1111111110
+        assert_eq!(
+            verify_totp_with_expiry::<Sha1>("07081804", TEST_KEY, 1111111109, 8, 30, 1)?,
+            Some(1)
+        );
+        assert_eq!(
+            verify_totp_with_expiry::<Sha1>("07081804", TEST_KEY, 1111111080, 8, 30, 1)?,
+            Some(30)
+        );
+        // The next slice ends one step later
+        assert_eq!(
+            verify_totp_with_expiry::<Sha1>("14050471", TEST_KEY, 1111111109, 8, 30, 1)?,
+            Some(31)
+        );
+        // The previous slice already ended
+        assert_eq!(
+            verify_totp_with_expiry::<Sha1>("07081804", TEST_KEY, 1111111111, 8, 30, 1)?,
+            Some(0)
+        );
+        assert_eq!(
+            verify_totp_with_expiry::<Sha1>("07081805", TEST_KEY, 1111111109, 8, 30, 1)?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "custom")]
+    fn totp_matched_slice() -> Result<(), ThotpError> {
+        for timestamp in [1111111109, 1111111111, 1111111140] {
+            let (valid, discrepancy) =
+                verify_totp_custom::<Sha1>("14050471", TEST_KEY, timestamp, 8, 30, 1)?;
+            assert!(valid);
+
+            let slice = verify_totp_slice::<Sha1>("14050471", TEST_KEY, timestamp, 8, 30, 1)?;
+            assert_eq!(
+                slice,
+                Some(
+                    (timestamp / 30)
+                        .checked_add_signed(discrepancy as i64)
+                        .unwrap()
+                )
+            );
+            assert_eq!(slice, Some(1111111111 / 30));
+        }
+
+        assert_eq!(
+            verify_totp_slice::<Sha1>("14050471", TEST_KEY, 1111111200, 8, 30, 1)?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_listed_slices() -> Result<(), ThotpError> {
+        // 1111111109 / 30 from the RFC 6238 test vectors
+        let slices = [1, 1111111109 / 30 - 5, 1111111109 / 30, 1111111109 / 30 + 7];
+        assert_eq!(
+            verify_totp_slices::<Sha1>("07081804", TEST_KEY, &slices, 8)?,
+            Some(1111111109 / 30)
+        );
+
+        // The neighbouring slices are not implied
+        let slices = [1111111109 / 30 - 1, 1111111109 / 30 + 1];
+        assert_eq!(
+            verify_totp_slices::<Sha1>("07081804", TEST_KEY, &slices, 8)?,
+            None
+        );
+        assert_eq!(
+            verify_totp_slices::<Sha1>("07081804", TEST_KEY, &[], 8)?,
+            None
+        );
+        assert_eq!(
+            verify_totp_slices::<Sha1>("0708180", TEST_KEY, &[1111111109 / 30], 8)?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn accepted_codes() -> Result<(), ThotpError> {
+        for drift in [0, 1, 3] {
+            // Retry if a new time slice started in between
+            let (nonce, codes) = loop {
+                let before = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() / 30;
+                let codes = accepted_codes_now::<Sha1>(TEST_KEY, 30, 6, drift)?;
+                let after = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() / 30;
+                if before == after {
+                    break (before, codes);
+                }
+            };
+
+            assert_eq!(codes.len(), 2 * drift as usize + 1);
+            assert_eq!(
+                codes[drift as usize],
+                (0, otp_custom::<Sha1>(TEST_KEY, nonce, 6)?)
+            );
+
+            for (discrepancy, code) in codes {
+                let n = nonce.checked_add_signed(discrepancy as i64).unwrap();
+                assert_eq!(code, otp_custom::<Sha1>(TEST_KEY, n, 6)?);
+                assert!(discrepancy.unsigned_abs() <= drift as u16);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn totp_code_table() -> Result<(), ThotpError> {
+        // Two minutes starting at the RFC 6238 test vector
+        let table = totp_table::<Sha1>(TEST_KEY, 1111111080, 1111111199, 30, 8)?;
+        assert_eq!(table.len(), 4);
+        assert_eq!(table[&1111111080], "07081804");
+        assert_eq!(table[&1111111110], "14050471");
+        for (timestamp, password) in &table {
+            assert_eq!(timestamp % 30, 0);
+            assert_eq!(*password, otp_custom::<Sha1>(TEST_KEY, timestamp / 30, 8)?);
+        }
+
+        // Both ends are included
+        assert_eq!(
+            totp_table::<Sha1>(TEST_KEY, 1111111080, 1111111200, 30, 8)?.len(),
+            5
+        );
+        assert_eq!(
+            totp_table::<Sha1>(TEST_KEY, 59, 59, 30, 8)?[&30],
+            "94287082"
+        );
+        assert!(totp_table::<Sha1>(TEST_KEY, 120, 0, 30, 8)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn totp_boundary_rounding() -> Result<(), ThotpError> {
+        let boundary = 37037036 * TIME_STEP as u64;
+
+        // Exactly on the boundary both policies assign the timestamp to the starting slice
+        assert_eq!(time_slice(boundary, 30, BoundaryRounding::Floor)?, 37037036);
+        assert_eq!(
+            time_slice(boundary, 30, BoundaryRounding::Nearest)?,
+            37037036
+        );
+
+        assert_eq!(
+            time_slice(boundary + 14, 30, BoundaryRounding::Floor)?,
+            37037036
+        );
+        assert_eq!(
+            time_slice(boundary + 14, 30, BoundaryRounding::Nearest)?,
+            37037036
+        );
+
+        assert_eq!(
+            time_slice(boundary + 15, 30, BoundaryRounding::Floor)?,
+            37037036
+        );
+        assert_eq!(
+            time_slice(boundary + 15, 30, BoundaryRounding::Nearest)?,
+            37037037
+        );
+        for rounding in [BoundaryRounding::Floor, BoundaryRounding::Nearest] {
+            assert!(matches!(
+                time_slice(boundary, 0, rounding),
+                Err(ThotpError::InvalidStep)
+            ));
+        }
+
+        let password = otp_custom::<Sha1>(TEST_KEY, 37037037, 6)?;
+        assert_eq!(
+            (true, 1),
+            verify_totp_rounded::<Sha1>(
+                &password,
+                TEST_KEY,
+                boundary + 29,
+                6,
+                30,
+                1,
+                BoundaryRounding::Floor
+            )?
+        );
+        assert_eq!(
+            (true, 0),
+            verify_totp_rounded::<Sha1>(
+                &password,
+                TEST_KEY,
+                boundary + 29,
+                6,
+                30,
+                1,
+                BoundaryRounding::Nearest
+            )?
+        );
+        assert_eq!(
+            (true, 0),
+            verify_totp_rounded::<Sha1>(
+                &password,
+                TEST_KEY,
+                boundary + 30,
+                6,
+                30,
+                1,
+                BoundaryRounding::Floor
+            )?
+        );
+        Ok(())
+    }
+
+    // The values in the next 3 tests come from the RFC
+    #[test]
+    fn totp_sha1() {
+        let secret: &[u8] = b"12345678901234567890";
+        assert_eq!(20, secret.len());
+
+        let pairs = vec![
+            ("94287082", 59),
+            ("07081804", 1111111109),
+            ("14050471", 1111111111),
+            ("89005924", 1234567890),
+            ("69279037", 2000000000),
+            ("65353130", 20000000000),
+        ];
+
+        pairs.into_iter().for_each(|(expected, timestamp)| {
+            assert_eq!(
+                expected,
+                otp_custom::<Sha1>(secret, timestamp / TIME_STEP as u64, 8).unwrap()
+            );
+            assert_eq!(
+                (true, 0),
+                verify_totp_custom::<Sha1>(expected, secret, timestamp, 8, TIME_STEP, 1).unwrap()
+            );
+        });
+    }
+    #[test]
+    fn totp_sha256() {
+        let secret: &[u8] = b"12345678901234567890123456789012";
+        assert_eq!(32, secret.len());
+
+        let pairs = vec![
+            ("46119246", 59),
+            ("68084774", 1111111109),
+            ("67062674", 1111111111),
+            ("91819424", 1234567890),
+            ("90698825", 2000000000),
+            ("77737706", 20000000000),
+        ];
+
+        pairs.into_iter().for_each(|(expected, timestamp)| {
+            assert_eq!(
+                expected,
+                otp_custom::<Sha256>(secret, timestamp / TIME_STEP as u64, 8).unwrap()
             );
             assert_eq!(
                 (true, 0),
@@ -534,4 +2422,566 @@ mod tests {
             );
         });
     }
+
+    // The RFC seeds are sized to each algorithm's output, reusing the 20 byte SHA1 seed
+    // for SHA256 and SHA512 does not reproduce the published values
+    #[test]
+    fn totp_seed_reuse() {
+        let secret: &[u8] = b"12345678901234567890";
+
+        let pairs = vec![
+            ("46119246", "90693936", 59),
+            ("68084774", "25091201", 1111111109),
+            ("67062674", "99943326", 1111111111),
+            ("91819424", "93441116", 1234567890),
+            ("90698825", "38618901", 2000000000),
+            ("77737706", "47863826", 20000000000),
+        ];
+
+        pairs
+            .into_iter()
+            .for_each(|(expected_256, expected_512, timestamp)| {
+                let nonce = timestamp / TIME_STEP as u64;
+                assert_ne!(
+                    expected_256,
+                    otp_custom::<Sha256>(secret, nonce, 8).unwrap()
+                );
+                assert_ne!(
+                    expected_512,
+                    otp_custom::<Sha512>(secret, nonce, 8).unwrap()
+                );
+            });
+    }
+
+    // The HMAC values and passwords come from RFC 4226 appendix D
+    #[test]
+    fn hotp_from_hmac() -> Result<(), ThotpError> {
+        let pairs = vec![
+            ("cc93cf18508d94934c64b65d8ba7667fb7cde4b0", "755224"),
+            ("75a48a19d4cbe100644e8ac1397eea747a2d33ab", "287082"),
+            ("0bacb7fa082fef30782211938bc1c5e70416ff44", "359152"),
+            ("66c28227d03a2d5529262ff016a1e6ef76557ece", "969429"),
+        ];
+
+        for (counter, (hmac, expected)) in pairs.into_iter().enumerate() {
+            let hmac: Vec<u8> = (0..hmac.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hmac[i..i + 2], 16).unwrap())
+                .collect();
+
+            // The HMAC computed by the crate is equal to the external one
+            assert_eq!(
+                hmac,
+                otp_core::hmac_digest::<Sha1>(TEST_KEY, &(counter as u64).to_be_bytes())?
+                    .as_slice()
+            );
+
+            assert!(verify_from_hmac(&hmac, expected, 6)?);
+            assert!(!verify_from_hmac(&hmac, "000000", 6)?);
+        }
+
+        assert!(verify_from_hmac(&[0; 19], "755224", 6).is_err());
+        assert!(verify_from_hmac(&[0; 20], "755224", 5).is_err());
+        Ok(())
+    }
+
+    /// A minimal RFC 4226 implementation independent of the crate's internals, used as a reference by
+    /// [otp_reference_differential].
+    fn reference_hotp<M: hmac::Mac + hmac::digest::KeyInit>(
+        secret: &[u8],
+        counter: u64,
+        digits: u32,
+    ) -> String {
+        let mut mac = <M as hmac::digest::KeyInit>::new_from_slice(secret).unwrap();
+        mac.update(&counter.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = ((hash[offset] as u64 & 0x7f) << 24)
+            | ((hash[offset + 1] as u64) << 16)
+            | ((hash[offset + 2] as u64) << 8)
+            | hash[offset + 3] as u64;
+
+        format!(
+            "{:0width$}",
+            binary % 10_u64.pow(digits),
+            width = digits as usize
+        )
+    }
+
+    /// Compares the passwords of 500 randomized cases of secrets, counters and digit lengths with
+    /// [reference_hotp]. This is a plain loop, failing cases are not shrunk. The seed is printed on
+    /// failure and the same cases are run again by setting `THOTP_TEST_SEED` to it.
+    #[test]
+    fn otp_reference_differential() -> Result<(), ThotpError> {
+        use hmac::Hmac;
+        use rand::Rng;
+
+        // The reference itself matches RFC 4226
+        assert_eq!(reference_hotp::<Hmac<Sha1>>(TEST_KEY, 0, 6), "755224");
+        assert_eq!(reference_hotp::<Hmac<Sha1>>(TEST_KEY, 9, 6), "520489");
+
+        let seed: u64 = match std::env::var("THOTP_TEST_SEED") {
+            Ok(seed) => seed.parse().expect("THOTP_TEST_SEED has to be a u64"),
+            Err(_) => StdRng::from_entropy().gen(),
+        };
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        for _ in 0..500 {
+            let mut secret = vec![0; rng.gen_range(1..=128)];
+            rng.fill_bytes(&mut secret);
+            let counter = match rng.gen_range(0..4) {
+                0 => rng.gen_range(0..1000),
+                1 => u64::MAX - rng.gen_range(0..1000),
+                _ => rng.gen(),
+            };
+            let digits = rng.gen_range(6..=9);
+
+            let cases = [
+                (
+                    otp_custom::<Sha1>(&secret, counter, digits)?,
+                    reference_hotp::<Hmac<Sha1>>(&secret, counter, digits as u32),
+                ),
+                (
+                    otp_custom::<Sha256>(&secret, counter, digits)?,
+                    reference_hotp::<Hmac<Sha256>>(&secret, counter, digits as u32),
+                ),
+                (
+                    otp_custom::<Sha512>(&secret, counter, digits)?,
+                    reference_hotp::<Hmac<Sha512>>(&secret, counter, digits as u32),
+                ),
+            ];
+
+            for (password, reference) in cases {
+                assert_eq!(
+                    password, reference,
+                    "seed {} counter {} digits {}",
+                    seed, counter, digits
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares passwords of random secrets, timestamps and counters generated with `oathtool`. Run it
+    /// with `cargo test --features oathtool oathtool`, which fails if `oathtool` is not installed.
+    #[test]
+    #[cfg(feature = "oathtool")]
+    fn oathtool_interop() -> Result<(), ThotpError> {
+        use rand::Rng;
+        use std::process::Command;
+
+        // The feature is only enabled to run this comparison, so a missing oathtool is a failure
+        let oathtool = |args: &[String]| -> String {
+            let output = Command::new("oathtool")
+                .args(args)
+                .output()
+                .expect("the oathtool feature requires oathtool to be installed");
+            assert!(output.status.success(), "oathtool failed: {:?}", output);
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..20 {
+            let secret = generate_secret(rng.gen_range(16..=64));
+            let hex: String = secret.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let timestamp = rng.gen_range(0..4_000_000_000_u64);
+            let digits = rng.gen_range(6..=8_u8);
+            let nonce = timestamp / 30;
+
+            for (algorithm, expected) in [
+                ("SHA1", otp_custom::<Sha1>(&secret, nonce, digits)?),
+                ("SHA256", otp_custom::<Sha256>(&secret, nonce, digits)?),
+                ("SHA512", otp_custom::<Sha512>(&secret, nonce, digits)?),
+            ] {
+                let args = [
+                    format!("--totp={}", algorithm),
+                    format!("--digits={}", digits),
+                    format!("--now=@{}", timestamp),
+                    hex.clone(),
+                ];
+                assert_eq!(oathtool(&args), expected, "{:?}", args);
+            }
+
+            let counter = rng.gen::<u32>() as u64;
+            let args = [
+                String::from("--hotp"),
+                format!("--counter={}", counter),
+                format!("--digits={}", digits),
+                hex.clone(),
+            ];
+            let expected = otp_custom::<Sha1>(&secret, counter, digits)?;
+            assert_eq!(oathtool(&args), expected, "{:?}", args);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "encoding")]
+    fn totp_base32() -> Result<(), ThotpError> {
+        let secret = encoding::encode(TEST_KEY, data_encoding::BASE32);
+        let password = otp_custom::<Sha1>(TEST_KEY, 1111111109 / 30, 8)?;
+
+        assert_eq!(
+            (true, 0),
+            verify_totp_b32::<Sha1>(&password, &secret, 1111111109, 8, 30, 1)?
+        );
+        assert_eq!(
+            (false, 0),
+            verify_totp_b32::<Sha1>("00000000", &secret, 1111111109, 8, 30, 1)?
+        );
+
+        assert!(matches!(
+            verify_totp_b32::<Sha1>(&password, "GEZDG!BV", 1111111109, 8, 30, 1),
+            Err(ThotpError::Encoding(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn totp_result_bool() -> Result<(), ThotpError> {
+        let totp = super::totp::Totp::new(TEST_KEY.to_vec()).with_digits(8);
+
+        let result = totp.verify("94287082", 59)?;
+        assert!(result.is_valid());
+        assert!(bool::from(result));
+        assert!(!!result);
+        assert!(result.valid);
+        assert_eq!(result.discrepancy, 0);
+        assert!(!result.suspected_replay);
+
+        let result = totp.verify("94287082", 89)?;
+        assert!(result.is_valid());
+        assert_eq!(result.discrepancy, -1);
+
+        let result = totp.verify("00000000", 59)?;
+        assert!(!result.is_valid());
+        assert!(!result);
+        assert!(!result.valid);
+        let valid: bool = result.into();
+        assert!(!valid);
+        assert_eq!(result.discrepancy, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_long_period() -> Result<(), ThotpError> {
+        let timestamp = 1_234_567_890;
+        let slice = timestamp / 300;
+
+        for (nonce, expected) in [(slice - 1, -1), (slice + 1, 1)] {
+            let password = otp_custom::<Sha1>(TEST_KEY, nonce, 6)?;
+            let (valid, discrepancy) =
+                verify_totp_period::<Sha1>(&password, TEST_KEY, timestamp, 6, 300, 1)?;
+            assert!(valid);
+            assert_eq!(discrepancy, expected);
+        }
+
+        // Two slices away is outside the drift
+        let password = otp_custom::<Sha1>(TEST_KEY, slice + 2, 6)?;
+        let (valid, _) = verify_totp_period::<Sha1>(&password, TEST_KEY, timestamp, 6, 300, 1)?;
+        assert!(!valid);
+
+        // Slices are 300 seconds long
+        let password = otp_custom::<Sha1>(TEST_KEY, slice, 6)?;
+        let start = slice * 300;
+        let (valid, discrepancy) =
+            verify_totp_period::<Sha1>(&password, TEST_KEY, start + 299, 6, 300, 0)?;
+        assert!(valid && discrepancy == 0);
+
+        assert!(matches!(
+            verify_totp_period::<Sha1>(&password, TEST_KEY, timestamp, 6, 0, 1),
+            Err(ThotpError::InvalidStep)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn totp_zero_step() -> Result<(), ThotpError> {
+        let password = otp_custom::<Sha1>(TEST_KEY, 1, 6)?;
+        let is_invalid_step = |error| matches!(error, ThotpError::InvalidStep);
+
+        // A step of 0 is rejected instead of dividing by it, with an explicit timestamp and with 0
+        for timestamp in [0, 59] {
+            assert!(
+                verify_totp_custom::<Sha1>(&password, TEST_KEY, timestamp, 6, 0, 1)
+                    .is_err_and(is_invalid_step)
+            );
+            assert!(
+                verify_totp_at_exact::<Sha1>(&password, TEST_KEY, timestamp, 6, 0, 1)
+                    .is_err_and(is_invalid_step)
+            );
+            assert!(
+                verify_totp_with_expiry::<Sha1>(&password, TEST_KEY, timestamp, 6, 0, 1)
+                    .is_err_and(is_invalid_step)
+            );
+            assert!(
+                verify_totp_instrumented::<Sha1>(&password, TEST_KEY, timestamp, 6, 0, 1)
+                    .is_err_and(is_invalid_step)
+            );
+            assert!(
+                verify_extended::<Sha1>(&password, TEST_KEY, timestamp, 6, 0, 1)
+                    .is_err_and(is_invalid_step)
+            );
+            assert!(
+                verify_totp_tolerance::<Sha1>(&password, TEST_KEY, timestamp, 6, 0, 0, 2)
+                    .is_err_and(is_invalid_step)
+            );
+            assert!(verify_totp_rounded::<Sha1>(
+                &password,
+                TEST_KEY,
+                timestamp,
+                6,
+                0,
+                1,
+                BoundaryRounding::Nearest
+            )
+            .is_err_and(is_invalid_step));
+            assert!(current_totp::<Sha1>(TEST_KEY, timestamp, 0, 6).is_err_and(is_invalid_step));
+            assert!(next_boundary_at(timestamp, 0).is_err_and(is_invalid_step));
+            assert!(totp_counter(timestamp, 0).is_err_and(is_invalid_step));
+
+            let outcomes = verify_totp_batch::<Sha1>(
+                &[(Secret::new(TEST_KEY.to_vec()), password.as_str())],
+                timestamp,
+                6,
+                0,
+                1,
+            );
+            assert!(outcomes
+                .into_iter()
+                .all(|outcome| outcome.is_err_and(is_invalid_step)));
+        }
+
+        assert!(accepted_codes_now::<Sha1>(TEST_KEY, 0, 6, 1).is_err_and(is_invalid_step));
+        assert!(totp_table::<Sha1>(TEST_KEY, 0, 90, 0, 6).is_err_and(is_invalid_step));
+        Ok(())
+    }
+
+    #[test]
+    fn verify_wrong_length() -> Result<(), ThotpError> {
+        let password = otp(TEST_KEY, 1)?;
+        assert_eq!(
+            verify_hotp(&password, TEST_KEY, 1, 0)?,
+            (true, Counter::new(2))
+        );
+        assert_eq!(
+            verify_hotp(&format!("{password}0"), TEST_KEY, 1, 0)?,
+            (false, Counter::new(1))
+        );
+        assert_eq!(
+            verify_hotp(&password[1..], TEST_KEY, 1, 0)?,
+            (false, Counter::new(1))
+        );
+        assert_eq!(verify_totp("", TEST_KEY, 59)?, (false, 0));
+        assert_eq!(verify_totp("94287082", TEST_KEY, 59)?, (false, 0));
+
+        // The 6 digit suffix of the 8 digit password is the 6 digit password
+        assert!(verify_totp_custom::<Sha1>("94287082", TEST_KEY, 59, 8, 30, 1)?.0);
+        assert!(verify_totp_custom::<Sha1>("287082", TEST_KEY, 59, 6, 30, 1)?.0);
+        for password in ["287082", "0094287082", "9428708"] {
+            assert_eq!(
+                verify_totp_custom::<Sha1>(password, TEST_KEY, 59, 8, 30, 1)?,
+                (false, 0)
+            );
+        }
+        assert_eq!(
+            verify_hotp_custom::<Sha1>("94287082", TEST_KEY, 1, 0, 6)?,
+            (false, Counter::new(1))
+        );
+        assert!(!verify_totp_pair::<Sha1>(
+            "94287082", "287082", TEST_KEY, 59, 6, 30
+        )?);
+        assert!(code_time_range::<Sha1>("1287082", TEST_KEY, 0, 300, 30, 6)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn totp_multiple_algorithms() -> Result<(), ThotpError> {
+        let algorithms = [Algorithm::Sha1, Algorithm::Sha256];
+        let timestamp = 1111111109;
+
+        let password = otp_custom::<Sha1>(TEST_KEY, timestamp / 30, 8)?;
+        let matched =
+            verify_totp_algorithms(&password, TEST_KEY, timestamp, 8, 30, 1, &algorithms)?;
+        assert_eq!(matched, Some((Algorithm::Sha1, 0)));
+
+        let password = otp_custom::<Sha256>(TEST_KEY, timestamp / 30 + 1, 8)?;
+        let matched =
+            verify_totp_algorithms(&password, TEST_KEY, timestamp, 8, 30, 1, &algorithms)?;
+        assert_eq!(matched, Some((Algorithm::Sha256, 1)));
+
+        // SHA512 is not in the accepted set
+        let password = otp_custom::<Sha512>(TEST_KEY, timestamp / 30, 8)?;
+        let matched =
+            verify_totp_algorithms(&password, TEST_KEY, timestamp, 8, 30, 1, &algorithms)?;
+        assert_eq!(matched, None);
+        assert_eq!(
+            verify_totp_algorithms(&password, TEST_KEY, timestamp, 8, 30, 1, &[])?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn totp_multiple_secrets() -> Result<(), ThotpError> {
+        let other: &[u8] = b"09876543210987654321";
+        let secrets = [other, TEST_KEY];
+        let timestamp = 1111111109;
+
+        let password = otp_custom::<Sha1>(TEST_KEY, timestamp / 30 - 1, 6)?;
+        let matched = verify_totp_multi_secret::<Sha1>(&password, &secrets, timestamp, 6, 30, 1)?;
+        assert_eq!(matched, Some((1, -1)));
+
+        let matched =
+            verify_totp_multi_secret::<Sha1>(&password, &secrets[..1], timestamp, 6, 30, 1)?;
+        assert_eq!(matched, None);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_encrypted_secret() -> Result<(), ThotpError> {
+        // A stand in for decrypting the secret
+        let encrypted: Vec<u8> = TEST_KEY.iter().map(|byte| byte ^ 0x5c).collect();
+        let decrypt = || Ok(encrypted.iter().map(|byte| byte ^ 0x5c).collect::<Vec<_>>());
+
+        assert_eq!(
+            verify_totp_with::<Sha1, _>(decrypt, "94287082", 59, 8, 30, 1)?,
+            (true, 0)
+        );
+        assert_eq!(
+            verify_totp_with::<Sha1, _>(decrypt, "94287083", 59, 8, 30, 1)?,
+            (false, 0)
+        );
+
+        // The buffer is zeroized after the verification, whether it succeeded or not
+        for password in ["94287082", "94287083"] {
+            let mut buffer = decrypt()?;
+            assert_eq!(buffer, TEST_KEY);
+            let _ = verify_totp_with::<Sha1, _>(|| Ok(&mut buffer), password, 59, 8, 30, 1)?;
+            assert_eq!(buffer, [0; 20]);
+        }
+
+        let failed = verify_totp_with::<Sha1, Vec<u8>>(
+            || Err(ThotpError::InvalidState),
+            "94287082",
+            59,
+            8,
+            30,
+            1,
+        );
+        assert!(matches!(failed, Err(ThotpError::InvalidState)));
+        Ok(())
+    }
+
+    #[test]
+    fn enrollment_confirmation() -> Result<(), ThotpError> {
+        let timestamp = 1_234_567_890;
+
+        let user_code = otp_custom::<Sha1>(TEST_KEY, timestamp / 30, 6)?;
+        assert!(confirm_enrollment::<Sha1>(
+            TEST_KEY, &user_code, timestamp, 6, 30, 1
+        )?);
+
+        // A code generated with a different algorithm means the QR was scanned incorrectly
+        let wrong_code = otp_custom::<Sha256>(TEST_KEY, timestamp / 30, 6)?;
+        assert_ne!(user_code, wrong_code);
+        assert!(!confirm_enrollment::<Sha1>(
+            TEST_KEY,
+            &wrong_code,
+            timestamp,
+            6,
+            30,
+            1
+        )?);
+        assert!(!confirm_enrollment::<Sha1>(
+            TEST_KEY, "", timestamp, 6, 30, 1
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_pair() -> Result<(), ThotpError> {
+        let nonce = 1111111109 / 30;
+        let previous = otp_custom::<Sha1>(TEST_KEY, nonce - 1, 6)?;
+        let current = otp_custom::<Sha1>(TEST_KEY, nonce, 6)?;
+        let next = otp_custom::<Sha1>(TEST_KEY, nonce + 1, 6)?;
+
+        assert!(verify_totp_pair::<Sha1>(
+            &previous, &current, TEST_KEY, 1111111109, 6, 30
+        )?);
+        assert!(verify_totp_pair::<Sha1>(
+            &current, &next, TEST_KEY, 1111111109, 6, 30
+        )?);
+
+        // Reversed, not consecutive or not around the current slice
+        assert!(!verify_totp_pair::<Sha1>(
+            &current, &previous, TEST_KEY, 1111111109, 6, 30
+        )?);
+        assert!(!verify_totp_pair::<Sha1>(
+            &previous, &next, TEST_KEY, 1111111109, 6, 30
+        )?);
+        assert!(!verify_totp_pair::<Sha1>(
+            &current, &current, TEST_KEY, 1111111109, 6, 30
+        )?);
+        assert!(!verify_totp_pair::<Sha1>(
+            &previous, &current, TEST_KEY, 1111111139, 6, 30
+        )?);
+        assert!(!verify_totp_pair::<Sha1>(
+            &current, "000000", TEST_KEY, 1111111109, 6, 30
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_record() -> Result<(), ThotpError> {
+        let secret: &[u8] = b"12345678901234567890123456789012";
+
+        let record = verify_totp_record::<Sha256>("68084774", secret, 1111111109, 8, 30, 1)?;
+        assert_eq!(
+            record,
+            VerificationRecord {
+                algorithm: String::from("SHA256"),
+                digits: 8,
+                step: 30,
+                matched: true,
+                drift: 0,
+                timestamp: 1111111109,
+                context: None,
+            }
+        );
+
+        let record = verify_totp_record::<Sha1>("00000000", secret, 1111111109, 8, 30, 1)?;
+        assert!(!record.matched);
+        assert_eq!(record.drift, 0);
+
+        // The record contains neither the secret nor the password
+        let debug = format!("{:?}", record);
+        assert!(!debug.contains("1234567890"));
+        assert!(!debug.contains("00000000"));
+
+        // A timestamp of 0 records the current time
+        let record = verify_totp_record::<Sha1>("00000000", secret, 0, 6, 30, 1)?;
+        assert_ne!(record.timestamp, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_record_context() -> Result<(), ThotpError> {
+        let secret: &[u8] = b"12345678901234567890123456789012";
+
+        let record = verify_totp_record::<Sha256>("68084774", secret, 1111111109, 8, 30, 1)?
+            .with_context("request=42 user=john");
+        assert!(record.matched);
+        assert_eq!(record.context.as_deref(), Some("request=42 user=john"));
+        assert!(format!("{:?}", record).contains("request=42 user=john"));
+
+        // The context is copied verbatim, even if it looks like a password or secret
+        let record = verify_totp_record::<Sha256>("00000000", secret, 1111111109, 8, 30, 1)?
+            .with_context("00000000 1234567890");
+        assert_eq!(record.context.as_deref(), Some("00000000 1234567890"));
+        assert!(!record.matched);
+        Ok(())
+    }
 }
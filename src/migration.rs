@@ -0,0 +1,498 @@
+//! Contains functions to export credentials in the `otpauth-migration` format used by the account
+//! transfer of Google Authenticator, and to parse it. Unlike otp uris, a single migration uri holds
+//! several accounts, and large exports are split into batches, one QR code each.
+//!
+//! The accounts are encoded as a protocol buffers `MigrationPayload` message, which is Base64 encoded
+//! in the `data` parameter of the uri. The format has no period, so only TOTPs with a time step of 30
+//! can be exported, and only passwords of 6 or 8 digits.
+//!
+//! ## Example
+//! ```
+//! use thotp::migration::{migration_uris, parse_migration_uri};
+//! use thotp::qr::parse_otpauth;
+//!
+//! let accounts = vec![
+//!     parse_otpauth("otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP").unwrap(),
+//!     parse_otpauth("otpauth://totp/Small%20Corp:jane?secret=GEZDGNBVGY3TQOJQ").unwrap(),
+//! ];
+//!
+//! let uris = migration_uris(&accounts, 1).unwrap();
+//! assert_eq!(uris.len(), 2);
+//!
+//! let batch = parse_migration_uri(&uris[1]).unwrap();
+//! assert_eq!((batch.batch_index, batch.batch_size), (1, 2));
+//! assert_eq!(batch.accounts, accounts[1..]);
+//! ```
+
+use super::qr::{generate_code_png, OtpauthParams};
+use super::ThotpError;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+/// The start of every migration uri, followed by the percent encoded payload.
+pub const MIGRATION_PREFIX: &str = "otpauth-migration://offline?data=";
+
+/// The version of the payload written by [migration_uris].
+const PAYLOAD_VERSION: u64 = 1;
+
+/// The only period the format supports.
+const PERIOD: u32 = 30;
+
+/// The accounts of a single migration uri parsed with [parse_migration_uri], along with its place in
+/// the export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationBatch {
+    /// The accounts in the batch, with the default period of 30
+    pub accounts: Vec<OtpauthParams>,
+
+    /// The index of the batch in the export, starting at 0
+    pub batch_index: u32,
+
+    /// The number of batches in the export
+    pub batch_size: u32,
+
+    /// An identifier shared by all batches of the same export
+    pub batch_id: u32,
+}
+
+/// Encodes the accounts into migration uris holding at most `per_code` accounts each. All uris share
+/// a random batch identifier and carry their index and the number of uris, so authenticator apps can
+/// tell when they scanned all of them.
+///
+/// Returns a [ThotpError::InvalidParameter] if `per_code` is 0, or if an account can not be
+/// represented in the format, i.e. its period is not 30, it does not have 6 or 8 digits, or it uses an
+/// unknown algorithm or type. No uris are returned for no accounts.
+pub fn migration_uris(
+    accounts: &[OtpauthParams],
+    per_code: usize,
+) -> Result<Vec<String>, ThotpError> {
+    if per_code == 0 {
+        return Err(ThotpError::InvalidParameter {
+            key: String::from("per_code"),
+            value: per_code.to_string(),
+        });
+    }
+
+    let batches = accounts.chunks(per_code);
+    let batch_size = batches.len() as u64;
+    let batch_id = (StdRng::from_entropy().next_u32() >> 1) as u64;
+
+    batches
+        .enumerate()
+        .map(|(batch_index, batch)| {
+            let mut payload = Vec::new();
+            for account in batch {
+                write_bytes(&mut payload, 1, &encode_account(account)?);
+            }
+            write_varint_field(&mut payload, 2, PAYLOAD_VERSION);
+            write_varint_field(&mut payload, 3, batch_size);
+            write_varint_field(&mut payload, 4, batch_index as u64);
+            write_varint_field(&mut payload, 5, batch_id);
+
+            let data = data_encoding::BASE64.encode(&payload);
+            Ok(format!(
+                "{}{}",
+                MIGRATION_PREFIX,
+                utf8_percent_encode(&data, NON_ALPHANUMERIC)
+            ))
+        })
+        .collect()
+}
+
+/// Encodes the accounts into migration uris just like [migration_uris] and renders a QR code PNG with
+/// [generate_code_png] for each of them, in the order they have to be scanned.
+///
+/// The QR codes are rendered with at least 300 pixels per side, since migration uris are long.
+///
+/// ## Example
+/// ```
+/// use thotp::migration::export_migration_qrs;
+/// use thotp::qr::parse_otpauth;
+///
+/// let account = parse_otpauth("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP").unwrap();
+/// let codes = export_migration_qrs(&vec![account; 5], 2).unwrap();
+///
+/// assert_eq!(codes.len(), 3);
+/// assert!(codes.iter().all(|png| png.starts_with(b"\x89PNG")));
+/// ```
+pub fn export_migration_qrs(
+    accounts: &[OtpauthParams],
+    per_code: usize,
+) -> Result<Vec<Vec<u8>>, ThotpError> {
+    migration_uris(accounts, per_code)?
+        .iter()
+        .map(|uri| generate_code_png(uri, 300))
+        .collect()
+}
+
+/// Parses a migration uri, e.g. one scanned from a QR code exported by Google Authenticator. Fields of
+/// the payload this crate does not know are skipped, while accounts using an algorithm other than
+/// `SHA1`, `SHA256` or `SHA512` result in a [ThotpError::InvalidParameter].
+///
+/// If an account has no issuer, but its name has an `issuer:` prefix like the label of otp uris, the
+/// prefix is used as the issuer.
+pub fn parse_migration_uri(uri: &str) -> Result<MigrationBatch, ThotpError> {
+    let data = uri
+        .strip_prefix(MIGRATION_PREFIX)
+        .ok_or_else(|| invalid("The uri is not an otpauth-migration uri"))?;
+
+    let data = percent_decode_str(data)
+        .decode_utf8()
+        .map_err(|_| invalid("The data parameter is not valid UTF-8"))?;
+    let payload = data_encoding::BASE64.decode(data.as_bytes())?;
+
+    let mut batch = MigrationBatch {
+        accounts: Vec::new(),
+        batch_index: 0,
+        batch_size: 1,
+        batch_id: 0,
+    };
+
+    for field in Fields(&payload) {
+        match field? {
+            (1, Value::Bytes(account)) => batch.accounts.push(decode_account(account)?),
+            (3, Value::Varint(size)) => batch.batch_size = to_u32(size)?,
+            (4, Value::Varint(index)) => batch.batch_index = to_u32(index)?,
+            (5, Value::Varint(id)) => batch.batch_id = to_u32(id)?,
+            _ => {}
+        }
+    }
+
+    Ok(batch)
+}
+
+/// Encodes an account as an `OtpParameters` message.
+fn encode_account(account: &OtpauthParams) -> Result<Vec<u8>, ThotpError> {
+    let unsupported = |key: &str, value: String| ThotpError::InvalidParameter {
+        key: key.to_string(),
+        value,
+    };
+
+    let algorithm = match account.algorithm.as_str() {
+        "SHA1" => 1,
+        "SHA256" => 2,
+        "SHA512" => 3,
+        other => return Err(unsupported("algorithm", other.to_string())),
+    };
+    let digits = match account.digits {
+        6 => 1,
+        8 => 2,
+        other => return Err(unsupported("digits", other.to_string())),
+    };
+    let otp_type = match account.otp_type.as_str() {
+        "hotp" => 1,
+        "totp" if account.period == PERIOD => 2,
+        "totp" => return Err(unsupported("period", account.period.to_string())),
+        other => return Err(unsupported("type", other.to_string())),
+    };
+
+    let mut message = Vec::new();
+    write_bytes(&mut message, 1, &account.secret);
+    write_bytes(&mut message, 2, account.account.as_bytes());
+    if let Some(ref issuer) = account.issuer {
+        write_bytes(&mut message, 3, issuer.as_bytes());
+    }
+    write_varint_field(&mut message, 4, algorithm);
+    write_varint_field(&mut message, 5, digits);
+    write_varint_field(&mut message, 6, otp_type);
+    if let Some(counter) = account.counter {
+        write_varint_field(&mut message, 7, counter);
+    }
+
+    Ok(message)
+}
+
+/// Decodes an `OtpParameters` message, unspecified enum values are the defaults of otp uris.
+fn decode_account(message: &[u8]) -> Result<OtpauthParams, ThotpError> {
+    let mut account = OtpauthParams {
+        otp_type: String::from("totp"),
+        secret: Vec::new(),
+        issuer: None,
+        account: String::new(),
+        algorithm: String::from("SHA1"),
+        digits: 6,
+        period: PERIOD,
+        counter: None,
+    };
+    let mut counter = 0;
+
+    for field in Fields(message) {
+        match field? {
+            (1, Value::Bytes(secret)) => account.secret = secret.to_vec(),
+            (2, Value::Bytes(name)) => account.account = to_string(name)?,
+            (3, Value::Bytes(issuer)) if !issuer.is_empty() => {
+                account.issuer = Some(to_string(issuer)?)
+            }
+            (4, Value::Varint(algorithm)) => {
+                account.algorithm = match algorithm {
+                    0 | 1 => "SHA1",
+                    2 => "SHA256",
+                    3 => "SHA512",
+                    4 => "MD5",
+                    _ => "unknown",
+                }
+                .to_string()
+            }
+            (5, Value::Varint(digits)) => account.digits = if digits == 2 { 8 } else { 6 },
+            (6, Value::Varint(1)) => account.otp_type = String::from("hotp"),
+            (7, Value::Varint(value)) => counter = value,
+            _ => {}
+        }
+    }
+
+    if !matches!(account.algorithm.as_str(), "SHA1" | "SHA256" | "SHA512") {
+        return Err(ThotpError::InvalidParameter {
+            key: String::from("algorithm"),
+            value: account.algorithm,
+        });
+    }
+
+    if account.otp_type == "hotp" {
+        account.counter = Some(counter);
+    }
+
+    // The name may carry the issuer like the label of otp uris
+    let name = std::mem::take(&mut account.account);
+    account.account = match (name.split_once(':'), &account.issuer) {
+        (Some((prefix, rest)), Some(issuer)) if prefix == issuer => rest.trim_start().to_string(),
+        (Some((prefix, rest)), None) => {
+            account.issuer = Some(prefix.to_string());
+            rest.trim_start().to_string()
+        }
+        _ => name,
+    };
+
+    Ok(account)
+}
+
+/// A decoded protocol buffers field value, fixed size values are skipped.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterates over the field numbers and values of a protocol buffers message.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Value<'a>), ThotpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let field = self.read_field();
+        if field.is_err() {
+            // Nothing after a malformed field can be read
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+impl<'a> Fields<'a> {
+    fn read_field(&mut self) -> Result<(u64, Value<'a>), ThotpError> {
+        let key = self.read_varint()?;
+
+        let value = match key & 0b111 {
+            0 => Value::Varint(self.read_varint()?),
+            1 => self.take(8).map(|_| Value::Fixed)?,
+            2 => {
+                let len = self.read_varint()?;
+                Value::Bytes(self.take(usize::try_from(len).map_err(|_| truncated())?)?)
+            }
+            5 => self.take(4).map(|_| Value::Fixed)?,
+            _ => return Err(invalid("The payload contains an unsupported wire type")),
+        };
+
+        Ok((key >> 3, value))
+    }
+
+    fn read_varint(&mut self) -> Result<u64, ThotpError> {
+        let mut value = 0;
+
+        for (index, byte) in self.0.iter().enumerate().take(10) {
+            value |= ((byte & 0x7f) as u64) << (7 * index);
+            if byte & 0x80 == 0 {
+                self.0 = &self.0[index + 1..];
+                return Ok(value);
+            }
+        }
+
+        Err(truncated())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ThotpError> {
+        if self.0.len() < len {
+            return Err(truncated());
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+}
+
+/// Appends a protocol buffers varint.
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Appends a varint field.
+fn write_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(out, field << 3);
+    write_varint(out, value);
+}
+
+/// Appends a length delimited field.
+fn write_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(out, field << 3 | 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn to_u32(value: u64) -> Result<u32, ThotpError> {
+    u32::try_from(value).map_err(|_| invalid("A batch field of the payload is out of range"))
+}
+
+fn to_string(bytes: &[u8]) -> Result<String, ThotpError> {
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| invalid("A name in the payload is not valid UTF-8"))
+}
+
+fn truncated() -> ThotpError {
+    invalid("The payload is truncated")
+}
+
+fn invalid(message: &str) -> ThotpError {
+    ThotpError::InvalidUri(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::qr::parse_otpauth;
+    use super::*;
+
+    #[test]
+    fn migration_batches() -> Result<(), ThotpError> {
+        let mut accounts = vec![
+            parse_otpauth(
+                "otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP&issuer=Big%20Corp",
+            )?,
+            parse_otpauth("otpauth://totp/jane?secret=GEZDGNBVGY3TQOJQ&algorithm=SHA256&digits=8")?,
+            parse_otpauth("otpauth://hotp/Tiny:bob?secret=GEZDGNBVGY3TQOJQ&counter=300")?,
+            parse_otpauth("otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP&algorithm=SHA512")?,
+            parse_otpauth("otpauth://hotp/eve?secret=JBSWY3DPEHPK3PXP")?,
+        ];
+
+        let uris = migration_uris(&accounts, 2)?;
+        assert_eq!(uris.len(), 3);
+
+        let batches = uris
+            .iter()
+            .map(|uri| parse_migration_uri(uri))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (index, batch) in batches.iter().enumerate() {
+            assert_eq!(batch.batch_index, index as u32);
+            assert_eq!(batch.batch_size, 3);
+            assert_eq!(batch.batch_id, batches[0].batch_id);
+        }
+        assert_eq!(batches[2].accounts.len(), 1);
+        assert!(batches
+            .iter()
+            .all(|batch| !format!("{:?}", batch).contains("72, 101")));
+
+        // The eve HOTP had no counter parameter, which defaults to 0
+        accounts[4].counter = Some(0);
+        let parsed: Vec<_> = batches
+            .into_iter()
+            .flat_map(|batch| batch.accounts)
+            .collect();
+        assert_eq!(parsed, accounts);
+
+        let codes = export_migration_qrs(&accounts, 2)?;
+        assert_eq!(codes.len(), 3);
+        assert_eq!(migration_uris(&accounts, 5)?.len(), 1);
+        assert!(migration_uris(&[], 5)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn migration_unsupported() -> Result<(), ThotpError> {
+        let account = parse_otpauth("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP&period=60")?;
+        assert!(matches!(
+            migration_uris(&[account], 1),
+            Err(ThotpError::InvalidParameter { key, .. }) if key == "period"
+        ));
+
+        let account = parse_otpauth("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP&digits=7")?;
+        assert!(matches!(
+            migration_uris(std::slice::from_ref(&account), 1),
+            Err(ThotpError::InvalidParameter { key, .. }) if key == "digits"
+        ));
+        assert!(matches!(
+            migration_uris(&[account], 0),
+            Err(ThotpError::InvalidParameter { key, .. }) if key == "per_code"
+        ));
+
+        assert!(parse_migration_uri("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP").is_err());
+        // A truncated account message
+        assert!(parse_migration_uri(&format!("{}CgUK", MIGRATION_PREFIX)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn migration_payload() -> Result<(), ThotpError> {
+        // An MD5 account as exported by Google Authenticator, with the issuer in the name
+        let mut account = Vec::new();
+        write_bytes(&mut account, 1, b"Hello!");
+        write_bytes(&mut account, 2, b"Big Corp:john");
+        write_varint_field(&mut account, 4, 4);
+        let mut payload = Vec::new();
+        write_bytes(&mut payload, 1, &account);
+        let uri = format!(
+            "{}{}",
+            MIGRATION_PREFIX,
+            data_encoding::BASE64.encode(&payload)
+        );
+        assert!(matches!(
+            parse_migration_uri(&uri),
+            Err(ThotpError::InvalidParameter { value, .. }) if value == "MD5"
+        ));
+
+        // The same account with SHA1 and an unknown fixed size field, which is skipped
+        account.truncate(account.len() - 2);
+        account.extend_from_slice(&[0x45, 1, 2, 3, 4]);
+        let mut payload = Vec::new();
+        write_bytes(&mut payload, 1, &account);
+        let uri = format!(
+            "{}{}",
+            MIGRATION_PREFIX,
+            data_encoding::BASE64.encode(&payload)
+        );
+
+        let batch = parse_migration_uri(&uri)?;
+        assert_eq!((batch.batch_index, batch.batch_size), (0, 1));
+        assert_eq!(
+            batch.accounts,
+            [parse_otpauth(
+                "otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEE"
+            )?]
+        );
+
+        // The encoding of a single account
+        let account = parse_otpauth("otpauth://totp/john?secret=JBSWY3DPEE")?;
+        assert_eq!(
+            encode_account(&account)?,
+            b"\x0a\x06Hello!\x12\x04john\x20\x01\x28\x01\x30\x02"
+        );
+        Ok(())
+    }
+}
@@ -6,7 +6,7 @@ use digest::{
     core_api::{BufferKindUser, CoreProxy, FixedOutputCore, UpdateCore},
     crypto_common::BlockSizeUser,
     typenum::{IsLess, Le, NonZero, U256},
-    FixedOutput, HashMarker, InvalidLength, Update,
+    HashMarker, InvalidLength, KeyInit, Output,
 };
 use hmac::{Hmac, Mac};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -22,11 +22,17 @@ pub(super) const TIME_STEP: u8 = 30;
 /// time steps.
 pub(super) const ALLOWED_DRIFT: u8 = 1;
 
-/// Generates a MAC of the secret key and nonce, hashed with the provided algorithm.
-#[inline]
-pub(super) fn hmac_digest<H>(secret: &[u8], nonce: &[u8]) -> Result<Vec<u8>, InvalidLength>
+/// A hashing algorithm HMACs can be computed with, such as `Sha1`, `Sha256` and `Sha512`. It is
+/// implemented for every algorithm satisfying the bounds of [Hmac], so the generic functions only
+/// have to require this trait instead of repeating them.
+pub trait OtpHash {
+    /// The HMAC of the algorithm
+    type Mac: Mac + KeyInit + Clone;
+}
+
+impl<H> OtpHash for H
 where
-    H: Update + FixedOutput + CoreProxy,
+    H: CoreProxy,
     H::Core: HashMarker
         + UpdateCore
         + FixedOutputCore
@@ -36,9 +42,65 @@ where
     <H::Core as BlockSizeUser>::BlockSize: IsLess<U256>,
     Le<<H::Core as BlockSizeUser>::BlockSize, U256>: NonZero,
 {
-    let mut mac = Hmac::<H>::new_from_slice(secret)?;
-    <Hmac<H> as Update>::update(&mut mac, nonce);
-    Ok(mac.finalize().into_bytes().to_vec())
+    type Mac = Hmac<H>;
+}
+
+/// Generates a MAC of the secret key and nonce, hashed with the provided algorithm. The MAC is
+/// returned as the fixed size array the digest produces, so generating a password does not allocate
+/// anything but the password itself.
+#[inline]
+pub(super) fn hmac_digest<H>(secret: &[u8], nonce: &[u8]) -> Result<Output<H::Mac>, InvalidLength>
+where
+    H: OtpHash,
+{
+    let mut mac = <H::Mac as KeyInit>::new_from_slice(secret)?;
+    mac.update(nonce);
+    Ok(mac.finalize().into_bytes())
+}
+
+/// Derives key material with HKDF from [RFC 5869](https://www.rfc-editor.org/rfc/rfc5869), using the
+/// HMAC of the provided algorithm. The pseudorandom key extracted from `ikm` is overwritten with 0s
+/// once the output is expanded.
+///
+/// Returns a [ThotpError::InvalidSecretLength] if `len` is larger than 255 times the output length
+/// of the algorithm.
+#[cfg(feature = "custom")]
+pub(super) fn hkdf<H>(
+    salt: &[u8],
+    ikm: &[u8],
+    info: &[u8],
+    len: usize,
+) -> Result<Vec<u8>, ThotpError>
+where
+    H: OtpHash,
+{
+    // An empty salt is the same HMAC key as the zero filled one the RFC defaults to
+    let mut prk = hmac_digest::<H>(salt, ikm)?;
+
+    let max = 255 * prk.len();
+    if len > max {
+        zeroize(&mut prk);
+        return Err(ThotpError::InvalidSecretLength { max });
+    }
+
+    let mut okm = Vec::with_capacity(len + prk.len());
+    let mut block = Vec::new();
+
+    for counter in 1..=len.div_ceil(prk.len()) as u8 {
+        let mut message = std::mem::take(&mut block);
+        message.extend_from_slice(info);
+        message.push(counter);
+
+        block = hmac_digest::<H>(&prk, &message)?.to_vec();
+        okm.extend_from_slice(&block);
+        zeroize(&mut message);
+    }
+
+    zeroize(&mut prk);
+    zeroize(&mut block);
+    zeroize(&mut okm[len..]);
+    okm.truncate(len);
+    Ok(okm)
 }
 
 /// The dynamic truncate function as described in [RFC 4226](https://www.rfc-editor.org/rfc/rfc4226).
@@ -47,7 +109,7 @@ where
 /// resulting in a sequence of 31 bits. This function returns those 4 bytes in an u32, mitigating the need to
 /// call the function str_to_num since it basically happens when we transform the byte array to an integer.
 #[inline]
-pub(super) fn dynamic_trunc(input: &mut [u8]) -> u32 {
+pub(super) fn dynamic_trunc(input: &[u8]) -> u32 {
     // Grab the last 4 bits
     let offset = (input.last().unwrap() & 0xf) as usize;
 
@@ -60,6 +122,42 @@ pub(super) fn dynamic_trunc(input: &mut [u8]) -> u32 {
     u32::from_be_bytes(result)
 }
 
+/// Reduces the truncated HMAC to the given number of digits and pads it with leading 0s if it is
+/// shorter than that.
+#[inline]
+pub(super) fn to_password(trunc: u32, digits: u8) -> String {
+    let mut password = String::with_capacity(digits as usize);
+    write_password(trunc, digits, &mut password);
+    password
+}
+
+/// Same as [to_password], but clears the given buffer and writes the password to it instead of
+/// allocating a new string.
+#[inline]
+pub(super) fn write_password(trunc: u32, digits: u8, out: &mut String) {
+    use std::fmt::Write;
+
+    // The truncated HMAC has at most 10 digits, so any larger modulus leaves it as is
+    let result = trunc as u64 % digit_modulus(digits.min(10));
+    out.clear();
+    // Writing to a string can not fail
+    let _ = write!(out, "{:0width$}", result, width = digits as usize);
+}
+
+/// Returns `10^digits`, the modulus reducing a truncated HMAC to a password of the given number of
+/// digits. Being a `const fn` it can be evaluated at compile time for constant digit lengths.
+///
+/// Panics if `digits` is greater than 19, since `10^20` does not fit in a `u64`. In a constant
+/// context this is a compile time error instead.
+#[inline]
+pub(super) const fn digit_modulus(digits: u8) -> u64 {
+    assert!(
+        digits <= 19,
+        "10^digits only fits in a u64 for up to 19 digits"
+    );
+    10_u64.pow(digits as u32)
+}
+
 /// Calculates the number of seconds passed from the unix epoch divided by the default timestep.
 #[inline]
 pub(super) fn time_step_now() -> Result<u64, ThotpError> {
@@ -67,6 +165,77 @@ pub(super) fn time_step_now() -> Result<u64, ThotpError> {
     Ok(time_step)
 }
 
+/// Returns the given timestamp, or the current system time if it is 0.
+#[cfg(feature = "custom")]
+#[inline]
+pub(super) fn resolve_timestamp(timestamp: u64) -> Result<u64, ThotpError> {
+    if timestamp != 0 {
+        return Ok(timestamp);
+    }
+
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Calculates the time slice of the given unix timestamp, i.e. the nonce its TOTP is generated with.
+/// Returns a [ThotpError::InvalidStep] if the `step` is 0 instead of dividing by it.
+#[cfg(feature = "custom")]
+#[inline]
+pub(super) fn checked_time_slice(timestamp: u64, step: u8) -> Result<u64, ThotpError> {
+    if step == 0 {
+        return Err(ThotpError::InvalidStep);
+    }
+
+    Ok(timestamp / step as u64)
+}
+
+/// Overwrites the given buffer with 0s. The writes are volatile so they are not optimized away even
+/// though the buffer is usually dropped right after.
+#[cfg(feature = "custom")]
+pub(super) fn zeroize(buffer: &mut [u8]) {
+    for byte in buffer.iter_mut() {
+        // SAFETY: The pointer comes from a mutable reference so it is valid and aligned
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Compares the given byte slices in constant time with regards to their contents. Slices of
+/// different lengths are never equal and return early.
+#[inline]
+pub(super) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b));
+
+    std::hint::black_box(diff) == 0
+}
+
+/// Returns the name of the hashing algorithm in the format used by OTP uris, e.g. `SHA256`.
+#[cfg(feature = "custom")]
+pub(super) fn algorithm_name<H>() -> String
+where
+    H: CoreProxy,
+    H::Core: digest::core_api::AlgorithmName,
+{
+    use digest::core_api::AlgorithmName;
+    use std::{fmt, marker::PhantomData};
+
+    struct Name<C>(PhantomData<C>);
+
+    impl<C: AlgorithmName> fmt::Display for Name<C> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            C::write_alg_name(f)
+        }
+    }
+
+    let name = Name::<H::Core>(PhantomData).to_string();
+
+    // Variable output cores append the output size, e.g. `Sha256_32`
+    name.split('_').next().unwrap_or_default().to_uppercase()
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::custom::{Sha1, Sha256, Sha512};
@@ -88,11 +257,51 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn hkdf_rfc_vectors() -> Result<(), ThotpError> {
+        // Test case 1 of RFC 5869
+        let okm = super::hkdf::<Sha256>(
+            &(0x00..=0x0c).collect::<Vec<u8>>(),
+            &[0x0b; 22],
+            &(0xf0..=0xf9).collect::<Vec<u8>>(),
+            42,
+        )?;
+        assert_eq!(
+            okm,
+            [
+                0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36,
+                0x2f, 0x2a, 0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56,
+                0xec, 0xc4, 0xc5, 0xbf, 0x34, 0x00, 0x72, 0x08, 0xd5, 0xb8, 0x87, 0x18, 0x58, 0x65
+            ]
+        );
+
+        // Test case 3, without salt and info
+        let okm = super::hkdf::<Sha256>(&[], &[0x0b; 22], &[], 42)?;
+        assert_eq!(
+            okm,
+            [
+                0x8d, 0xa4, 0xe7, 0x75, 0xa5, 0x63, 0xc1, 0x8f, 0x71, 0x5f, 0x80, 0x2a, 0x06, 0x3c,
+                0x5a, 0x31, 0xb8, 0xa1, 0x1f, 0x5c, 0x5e, 0xe1, 0x87, 0x9e, 0xc3, 0x45, 0x4e, 0x5f,
+                0x3c, 0x73, 0x8d, 0x2d, 0x9d, 0x20, 0x13, 0x95, 0xfa, 0xa4, 0xb6, 0x1a, 0x96, 0xc8
+            ]
+        );
+
+        assert_eq!(
+            super::hkdf::<Sha256>(&[], b"ikm", &[], 255 * 32)?.len(),
+            255 * 32
+        );
+        assert!(matches!(
+            super::hkdf::<Sha256>(&[], b"ikm", &[], 255 * 32 + 1),
+            Err(ThotpError::InvalidSecretLength { max: 8160 })
+        ));
+        Ok(())
+    }
+
     #[test]
     fn dynamic_trunc_() -> Result<(), ThotpError> {
-        let mut hmac = super::hmac_digest::<Sha1>(b"super secret key", b"1")?;
+        let hmac = super::hmac_digest::<Sha1>(b"super secret key", b"1")?;
         assert_eq!(
-            hmac,
+            hmac.as_slice(),
             [
                 104, 105, 130, 165, 155, 87, 155, 213, 180, 67, 104, 223, 123, 179, 211, 125, 173,
                 78, 220, 226
@@ -104,11 +313,109 @@ mod tests {
         assert_eq!(mask, 2);
 
         // Based on the offset we know the slice will be [130, 165, 155, 87]
-        let res = dynamic_trunc(&mut hmac);
+        let res = dynamic_trunc(&hmac);
 
         // We also know the first bit will be masked, which in this case is the first bit of 130
         // and this will transform 130 to 2, the rest are hex representations of 165, 155 and 87
         assert_eq!(res, 0x02_a5_9b_57);
         Ok(())
     }
+
+    #[test]
+    fn digit_moduli() {
+        const MODULUS: u64 = digit_modulus(8);
+        assert_eq!(MODULUS, 100_000_000);
+
+        assert_eq!(digit_modulus(0), 1);
+        assert_eq!(digit_modulus(6), 1_000_000);
+        assert_eq!(digit_modulus(10), 10_000_000_000);
+        assert_eq!(digit_modulus(19), 10_000_000_000_000_000_000);
+        assert!(std::panic::catch_unwind(|| digit_modulus(20)).is_err());
+
+        // 10 digits no longer overflow and longer passwords are padded
+        assert_eq!(to_password(u32::MAX >> 1, 10), "2147483647");
+        assert_eq!(to_password(u32::MAX >> 1, 12), "002147483647");
+    }
+
+    #[test]
+    fn password_padding() {
+        assert_eq!(to_password(7, 6), "000007");
+        assert_eq!(to_password(1_000_007, 6), "000007");
+        assert_eq!(to_password(0, 8), "00000000");
+        assert_eq!(to_password(0x02_a5_9b_57, 6), "407639");
+        assert_eq!(to_password(0x02_a5_9b_57, 9), "044407639");
+        assert_eq!(to_password(123, 0), "0");
+
+        let mut buffer = String::from("leftover");
+        write_password(0x02_a5_9b_57, 6, &mut buffer);
+        assert_eq!(buffer, "407639");
+    }
+
+    #[test]
+    #[cfg(feature = "custom")]
+    fn hmac_rfc_vectors() -> Result<(), ThotpError> {
+        fn hex(bytes: &[u8]) -> String {
+            bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+        }
+
+        // Test case 1 of RFC 4231 and RFC 2202
+        let (key, data) = ([0x0b; 20], b"Hi There");
+        assert_eq!(
+            hex(&hmac_digest::<Sha1>(&key, data)?),
+            "b617318655057264e28bc0b6fb378c8ef146be00"
+        );
+        assert_eq!(
+            hex(&hmac_digest::<Sha256>(&key, data)?),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hex(&hmac_digest::<Sha512>(&key, data)?),
+            concat!(
+                "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cde",
+                "daa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854"
+            )
+        );
+
+        // Keys longer than the block size are hashed first, test case 6 of RFC 4231 and RFC 2202
+        let data = b"Test Using Larger Than Block-Size Key - Hash Key First";
+        assert_eq!(
+            hex(&hmac_digest::<Sha1>(&[0xaa; 80], data)?),
+            "aa4ae5e15272d00e95705637ce8a3b55ed402112"
+        );
+        assert_eq!(
+            hex(&hmac_digest::<Sha256>(&[0xaa; 131], data)?),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+        assert_eq!(
+            hex(&hmac_digest::<Sha512>(&[0xaa; 131], data)?),
+            concat!(
+                "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f352",
+                "6b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598"
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "custom")]
+    fn zeroize_buffer() {
+        let mut buffer = b"12345678901234567890".to_vec();
+        zeroize(&mut buffer);
+        assert_eq!(buffer, [0; 20]);
+    }
+
+    #[test]
+    fn constant_time_eq() {
+        assert!(ct_eq(b"123456", b"123456"));
+        assert!(!ct_eq(b"123456", b"123457"));
+        assert!(!ct_eq(b"123456", b"1234567"));
+        assert!(ct_eq(b"", b""));
+    }
+
+    #[test]
+    fn algorithm_names() {
+        assert_eq!(algorithm_name::<Sha1>(), "SHA1");
+        assert_eq!(algorithm_name::<Sha256>(), "SHA256");
+        assert_eq!(algorithm_name::<Sha512>(), "SHA512");
+    }
 }
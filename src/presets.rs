@@ -0,0 +1,223 @@
+//! Contains the [IssuerPresets] registry of the OTP parameters known providers use, for filling in
+//! parameters an otp uri omits. Presets are only meant to be used as defaults, parameters that are
+//! explicitly provided should always take precedence over them.
+//!
+//! It also contains the [TotpPolicy] of a digit length and time step, which can be packed into a
+//! single integer for configs that only allow numbers.
+//!
+//! ## Example
+//! ```
+//! use thotp::custom::Algorithm;
+//! use thotp::presets::preset_for;
+//!
+//! let preset = preset_for("Authy").unwrap();
+//!
+//! assert_eq!(preset.algorithm, Algorithm::Sha1);
+//! assert_eq!(preset.digits, 7);
+//! assert_eq!(preset.step, 10);
+//!
+//! assert!(preset_for("Unknown Corp").is_none());
+//! ```
+
+use super::custom::Algorithm;
+use super::ThotpError;
+use std::collections::HashMap;
+
+/// The OTP parameters a provider uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IssuerPreset {
+    /// The hashing algorithm
+    pub algorithm: Algorithm,
+
+    /// The digit length of the passwords
+    pub digits: u8,
+
+    /// The time step of TOTPs
+    pub step: u8,
+}
+
+impl IssuerPreset {
+    /// Creates a preset with the given parameters.
+    pub const fn new(algorithm: Algorithm, digits: u8, step: u8) -> Self {
+        Self {
+            algorithm,
+            digits,
+            step,
+        }
+    }
+}
+
+/// The digit length and time step of TOTPs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TotpPolicy {
+    /// The digit length of the passwords
+    pub digits: u8,
+
+    /// The time step
+    pub step: u8,
+}
+
+impl TotpPolicy {
+    /// Creates a policy with the given parameters.
+    pub const fn new(digits: u8, step: u8) -> Self {
+        Self { digits, step }
+    }
+
+    /// Unpacks a policy packed with [TotpPolicy::to_compact]. The lowest 8 bits are the time step and
+    /// the next 8 bits are the digit length, so e.g. 6 digits and a step of 30 are `0x061e`.
+    ///
+    /// Returns a [ThotpError::InvalidPolicy] if any of the upper 16 bits are set, a
+    /// [ThotpError::InvalidDigits] if the digit length is not in the range of `6..=10` and a
+    /// [ThotpError::InvalidStep] if the step is 0.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::presets::TotpPolicy;
+    ///
+    /// let policy = TotpPolicy::from_compact(0x061e).unwrap();
+    ///
+    /// assert_eq!(policy, TotpPolicy::new(6, 30));
+    /// assert_eq!(policy.to_compact(), 0x061e);
+    /// ```
+    pub fn from_compact(code: u32) -> Result<Self, ThotpError> {
+        if code > 0xffff {
+            return Err(ThotpError::InvalidPolicy(code));
+        }
+
+        let [digits, step] = (code as u16).to_be_bytes();
+
+        if !(6..=10).contains(&digits) {
+            return Err(ThotpError::InvalidDigits);
+        }
+
+        if step == 0 {
+            return Err(ThotpError::InvalidStep);
+        }
+
+        Ok(Self { digits, step })
+    }
+
+    /// Packs the policy into a single integer, see [TotpPolicy::from_compact] for the layout.
+    pub fn to_compact(&self) -> u32 {
+        u32::from(self.digits) << 8 | u32::from(self.step)
+    }
+}
+
+/// The presets [IssuerPresets::builtin] starts with.
+const BUILTIN: [(&str, IssuerPreset); 4] = [
+    ("authy", IssuerPreset::new(Algorithm::Sha1, 7, 10)),
+    ("github", IssuerPreset::new(Algorithm::Sha1, 6, 30)),
+    ("google", IssuerPreset::new(Algorithm::Sha1, 6, 30)),
+    ("microsoft", IssuerPreset::new(Algorithm::Sha1, 6, 30)),
+];
+
+/// A registry mapping issuers to their presets. Issuers are looked up case insensitively and with
+/// surrounding whitespace ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IssuerPresets {
+    presets: HashMap<String, IssuerPreset>,
+}
+
+impl IssuerPresets {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry containing the built in presets of common providers.
+    pub fn builtin() -> Self {
+        let presets = BUILTIN
+            .iter()
+            .map(|(issuer, preset)| (issuer.to_string(), *preset))
+            .collect();
+
+        Self { presets }
+    }
+
+    /// Adds the preset for the given issuer, returning the one it replaced if any.
+    pub fn insert(&mut self, issuer: &str, preset: IssuerPreset) -> Option<IssuerPreset> {
+        self.presets.insert(normalize(issuer), preset)
+    }
+
+    /// Returns the preset for the given issuer, if any.
+    pub fn get(&self, issuer: &str) -> Option<IssuerPreset> {
+        self.presets.get(&normalize(issuer)).copied()
+    }
+}
+
+/// Returns the built in preset for the given issuer, if any. Use [IssuerPresets] to add presets of
+/// additional issuers.
+pub fn preset_for(issuer: &str) -> Option<IssuerPreset> {
+    let issuer = normalize(issuer);
+
+    BUILTIN
+        .iter()
+        .find(|(name, _)| *name == issuer)
+        .map(|(_, preset)| *preset)
+}
+
+/// Normalizes an issuer for the lookup.
+fn normalize(issuer: &str) -> String {
+    issuer.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_presets() {
+        let authy = IssuerPreset::new(Algorithm::Sha1, 7, 10);
+        assert_eq!(preset_for("Authy"), Some(authy));
+        assert_eq!(preset_for(" authy "), Some(authy));
+        assert_eq!(IssuerPresets::builtin().get("AUTHY"), Some(authy));
+        assert_eq!(preset_for("Unknown Corp"), None);
+        assert_eq!(IssuerPresets::new().get("Authy"), None);
+    }
+
+    #[test]
+    fn compact_policy() -> Result<(), ThotpError> {
+        for (digits, step) in [(6, 30), (7, 10), (8, 60), (10, 255), (6, 1)] {
+            let policy = TotpPolicy::new(digits, step);
+            assert_eq!(TotpPolicy::from_compact(policy.to_compact())?, policy);
+        }
+        assert_eq!(TotpPolicy::new(8, 60).to_compact(), 0x083c);
+
+        assert!(matches!(
+            TotpPolicy::from_compact(0x1_061e),
+            Err(ThotpError::InvalidPolicy(0x1_061e))
+        ));
+        assert!(matches!(
+            TotpPolicy::from_compact(u32::MAX),
+            Err(ThotpError::InvalidPolicy(u32::MAX))
+        ));
+        assert!(matches!(
+            TotpPolicy::from_compact(0x051e),
+            Err(ThotpError::InvalidDigits)
+        ));
+        assert!(matches!(
+            TotpPolicy::from_compact(0x0b1e),
+            Err(ThotpError::InvalidDigits)
+        ));
+        assert!(matches!(
+            TotpPolicy::from_compact(0x0600),
+            Err(ThotpError::InvalidStep)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn custom_presets() {
+        let mut presets = IssuerPresets::builtin();
+        let preset = IssuerPreset::new(Algorithm::Sha256, 8, 60);
+
+        assert_eq!(presets.insert("Big Corp", preset), None);
+        assert_eq!(presets.get("big corp"), Some(preset));
+        assert_eq!(preset_for("Big Corp"), None);
+
+        // Built in presets can be replaced
+        let replaced = presets.insert("Authy", preset);
+        assert_eq!(replaced, preset_for("Authy"));
+        assert_eq!(presets.get("Authy"), Some(preset));
+    }
+}
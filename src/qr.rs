@@ -5,9 +5,17 @@
 
 pub use qrcode::EcLevel;
 
+#[cfg(feature = "custom")]
+use super::custom::OtpHash;
+use super::encoding::decode_base32;
+use super::otp_core::ct_eq;
+#[cfg(feature = "custom")]
+use super::otp_core::resolve_timestamp;
 use super::ThotpError;
-use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
-use qrcode::{self, render::svg::Color, QrCode};
+#[cfg(feature = "custom")]
+use digest::core_api::{AlgorithmName, CoreProxy};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use qrcode::{self, render::svg::Color, QrCode, Version};
 use std::fmt::Write;
 
 /// Generates a QR code SVG ready to be scanned by an authenticator app.
@@ -23,12 +31,8 @@ pub fn generate_code_svg(
     height: Option<u32>,
     ec_level: EcLevel,
 ) -> Result<String, ThotpError> {
-    let width = if let Some(width) = width { width } else { 200 };
-    let height = if let Some(height) = height {
-        height
-    } else {
-        200
-    };
+    let width = width.unwrap_or(200);
+    let height = height.unwrap_or(200);
 
     let code = QrCode::with_error_correction_level(otp_uri, ec_level)?;
 
@@ -40,11 +44,441 @@ pub fn generate_code_svg(
         .build())
 }
 
+/// The signature every PNG starts with.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Generates a QR code PNG ready to be scanned by an authenticator app, e.g. where SVGs can not be
+/// displayed. The code is rendered in black and white with at least `size` pixels per side, including
+/// a quiet zone of 4 modules on each side.
+///
+/// The image data is stored uncompressed, as an 8 bit grayscale PNG.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::generate_code_png;
+///
+/// let png = generate_code_png("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP", 200).unwrap();
+///
+/// assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+/// ```
+pub fn generate_code_png(otp_uri: &str, size: u32) -> Result<Vec<u8>, ThotpError> {
+    let code = QrCode::new(otp_uri)?;
+    let modules = code.width();
+    let colors = code.to_colors();
+
+    // The same module size the SVG renderer uses
+    let unit = (size as usize).div_ceil(modules + 8).max(1);
+    let total = (modules + 8) * unit;
+
+    // Every row starts with its filter type, 0 for none
+    let mut pixels = Vec::with_capacity((total + 1) * total);
+    for y in 0..total {
+        pixels.push(0);
+        for x in 0..total {
+            let dark = match (x / unit).checked_sub(4).zip((y / unit).checked_sub(4)) {
+                Some((x, y)) if x < modules && y < modules => {
+                    colors[y * modules + x] == qrcode::Color::Dark
+                }
+                _ => false,
+            };
+            pixels.push(if dark { 0x00 } else { 0xff });
+        }
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(total as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(total as u32).to_be_bytes());
+    // 8 bit grayscale, deflate compression, adaptive filtering and no interlacing
+    ihdr.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_png_chunk(&mut png, b"IHDR", &ihdr);
+    write_png_chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+    write_png_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+/// Generates a QR code PNG just like [generate_code_png] and returns it as a `data:image/png;base64,`
+/// URI, e.g. to inline it in an `<img>` tag of an enrollment page.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::generate_code_data_uri;
+///
+/// let uri = generate_code_data_uri("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP", 200).unwrap();
+/// let html = format!(r#"<img src="{}" alt="Scan me">"#, uri);
+///
+/// assert!(html.starts_with(r#"<img src="data:image/png;base64,"#));
+/// ```
+pub fn generate_code_data_uri(otp_uri: &str, size: u32) -> Result<String, ThotpError> {
+    let png = generate_code_png(otp_uri, size)?;
+    Ok(format!(
+        "data:image/png;base64,{}",
+        data_encoding::BASE64.encode(&png)
+    ))
+}
+
+/// The dimensions of the QR code of an otp uri, as returned by [qr_capacity_info].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QrInfo {
+    /// The QR code version in the range of `1..=40`, a higher version holds more data
+    pub version: u8,
+
+    /// The number of modules per side, not counting the quiet zone
+    pub modules: u32,
+
+    /// The smallest number of pixels per side that renders every module at least 4 pixels wide,
+    /// including a quiet zone of 4 modules on each side
+    pub min_size: u32,
+}
+
+/// Calculates the dimensions of the QR code [generate_code_png] generates for the given otp uri
+/// without rendering it, e.g. to reserve space for it in a layout. Long uris need a higher version
+/// with more modules, so they need more space to stay scannable.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::qr_capacity_info;
+///
+/// let info = qr_capacity_info("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP").unwrap();
+///
+/// assert_eq!(info.version, 3);
+/// assert_eq!(info.modules, 29);
+/// assert_eq!(info.min_size, (29 + 8) * 4);
+/// ```
+pub fn qr_capacity_info(otp_uri: &str) -> Result<QrInfo, ThotpError> {
+    let code = QrCode::new(otp_uri)?;
+
+    let version = match code.version() {
+        Version::Normal(version) | Version::Micro(version) => version as u8,
+    };
+    let modules = code.width() as u32;
+
+    Ok(QrInfo {
+        version,
+        modules,
+        min_size: (modules + 8) * 4,
+    })
+}
+
+/// A TOTP enrollment to distribute on paper with [enrollment_sheet_html]. The recovery codes are
+/// generated and stored by the caller, this crate only lays them out.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Enrollment {
+    /// The otp uri holding the secret and parameters of the credential
+    pub otp_uri: String,
+
+    /// The recovery codes of the account, printed in the given order
+    pub recovery_codes: Vec<String>,
+}
+
+impl std::fmt::Debug for Enrollment {
+    // The uri and the recovery codes are omitted on purpose, they hold the secrets
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Enrollment")
+            .field("recovery_codes", &self.recovery_codes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Generates a printable HTML page for the given enrollment, for organizations handing out MFA
+/// credentials on paper. The page contains the account and issuer, the QR code generated by
+/// [generate_code_png] as an inline image, the Base32 secret for manual entry in groups of 4
+/// characters, the way authenticator apps display it, and the recovery codes.
+///
+/// The page holds the secret and the recovery codes, so it has to be handled as carefully as they
+/// are and never be cached or logged. Returns an error if the otp uri can not be parsed with
+/// [parse_otpauth] or is too long for a QR code.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::{enrollment_sheet_html, Enrollment};
+///
+/// let enrollment = Enrollment {
+///     otp_uri: "otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP".to_string(),
+///     recovery_codes: vec!["1234-5678".to_string(), "8765-4321".to_string()],
+/// };
+///
+/// let html = enrollment_sheet_html(&enrollment).unwrap();
+///
+/// assert!(html.contains("JBSW Y3DP EHPK 3PXP"));
+/// assert!(html.contains("<li><code>1234-5678</code></li>"));
+/// ```
+pub fn enrollment_sheet_html(enrollment: &Enrollment) -> Result<String, ThotpError> {
+    let mut html = credential_html(&enrollment.otp_uri)?;
+
+    html.push_str(concat!(
+        "<h2>Recovery codes</h2>\n",
+        "<p>Each code can be used once if you lose access to your authenticator app.</p>\n",
+        "<ol>\n",
+    ));
+
+    for code in &enrollment.recovery_codes {
+        writeln!(html, "<li><code>{}</code></li>", escape_html(code))?;
+    }
+
+    html.push_str("</ol>\n</body>\n</html>\n");
+    Ok(html)
+}
+
+/// Generates the start of an HTML page for the given otp uri up to the manual entry key, for
+/// [enrollment_sheet_html] and [credential_to_portable_html]. The `<body>` is left open.
+fn credential_html(otp_uri: &str) -> Result<String, ThotpError> {
+    let params = parse_otpauth(otp_uri)?;
+    let qr = generate_code_data_uri(otp_uri, 300)?;
+
+    let secret = data_encoding::BASE32_NOPAD.encode(&params.secret);
+    let grouped = secret
+        .as_bytes()
+        .chunks(4)
+        .map(|group| std::str::from_utf8(group).expect("Base32 is ASCII"))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let title = match params.issuer {
+        Some(ref issuer) => format!("{} ({})", escape_html(issuer), escape_html(&params.account)),
+        None => escape_html(&params.account),
+    };
+
+    let mut html = String::new();
+    write!(
+        html,
+        concat!(
+            "<!DOCTYPE html>\n",
+            "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n",
+            "<style>body {{ font-family: sans-serif; }} code {{ font-size: 1.25em; }}</style>\n",
+            "</head>\n<body>\n<h1>{title}</h1>\n",
+            "<p>Scan the QR code with your authenticator app:</p>\n",
+            "<img src=\"{qr}\" alt=\"QR code of the authenticator secret\" width=\"300\" height=\"300\">\n",
+            "<p>Or enter this key manually:</p>\n<p><code>{grouped}</code></p>\n",
+        ),
+        title = title,
+        qr = qr,
+        grouped = grouped,
+    )?;
+
+    Ok(html)
+}
+
+/// Generates a self contained HTML file for the given credential, e.g. for users to download from a
+/// self service portal and open on another device than the one they scan the QR code with. The file
+/// contains the QR code generated by [generate_code_png] as an inline image and the Base32 secret for
+/// manual entry in groups of 4 characters, so it renders offline without loading anything.
+///
+/// The `account` is the account name shown in the authenticator app, e.g. the user's email or
+/// [Credential::account]. The file holds the secret, so it has to be handled as carefully as the
+/// secret itself.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::{credential_to_portable_html, Credential};
+///
+/// let credential = Credential::from_uri("otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP").unwrap();
+/// let html = credential_to_portable_html(&credential, "john@email.com").unwrap();
+///
+/// assert!(html.contains("<img src=\"data:image/png;base64,"));
+/// assert!(html.contains("JBSW Y3DP EHPK 3PXP"));
+/// ```
+#[cfg(feature = "custom")]
+pub fn credential_to_portable_html(
+    credential: &Credential,
+    account: &str,
+) -> Result<String, ThotpError> {
+    use super::custom::Algorithm;
+
+    let totp = credential.totp();
+    let label = match credential.issuer() {
+        Some(issuer) => format!("{}:{}", issuer, account),
+        None => account.to_string(),
+    };
+
+    let mut uri = format!(
+        "otpauth://totp/{}?secret={}",
+        utf8_percent_encode(&label, NON_ALPHANUMERIC),
+        data_encoding::BASE32_NOPAD.encode(totp.secret())
+    );
+    if let Some(issuer) = credential.issuer() {
+        write!(
+            uri,
+            "&issuer={}",
+            utf8_percent_encode(issuer, NON_ALPHANUMERIC)
+        )?;
+    }
+
+    let algorithm = match totp.algorithm() {
+        Algorithm::Sha1 => "SHA1",
+        Algorithm::Sha256 => "SHA256",
+        Algorithm::Sha512 => "SHA512",
+    };
+    uri_append_params(
+        &mut uri,
+        Some(algorithm),
+        Some(totp.digits()),
+        Some(totp.step()),
+    )?;
+
+    let mut html = credential_html(&uri)?;
+    html.push_str("</body>\n</html>\n");
+    Ok(html)
+}
+
+/// Escapes the characters with a special meaning in HTML.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Appends a PNG chunk with its length and checksum.
+fn write_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let crc = crc32(kind.iter().chain(data));
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps the data in a zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with the default window and no preset dictionary
+    let mut stream = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        stream.push(blocks.peek().is_none() as u8);
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    // The Adler-32 checksum of the uncompressed data
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    stream.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    stream
+}
+
+/// The CRC-32 checksum of PNG chunks.
+fn crc32<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u32 {
+    let crc = bytes.into_iter().fold(u32::MAX, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    });
+    !crc
+}
+
+/// Generates a QR code SVG just like [generate_code_svg], with the given PNG logo overlaid in its
+/// center, e.g. for branded enrollment screens. The code is rendered with at least `size` pixels per
+/// side and the highest error correction level, so the modules covered by the logo can be recovered.
+///
+/// The logo is embedded as is and drawn at its pixel size. To keep the code scannable, a
+/// [ThotpError::InvalidLogo] is returned if the logo is wider or taller than a quarter of the code,
+/// not counting the quiet zone around it, or if it is not a PNG. A logo of that size covers fewer
+/// codewords of each block than its error correction codewords can recover.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::generate_code_svg_with_logo;
+///
+/// // A 1x1 PNG
+/// let logo = data_encoding::BASE64.decode(
+///     b"iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==",
+/// ).unwrap();
+///
+/// let svg = generate_code_svg_with_logo("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP", 200, &logo).unwrap();
+///
+/// assert!(svg.contains("<image"));
+/// ```
+pub fn generate_code_svg_with_logo(
+    otp_uri: &str,
+    size: u32,
+    logo_png: &[u8],
+) -> Result<String, ThotpError> {
+    let (logo_width, logo_height) = png_dimensions(logo_png)?;
+
+    let code = QrCode::with_error_correction_level(otp_uri, EcLevel::H)?;
+    let svg = code
+        .render()
+        .min_dimensions(size, size)
+        .dark_color(Color("#000000"))
+        .light_color(Color("#ffffff"))
+        .build();
+
+    // The same module size the renderer uses, the quiet zone is 4 modules on each side
+    let modules = code.width() as u32;
+    let unit = size.div_ceil(modules + 8).max(1);
+    let total = (modules + 8) * unit;
+    let max_logo = modules * unit / 4;
+
+    if logo_width > max_logo || logo_height > max_logo {
+        return Err(ThotpError::InvalidLogo(format!(
+            "The logo is {}x{} pixels, but may be at most {}x{} to keep the code scannable",
+            logo_width, logo_height, max_logo, max_logo
+        )));
+    }
+
+    let image = format!(
+        r#"<image x="{}" y="{}" width="{}" height="{}" href="data:image/png;base64,{}"/>"#,
+        (total - logo_width) / 2,
+        (total - logo_height) / 2,
+        logo_width,
+        logo_height,
+        data_encoding::BASE64.encode(logo_png)
+    );
+
+    // Draw the logo over the modules, right before the closing tag
+    let end = svg.len() - "</svg>".len();
+    Ok(format!("{}{}</svg>", &svg[..end], image))
+}
+
+/// Reads the width and height of a PNG from its header chunk.
+fn png_dimensions(png: &[u8]) -> Result<(u32, u32), ThotpError> {
+    // The signature is followed by the length and type of the header chunk, then the dimensions
+    if png.len() < 24 || !png.starts_with(PNG_SIGNATURE) || &png[12..16] != b"IHDR" {
+        return Err(ThotpError::InvalidLogo(String::from(
+            "The logo is not a PNG",
+        )));
+    }
+
+    let width = u32::from_be_bytes([png[16], png[17], png[18], png[19]]);
+    let height = u32::from_be_bytes([png[20], png[21], png[22], png[23]]);
+    Ok((width, height))
+}
+
+/// The characters escaped in the secret, everything but unreserved characters and `=`. Base32 secrets
+/// are therefore never escaped, while the `+` and `/` of nonstandard Base64 secrets are.
+const SECRET_ESCAPED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'=')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
 /// Generates an otp uri following [this specification](https://github.com/google/google-authenticator/wiki/Key-Uri-Format)
 ///
 /// The `otp_type` must be either be `"totp"` or `"hotp"` or the function will return an error.
 ///
 /// The `secret` is what gets appended to the otp uri as described in the spec along with `label` and `issuer`.
+/// It is percent encoded if it contains anything but alphanumeric characters, `-`, `.`, `_`, `~` and
+/// `=`, which Base32 secrets never do.
 ///
 /// The `counter` can be used to set the initial counter value for HOTP passwords. If not provided
 /// when generating HOTPs it will default to 0, omitted when generating TOTps.
@@ -61,6 +495,7 @@ pub fn otp_uri(
         )));
     }
 
+    let secret = utf8_percent_encode(secret, SECRET_ESCAPED);
     let label = utf8_percent_encode(label, NON_ALPHANUMERIC);
     let issuer = utf8_percent_encode(issuer, NON_ALPHANUMERIC);
 
@@ -115,6 +550,691 @@ pub fn uri_append_params(
     Ok(())
 }
 
+/// The parameters of an otp uri parsed with [parse_otpauth]. Parameters omitted from the uri are
+/// set to the defaults of the specification, i.e. `SHA1`, 6 digits and a period of 30.
+#[derive(Clone, PartialEq, Eq)]
+pub struct OtpauthParams {
+    /// Either `"totp"` or `"hotp"`
+    pub otp_type: String,
+
+    /// The decoded secret
+    pub secret: Vec<u8>,
+
+    /// The issuer from the `issuer` parameter, or the label prefix if the parameter is omitted
+    pub issuer: Option<String>,
+
+    /// The account name from the label, without the issuer prefix
+    pub account: String,
+
+    /// One of `"SHA1"`, `"SHA256"` or `"SHA512"`
+    pub algorithm: String,
+
+    /// The digit length of the passwords
+    pub digits: u8,
+
+    /// The time step of TOTPs in seconds
+    pub period: u32,
+
+    /// The initial counter of HOTPs, `None` for TOTPs
+    pub counter: Option<u64>,
+}
+
+impl std::fmt::Debug for OtpauthParams {
+    // The secret is omitted on purpose, only its length is shown
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OtpauthParams")
+            .field("otp_type", &self.otp_type)
+            .field("secret", &format_args!("{} bytes", self.secret.len()))
+            .field("issuer", &self.issuer)
+            .field("account", &self.account)
+            .field("algorithm", &self.algorithm)
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .field("counter", &self.counter)
+            .finish()
+    }
+}
+
+#[cfg(feature = "custom")]
+impl OtpauthParams {
+    /// Creates a [Totp](super::totp::Totp) from the parsed secret, algorithm, digits and period,
+    /// returns an error if the parameters belong to a HOTP. Since a [Totp](super::totp::Totp) only
+    /// supports time steps of up to 255 seconds, a longer period returns a
+    /// [ThotpError::InvalidParameter], use [verify_totp_period](super::custom::verify_totp_period)
+    /// for those instead.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::qr::parse_otpauth;
+    ///
+    /// let params = parse_otpauth("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP&algorithm=SHA256").unwrap();
+    /// let totp = params.to_totp().unwrap();
+    ///
+    /// let password = totp.generate(0).unwrap();
+    /// assert!(totp.verify(&password, 0).unwrap().valid);
+    /// ```
+    pub fn to_totp(&self) -> Result<super::totp::Totp, ThotpError> {
+        if self.otp_type != "totp" {
+            return Err(invalid_uri("The otp uri does not belong to a TOTP"));
+        }
+
+        let step = u8::try_from(self.period)
+            .map_err(|_| invalid_parameter("period", &self.period.to_string()))?;
+
+        Ok(super::totp::Totp::new(self.secret.clone())
+            .with_algorithm(self.custom_algorithm()?)
+            .with_digits(self.digits)
+            .with_step(step))
+    }
+
+    /// Creates a [HotpServer](super::server::HotpServer) starting at the parsed counter and accepting
+    /// passwords up to `lookahead` counters after it, returns an error if the parameters belong to a
+    /// TOTP. Since the server does not hold the secret, it and the hashing algorithm still have to be
+    /// provided when verifying.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::custom::{otp_custom, Sha1};
+    /// use thotp::qr::parse_otpauth;
+    ///
+    /// let params = parse_otpauth("otpauth://hotp/john?secret=JBSWY3DPEHPK3PXP&counter=5").unwrap();
+    /// let mut server = params.to_hotp(3).unwrap();
+    ///
+    /// let password = otp_custom::<Sha1>(&params.secret, 7, 6).unwrap();
+    /// assert!(server.verify::<Sha1>(&params.secret, &password).unwrap());
+    /// ```
+    pub fn to_hotp(&self, lookahead: u8) -> Result<super::server::HotpServer, ThotpError> {
+        if self.otp_type != "hotp" {
+            return Err(invalid_uri("The otp uri does not belong to a HOTP"));
+        }
+
+        super::server::HotpServer::new(self.counter.unwrap_or_default(), lookahead, self.digits)
+    }
+
+    /// Maps the parsed algorithm name to an [Algorithm](super::custom::Algorithm).
+    fn custom_algorithm(&self) -> Result<super::custom::Algorithm, ThotpError> {
+        use super::custom::Algorithm;
+
+        match self.algorithm.as_str() {
+            "SHA1" => Ok(Algorithm::Sha1),
+            "SHA256" => Ok(Algorithm::Sha256),
+            "SHA512" => Ok(Algorithm::Sha512),
+            _ => Err(invalid_uri(
+                "Invalid algorithm provided, accepted values are \"SHA1\", \"SHA256\" and \"SHA512\"",
+            )),
+        }
+    }
+}
+
+/// Parses an otp uri following [this specification](https://github.com/google/google-authenticator/wiki/Key-Uri-Format)
+/// into its parameters.
+///
+/// The secret is decoded from Base32, which may be unpadded and lowercase, or if that fails and it
+/// contains characters only Base64 uses, from Base64 as used by some nonstandard uris. The label and the parameter
+/// values are percent decoded and whitespace around the numeric parameters `digits`, `period` and
+/// `counter` is ignored. Returns an error if the uri is malformed, the secret is missing or any of the
+/// known parameters have invalid values, unknown parameters are ignored. Numeric parameters which can
+/// not be parsed result in a [ThotpError::InvalidParameter] containing the offending key and value.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::parse_otpauth;
+///
+/// let params = parse_otpauth(
+///     "otpauth://totp/Big%20Corp:john.doe?secret=JBSWY3DPEHPK3PXP&issuer=Big%20Corp&digits=8",
+/// )
+/// .unwrap();
+///
+/// assert_eq!(params.otp_type, "totp");
+/// assert_eq!(params.secret, b"Hello!\xde\xad\xbe\xef");
+/// assert_eq!(params.issuer.as_deref(), Some("Big Corp"));
+/// assert_eq!(params.account, "john.doe");
+/// assert_eq!(params.algorithm, "SHA1");
+/// assert_eq!(params.digits, 8);
+/// assert_eq!(params.period, 30);
+/// ```
+///
+/// If the issuer appears both as the label prefix and as the `issuer` parameter, the parameter wins,
+/// use [parse_otpauth_strict] to reject uris where the two don't match.
+pub fn parse_otpauth(uri: &str) -> Result<OtpauthParams, ThotpError> {
+    parse_otpauth_with(uri, false)
+}
+
+/// Parses an otp uri just like [parse_otpauth], but returns a [ThotpError::IssuerMismatch] if the
+/// label prefix and the `issuer` parameter are both present and differ, which can indicate a
+/// malformed or spoofed QR code.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::parse_otpauth_strict;
+/// use thotp::ThotpError;
+///
+/// let params =
+///     parse_otpauth_strict("otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP&issuer=Big+Corp");
+/// assert_eq!(params.unwrap().issuer.as_deref(), Some("Big Corp"));
+///
+/// let params = parse_otpauth_strict("otpauth://totp/Evil:john?secret=JBSWY3DPEHPK3PXP&issuer=Big+Corp");
+/// assert!(matches!(params, Err(ThotpError::IssuerMismatch { .. })));
+/// ```
+pub fn parse_otpauth_strict(uri: &str) -> Result<OtpauthParams, ThotpError> {
+    parse_otpauth_with(uri, true)
+}
+
+fn parse_otpauth_with(uri: &str, strict: bool) -> Result<OtpauthParams, ThotpError> {
+    let rest = uri
+        .strip_prefix("otpauth://")
+        .ok_or_else(|| invalid_uri("The otp uri must start with \"otpauth://\""))?;
+
+    let (otp_type, rest) = rest
+        .split_once('/')
+        .ok_or_else(|| invalid_uri("The otp uri is missing a label"))?;
+
+    let otp_type = otp_type.to_lowercase();
+    if otp_type != "totp" && otp_type != "hotp" {
+        return Err(invalid_uri(
+            "Invalid otp type provided, accepted values are \"hotp\" and \"totp\"",
+        ));
+    }
+
+    let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let label = percent_decode(label, true)?;
+
+    let (label_issuer, account) = match label.split_once(':') {
+        Some((issuer, account)) => (Some(issuer.trim().to_string()), account.trim().to_string()),
+        None => (None, label.trim().to_string()),
+    };
+
+    let mut params = OtpauthParams {
+        otp_type,
+        secret: Vec::new(),
+        issuer: label_issuer.clone(),
+        account,
+        algorithm: String::from("SHA1"),
+        digits: 6,
+        period: 30,
+        counter: None,
+    };
+
+    let mut secret = None;
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+        // Secrets never contain spaces, but Base64 ones can contain an unescaped `+`
+        let value = percent_decode(value, key != "secret")?;
+
+        match key {
+            "secret" => secret = Some(value),
+            "issuer" => params.issuer = Some(value),
+            "algorithm" => {
+                let algorithm = value.to_uppercase();
+                if algorithm != "SHA1" && algorithm != "SHA256" && algorithm != "SHA512" {
+                    return Err(invalid_uri(
+                        "Invalid algorithm provided, accepted values are \"SHA1\", \"SHA256\" and \"SHA512\"",
+                    ));
+                }
+                params.algorithm = algorithm;
+            }
+            "digits" => {
+                params.digits = parse_numeric(key, &value)?;
+                if !(6..=10).contains(&params.digits) {
+                    return Err(ThotpError::InvalidDigits);
+                }
+            }
+            "period" => {
+                params.period = parse_numeric(key, &value)?;
+                if params.period == 0 {
+                    return Err(invalid_parameter(key, &value));
+                }
+            }
+            "counter" => params.counter = Some(parse_numeric(key, &value)?),
+            _ => {}
+        }
+    }
+
+    if strict {
+        if let (Some(label), Some(parameter)) = (label_issuer, params.issuer.as_ref()) {
+            if &label != parameter {
+                return Err(ThotpError::IssuerMismatch {
+                    label,
+                    parameter: parameter.clone(),
+                });
+            }
+        }
+    }
+
+    let secret = secret.ok_or_else(|| invalid_uri("The otp uri is missing a secret"))?;
+    params.secret = decode_secret(&secret)?;
+
+    if params.otp_type == "hotp" && params.counter.is_none() {
+        params.counter = Some(0);
+    }
+
+    Ok(params)
+}
+
+/// Parses an otp uri with [parse_otpauth] and describes it in a human readable summary, e.g. for
+/// support tooling. The secret is redacted and only its length is shown.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::describe_otpauth;
+///
+/// let summary = describe_otpauth("otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP").unwrap();
+///
+/// assert_eq!(summary, "TOTP for Example:alice, SHA1, 6 digits, 30s period, 10 byte secret");
+/// ```
+pub fn describe_otpauth(uri: &str) -> Result<String, ThotpError> {
+    let params = parse_otpauth(uri)?;
+
+    let label = match params.issuer {
+        Some(ref issuer) => format!("{}:{}", issuer, params.account),
+        None => params.account.clone(),
+    };
+
+    let moving_factor = match params.counter {
+        Some(counter) => format!("counter {}", counter),
+        None => format!("{}s period", params.period),
+    };
+
+    Ok(format!(
+        "{} for {}, {}, {} digits, {}, {} byte secret",
+        params.otp_type.to_uppercase(),
+        label,
+        params.algorithm,
+        params.digits,
+        moving_factor,
+        params.secret.len()
+    ))
+}
+
+/// Checks whether the given otp uris refer to the same credential. The uris are parsed with [parse_otpauth]
+/// and their type, secret, issuer, account, algorithm, digits and period are compared, so differences
+/// in parameter order, percent encoding or omitted default parameters are ignored.
+///
+/// The secrets are compared in constant time.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::uri_equivalent;
+///
+/// assert!(uri_equivalent(
+///     "otpauth://totp/john?secret=JBSWY3DPEHPK3PXP&issuer=Big%20Corp",
+///     "otpauth://totp/john?issuer=Big+Corp&secret=JBSWY3DPEHPK3PXP&digits=6",
+/// )
+/// .unwrap());
+/// ```
+pub fn uri_equivalent(a: &str, b: &str) -> Result<bool, ThotpError> {
+    let a = parse_otpauth(a)?;
+    let b = parse_otpauth(b)?;
+
+    let secret_eq = ct_eq(&a.secret, &b.secret);
+
+    Ok(secret_eq
+        && a.otp_type == b.otp_type
+        && a.issuer == b.issuer
+        && a.account == b.account
+        && a.algorithm == b.algorithm
+        && a.digits == b.digits
+        && a.period == b.period)
+}
+
+/// The result of [rotate_secret]. The new secret should be persisted alongside the old one until the
+/// grace window ends, after which the old one can be dropped.
+#[cfg(feature = "custom")]
+#[derive(Clone)]
+pub struct SecretRotation<H> {
+    /// The newly generated secret
+    pub new_secret: Vec<u8>,
+
+    /// The otp uri of the new secret
+    pub new_uri: String,
+
+    /// The QR code SVG of the new uri, ready to be scanned by an authenticator app
+    pub new_qr: String,
+
+    /// Accepts passwords of both secrets until the grace window ends and of the new one afterwards
+    pub grace_verifier: super::custom::GraceVerifier<H>,
+}
+
+#[cfg(feature = "custom")]
+impl<H> std::fmt::Debug for SecretRotation<H> {
+    // The secret and the uri and QR code containing it are omitted on purpose
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretRotation")
+            .field("grace_verifier", &self.grace_verifier)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Rotates the TOTP secret of the given account. A new secret at least as long as the old one and no
+/// shorter than the RFC recommended 20 bytes is generated, along with its otp uri, the QR code SVG of
+/// the uri and a [GraceVerifier](super::custom::GraceVerifier) which keeps accepting passwords of the
+/// old secret until the unix timestamp `grace_until`, so users have time to scan the new QR code. The
+/// grace verifier accepts passwords in the range of `[-allowed_drift, allowed_drift]` time slices.
+///
+/// The algorithm, digits and period are added to the uri, see [uri_append_params] for which
+/// authenticators honor them.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, Sha1};
+/// use thotp::qr::{parse_otpauth, rotate_secret};
+///
+/// let old_secret = b"12345678901234567890";
+/// let rotation = rotate_secret::<Sha1>(old_secret, "Big Corp", "john", 2_000, 6, 30, 1).unwrap();
+///
+/// assert_eq!(parse_otpauth(&rotation.new_uri).unwrap().secret, rotation.new_secret);
+/// assert!(rotation.new_qr.contains("<svg"));
+///
+/// let old_password = otp_custom::<Sha1>(old_secret, 1_000 / 30, 6).unwrap();
+/// assert!(rotation.grace_verifier.verify(&old_password, 1_000).unwrap().0);
+/// assert!(!rotation.grace_verifier.verify(&old_password, 3_000).unwrap().0);
+/// ```
+#[cfg(feature = "custom")]
+pub fn rotate_secret<H>(
+    old_secret: &[u8],
+    issuer: &str,
+    account: &str,
+    grace_until: u64,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+) -> Result<SecretRotation<H>, ThotpError>
+where
+    H: OtpHash + CoreProxy,
+    H::Core: AlgorithmName,
+{
+    let new_secret = super::generate_secret(old_secret.len().max(20));
+
+    let label = format!("{}:{}", issuer, account);
+    let encoded = data_encoding::BASE32.encode(&new_secret);
+    let mut new_uri = otp_uri("totp", &encoded, &label, issuer, None)?;
+    let algorithm = super::otp_core::algorithm_name::<H>();
+    uri_append_params(&mut new_uri, Some(&algorithm), Some(digits), Some(step))?;
+    let new_qr = generate_code_svg(&new_uri, None, None, EcLevel::H)?;
+
+    let grace_verifier = super::custom::GraceVerifier::new(
+        old_secret.to_vec(),
+        new_secret.clone(),
+        grace_until,
+        digits,
+        step,
+        allowed_drift,
+    );
+
+    Ok(SecretRotation {
+        new_secret,
+        new_uri,
+        new_qr,
+        grace_verifier,
+    })
+}
+
+/// A TOTP credential parsed once from an otp uri, for keystores persisting the whole uri of every
+/// credential. The secret, algorithm, digits and period are kept so verifying does not parse the uri
+/// again.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, Sha1};
+/// use thotp::qr::Credential;
+///
+/// let credential = Credential::from_uri("otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP").unwrap();
+/// assert_eq!(credential.issuer(), Some("Big Corp"));
+/// assert_eq!(credential.account(), "john");
+///
+/// let password = otp_custom::<Sha1>(b"Hello!\xde\xad\xbe\xef", 1_000_000_000 / 30, 6).unwrap();
+/// assert!(credential.verify(&password, 1_000_000_000, 1).unwrap().valid);
+/// ```
+#[cfg(feature = "custom")]
+#[derive(Debug, Clone)]
+pub struct Credential {
+    totp: super::totp::Totp,
+    issuer: Option<String>,
+    account: String,
+}
+
+#[cfg(feature = "custom")]
+impl Credential {
+    /// Parses the given otp uri with [parse_otpauth], returns an error if it is malformed or belongs
+    /// to a HOTP.
+    pub fn from_uri(uri: &str) -> Result<Self, ThotpError> {
+        let params = parse_otpauth(uri)?;
+
+        Ok(Self {
+            totp: params.to_totp()?,
+            issuer: params.issuer,
+            account: params.account,
+        })
+    }
+
+    /// Reads the otp uri from the given file and parses it with [Credential::from_uri]. Surrounding
+    /// whitespace such as a trailing newline is ignored. The uri read from the file is overwritten
+    /// with 0s once it is parsed.
+    pub fn from_uri_file(path: impl AsRef<std::path::Path>) -> Result<Self, ThotpError> {
+        Self::from_uri_bytes(std::fs::read(path)?)
+    }
+
+    /// Reads the otp uri from the given environment variable and parses it with [Credential::from_uri].
+    /// Surrounding whitespace is ignored. The copy of the uri read from the environment is overwritten
+    /// with 0s once it is parsed, the environment itself is left as is.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::qr::Credential;
+    ///
+    /// std::env::set_var("OTP_URI", "otpauth://totp/john?secret=JBSWY3DPEHPK3PXP");
+    ///
+    /// let credential = Credential::from_env("OTP_URI").unwrap();
+    /// assert_eq!(credential.account(), "john");
+    /// ```
+    pub fn from_env(var: &str) -> Result<Self, ThotpError> {
+        let uri = std::env::var(var).map_err(|source| ThotpError::Env {
+            name: String::from(var),
+            source,
+        })?;
+
+        Self::from_uri_bytes(uri.into_bytes())
+    }
+
+    /// Parses the otp uri from the given bytes and zeroizes them afterwards.
+    fn from_uri_bytes(mut uri: Vec<u8>) -> Result<Self, ThotpError> {
+        let credential = match std::str::from_utf8(&uri) {
+            Ok(parsed) => Self::from_uri(parsed.trim()),
+            Err(_) => Err(invalid_uri("The otp uri contains invalid UTF-8")),
+        };
+
+        super::otp_core::zeroize(&mut uri);
+        credential
+    }
+
+    /// Returns the issuer of the credential, if any.
+    pub fn issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    /// Returns the account name of the credential.
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    /// Returns the [Totp](super::totp::Totp) holding the secret and parameters of the credential.
+    pub fn totp(&self) -> &super::totp::Totp {
+        &self.totp
+    }
+
+    /// Verifies the given password in the range of `[-allowed_drift, allowed_drift]` time slices.
+    ///
+    /// If a `timestamp` of 0 is provided, the current system time will be used for the calculation.
+    #[must_use = "the verification result must be checked"]
+    pub fn verify(
+        &self,
+        password: &str,
+        timestamp: u64,
+        allowed_drift: u8,
+    ) -> Result<super::custom::TotpResult, ThotpError> {
+        self.totp
+            .verify_with_drift(password, timestamp, allowed_drift)
+    }
+}
+
+/// A registry of [Credential]s keyed by account, e.g. for a password manager showing the current
+/// passwords of all its credentials or finding out which credential a pasted password belongs to.
+///
+/// ## Example
+/// ```
+/// use thotp::qr::{Credential, CredentialStore};
+///
+/// let mut store = CredentialStore::new();
+/// store.insert("john", Credential::from_uri("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP").unwrap());
+/// store.insert("jane", Credential::from_uri("otpauth://totp/jane?secret=GEZDGNBVGY3TQOJQ").unwrap());
+///
+/// let codes = store.current_codes(1_000_000_000).unwrap();
+/// let (account, code) = &codes[1];
+///
+/// assert_eq!(account, "john");
+/// assert_eq!(store.which_matches(code, 1_000_000_000, 1).unwrap().as_deref(), Some("john"));
+/// ```
+#[cfg(feature = "custom")]
+#[derive(Debug, Clone, Default)]
+pub struct CredentialStore {
+    credentials: std::collections::BTreeMap<String, Credential>,
+}
+
+#[cfg(feature = "custom")]
+impl CredentialStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of credentials in the store.
+    pub fn len(&self) -> usize {
+        self.credentials.len()
+    }
+
+    /// Returns `true` if the store holds no credentials.
+    pub fn is_empty(&self) -> bool {
+        self.credentials.is_empty()
+    }
+
+    /// Adds the credential of the account, returning the one it replaced if any.
+    pub fn insert(
+        &mut self,
+        account: impl Into<String>,
+        credential: Credential,
+    ) -> Option<Credential> {
+        self.credentials.insert(account.into(), credential)
+    }
+
+    /// Returns the credential of the account, if any.
+    pub fn get(&self, account: &str) -> Option<&Credential> {
+        self.credentials.get(account)
+    }
+
+    /// Removes the credential of the account, returning it if there was one.
+    pub fn remove(&mut self, account: &str) -> Option<Credential> {
+        self.credentials.remove(account)
+    }
+
+    /// Generates the password of every credential for the given unix timestamp, as `(account, password)`
+    /// pairs ordered by account.
+    ///
+    /// If a `timestamp` of 0 is provided, the current system time will be used for the calculation. It
+    /// is read once, so the passwords of all credentials are generated for the same time.
+    pub fn current_codes(&self, timestamp: u64) -> Result<Vec<(String, String)>, ThotpError> {
+        let timestamp = resolve_timestamp(timestamp)?;
+
+        self.credentials
+            .iter()
+            .map(|(account, credential)| {
+                Ok((account.clone(), credential.totp.generate(timestamp)?))
+            })
+            .collect()
+    }
+
+    /// Returns the account of the credential the given password belongs to, in the range of
+    /// `[-allowed_drift, allowed_drift]` time slices, or `None` if it belongs to none of them.
+    ///
+    /// The password is verified against every credential, even after one matched, and each of them
+    /// compares it in constant time, so the time taken does not reveal which one matched. If the
+    /// password matches more than one credential, which becomes likely with many of them, the first
+    /// account in order is returned.
+    ///
+    /// If a `timestamp` of 0 is provided, the current system time will be used for the calculation.
+    #[must_use = "the verification result must be checked"]
+    pub fn which_matches(
+        &self,
+        password: &str,
+        timestamp: u64,
+        allowed_drift: u8,
+    ) -> Result<Option<String>, ThotpError> {
+        let timestamp = resolve_timestamp(timestamp)?;
+        let mut matched = None;
+
+        for (account, credential) in &self.credentials {
+            let result = credential.verify(password, timestamp, allowed_drift)?;
+            if result.valid && matched.is_none() {
+                matched = Some(account.clone());
+            }
+        }
+
+        Ok(matched)
+    }
+}
+
+/// Decodes the secret from Base32, falling back to Base64 for nonstandard uris. The fallback is only
+/// attempted if the secret contains characters only Base64 uses, i.e. `+`, `/`, `0`, `1`, `8`, `9`
+/// or letters of both cases, so a mistyped Base32 secret is never decoded as a different Base64 key.
+/// If neither succeeds, the Base32 error is returned.
+fn decode_secret(secret: &str) -> Result<Vec<u8>, ThotpError> {
+    decode_base32(secret).or_else(|error| {
+        let base64_only = secret.contains(['+', '/', '0', '1', '8', '9'])
+            || (secret.contains(|c: char| c.is_ascii_lowercase())
+                && secret.contains(|c: char| c.is_ascii_uppercase()));
+        if !base64_only {
+            return Err(error);
+        }
+
+        data_encoding::BASE64
+            .decode(secret.as_bytes())
+            .or_else(|_| data_encoding::BASE64_NOPAD.decode(secret.as_bytes()))
+            .map_err(|_| error)
+    })
+}
+
+/// Percent decodes the given uri component, treating `+` as a space if `plus_as_space` is set.
+fn percent_decode(value: &str, plus_as_space: bool) -> Result<String, ThotpError> {
+    let value = match plus_as_space {
+        true => value.replace('+', " "),
+        false => value.to_string(),
+    };
+    percent_decode_str(&value)
+        .decode_utf8()
+        .map(|decoded| decoded.into_owned())
+        .map_err(|_| invalid_uri("The otp uri contains invalid UTF-8"))
+}
+
+/// Parses the value of a numeric uri parameter, ignoring surrounding whitespace.
+fn parse_numeric<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, ThotpError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid_parameter(key, value))
+}
+
+fn invalid_parameter(key: &str, value: &str) -> ThotpError {
+    ThotpError::InvalidParameter {
+        key: String::from(key),
+        value: String::from(value),
+    }
+}
+
+fn invalid_uri(message: &str) -> ThotpError {
+    ThotpError::InvalidUri(String::from(message))
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::encoding::{decode, encode};
@@ -136,6 +1256,757 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parse_uri() -> Result<(), ThotpError> {
+        let secret = encode(b"12345678901234567890", data_encoding::BASE32);
+        let uri = otp_uri(
+            "hotp",
+            &secret,
+            "Big Corp:john.doe@email.com",
+            "Big Corp",
+            Some(7),
+        )?;
+
+        let params = parse_otpauth(&uri)?;
+        assert_eq!(
+            params,
+            OtpauthParams {
+                otp_type: String::from("hotp"),
+                secret: b"12345678901234567890".to_vec(),
+                issuer: Some(String::from("Big Corp")),
+                account: String::from("john.doe@email.com"),
+                algorithm: String::from("SHA1"),
+                digits: 6,
+                period: 30,
+                counter: Some(7),
+            }
+        );
+
+        let params = parse_otpauth(
+            "otpauth://totp/john?secret=gezdgnbvgy3tqojq&algorithm=sha256&digits=8&period=60",
+        )?;
+        assert_eq!(params.secret, b"1234567890");
+        assert_eq!(params.issuer, None);
+        assert_eq!(params.algorithm, "SHA256");
+        assert_eq!(params.digits, 8);
+        assert_eq!(params.period, 60);
+        assert_eq!(params.counter, None);
+
+        assert!(parse_otpauth("https://totp/john?secret=GEZDGNBV").is_err());
+        assert!(parse_otpauth("otpauth://motp/john?secret=GEZDGNBV").is_err());
+        assert!(parse_otpauth("otpauth://totp/john?issuer=Big").is_err());
+        assert!(parse_otpauth("otpauth://totp/john?secret=GEZDGNBV&algorithm=MD5").is_err());
+        assert!(parse_otpauth("otpauth://totp/john?secret=GEZDGNBV&digits=4").is_err());
+        assert!(parse_otpauth("otpauth://totp/john?secret=GEZDGNBV&period=0").is_err());
+        assert!(parse_otpauth("otpauth://totp/john?secret=1!").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn params_debug_redacts_secret() -> Result<(), ThotpError> {
+        let params = parse_otpauth("otpauth://totp/john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ")?;
+        let debug = format!("{:?}", params);
+        assert!(debug.contains("20 bytes"));
+        assert!(debug.contains("john"));
+        assert!(!debug.contains("49, 50"));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_uri_numeric_params() -> Result<(), ThotpError> {
+        let params = parse_otpauth(
+            "otpauth://hotp/john?secret=GEZDGNBV&digits=%206%20&period=+60&counter=%091",
+        )?;
+        assert_eq!(params.digits, 6);
+        assert_eq!(params.period, 60);
+        assert_eq!(params.counter, Some(1));
+
+        match parse_otpauth("otpauth://totp/john?secret=GEZDGNBV&digits=six") {
+            Err(ThotpError::InvalidParameter { key, value }) => {
+                assert_eq!(key, "digits");
+                assert_eq!(value, "six");
+            }
+            _ => panic!("expected an invalid parameter error"),
+        }
+
+        match parse_otpauth("otpauth://totp/john?secret=GEZDGNBV&period=0") {
+            Err(ThotpError::InvalidParameter { key, value }) => {
+                assert_eq!(key, "period");
+                assert_eq!(value, "0");
+            }
+            _ => panic!("expected an invalid parameter error"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_uri_issuer_consistency() -> Result<(), ThotpError> {
+        let matching = "otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP&issuer=Big+Corp";
+        assert_eq!(
+            parse_otpauth_strict(matching)?.issuer.as_deref(),
+            Some("Big Corp")
+        );
+        assert_eq!(parse_otpauth(matching)?.issuer.as_deref(), Some("Big Corp"));
+
+        let mismatching =
+            "otpauth://totp/Evil%20Corp:john?secret=JBSWY3DPEHPK3PXP&issuer=Big%20Corp";
+        match parse_otpauth_strict(mismatching) {
+            Err(ThotpError::IssuerMismatch { label, parameter }) => {
+                assert_eq!(label, "Evil Corp");
+                assert_eq!(parameter, "Big Corp");
+            }
+            other => panic!("Expected an issuer mismatch, got {:?}", other),
+        }
+        // The parameter wins when parsing leniently
+        assert_eq!(
+            parse_otpauth(mismatching)?.issuer.as_deref(),
+            Some("Big Corp")
+        );
+
+        let prefix_only = "otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP";
+        assert_eq!(
+            parse_otpauth_strict(prefix_only)?.issuer.as_deref(),
+            Some("Big Corp")
+        );
+
+        let parameter_only = "otpauth://totp/john?secret=JBSWY3DPEHPK3PXP&issuer=Big%20Corp";
+        assert_eq!(
+            parse_otpauth_strict(parameter_only)?.issuer.as_deref(),
+            Some("Big Corp")
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "custom")]
+    fn params_to_totp() -> Result<(), ThotpError> {
+        use super::super::custom::{otp_custom, Algorithm, Sha256};
+
+        let params = parse_otpauth(
+            "otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP&algorithm=SHA256&digits=8&period=60",
+        )?;
+        let totp = params.to_totp()?;
+        assert_eq!(totp.secret(), params.secret);
+        assert_eq!(totp.algorithm(), Algorithm::Sha256);
+        assert_eq!(totp.digits(), 8);
+        assert_eq!(totp.step(), 60);
+
+        let password = otp_custom::<Sha256>(&params.secret, 1_234_567_890 / 60, 8)?;
+        assert!(totp.verify(&password, 1_234_567_890)?.valid);
+
+        assert!(params.to_hotp(5).is_err());
+
+        // Periods longer than a Totp supports are parsed, but not converted
+        let params = parse_otpauth("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP&period=3600")?;
+        assert_eq!(params.period, 3600);
+        assert!(matches!(
+            params.to_totp(),
+            Err(ThotpError::InvalidParameter { key, .. }) if key == "period"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "custom")]
+    fn params_to_hotp() -> Result<(), ThotpError> {
+        use super::super::custom::{otp_custom, Sha1};
+        use super::super::Counter;
+
+        let params = parse_otpauth("otpauth://hotp/john?secret=JBSWY3DPEHPK3PXP&counter=10")?;
+        let mut server = params.to_hotp(2)?;
+        assert_eq!(server.counter(), Counter::new(10));
+
+        let password = otp_custom::<Sha1>(&params.secret, 12, 6)?;
+        assert!(server.verify::<Sha1>(&params.secret, &password)?);
+        assert_eq!(server.counter(), Counter::new(13));
+
+        assert!(params.to_totp().is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "custom")]
+    fn secret_rotation() -> Result<(), ThotpError> {
+        use super::super::custom::{otp_custom, Sha256};
+
+        let old_secret = b"12345678901234567890123456789012";
+        let rotation = rotate_secret::<Sha256>(old_secret, "Big Corp", "john", 2_000, 8, 30, 0)?;
+        assert_eq!(rotation.new_secret.len(), 32);
+        assert_ne!(rotation.new_secret, old_secret);
+
+        let params = parse_otpauth_strict(&rotation.new_uri)?;
+        assert_eq!(params.secret, rotation.new_secret);
+        assert_eq!(params.issuer.as_deref(), Some("Big Corp"));
+        assert_eq!(params.account, "john");
+        assert_eq!(params.algorithm, "SHA256");
+        assert_eq!(params.digits, 8);
+        assert_eq!(
+            rotation.new_qr,
+            generate_code_svg(&rotation.new_uri, None, None, EcLevel::H)?
+        );
+
+        let verifier = &rotation.grace_verifier;
+        let old_password = otp_custom::<Sha256>(old_secret, 1_000 / 30, 8)?;
+        let new_password = otp_custom::<Sha256>(&rotation.new_secret, 1_000 / 30, 8)?;
+        assert_eq!(verifier.verify(&old_password, 1_000)?, (true, 0));
+        assert_eq!(verifier.verify(&new_password, 1_000)?, (true, 0));
+
+        // Past the grace window only the new secret is accepted
+        let old_password = otp_custom::<Sha256>(old_secret, 3_000 / 30, 8)?;
+        let new_password = otp_custom::<Sha256>(&rotation.new_secret, 3_000 / 30, 8)?;
+        assert!(!verifier.verify(&old_password, 3_000)?.0);
+        assert!(verifier.verify(&new_password, 3_000)?.0);
+
+        // The given drift is used instead of the default one
+        assert!(!verifier.verify(&new_password, 3_030)?.0);
+        let drifting = rotate_secret::<Sha256>(old_secret, "Big Corp", "john", 2_000, 8, 30, 1)?;
+        let new_password = otp_custom::<Sha256>(&drifting.new_secret, 3_000 / 30, 8)?;
+        assert_eq!(
+            drifting.grace_verifier.verify(&new_password, 3_030)?,
+            (true, -1)
+        );
+
+        assert!(!format!("{:?}", rotation).contains("otpauth"));
+        assert!(!format!("{:?}", rotation).contains("svg"));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "custom")]
+    fn credential_from_uri() -> Result<(), ThotpError> {
+        use super::super::custom::{otp_custom, Sha256};
+
+        let credential = Credential::from_uri(
+            "otpauth://totp/Big%20Corp:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA&algorithm=SHA256&digits=8",
+        )?;
+        assert_eq!(credential.issuer(), Some("Big Corp"));
+        assert_eq!(credential.account(), "john");
+        assert_eq!(credential.totp().digits(), 8);
+
+        let secret = b"12345678901234567890123456789012";
+        for timestamp in [59, 1111111109, 1234567890, 2000000000] {
+            let password = otp_custom::<Sha256>(secret, timestamp / 30, 8)?;
+            assert!(credential.verify(&password, timestamp, 0)?.valid);
+
+            let previous = otp_custom::<Sha256>(secret, timestamp / 30 - 1, 8)?;
+            assert_eq!(credential.verify(&previous, timestamp, 1)?.discrepancy, -1);
+            assert!(!credential.verify(&previous, timestamp, 0)?.valid);
+        }
+
+        assert!(Credential::from_uri("otpauth://hotp/john?secret=JBSWY3DPEHPK3PXP").is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "custom")]
+    fn credential_store() -> Result<(), ThotpError> {
+        let mut store = CredentialStore::new();
+        assert!(store.is_empty());
+        assert!(store.current_codes(1111111109)?.is_empty());
+        assert_eq!(store.which_matches("123456", 1111111109, 1)?, None);
+
+        let uris = [
+            ("john", "otpauth://totp/john?secret=JBSWY3DPEHPK3PXP"),
+            ("jane", "otpauth://totp/jane?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8"),
+            (
+                "jim",
+                "otpauth://totp/jim?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA&algorithm=SHA256&period=60",
+            ),
+        ];
+        for (account, uri) in uris {
+            assert!(store.insert(account, Credential::from_uri(uri)?).is_none());
+        }
+        assert_eq!(store.len(), 3);
+
+        let codes = store.current_codes(1111111109)?;
+        let accounts: Vec<_> = codes.iter().map(|(account, _)| account.as_str()).collect();
+        assert_eq!(accounts, ["jane", "jim", "john"]);
+        assert_eq!(codes[0].1, "07081804");
+
+        for (account, code) in &codes {
+            assert_eq!(
+                code,
+                &store.get(account).unwrap().totp().generate(1111111109)?
+            );
+            assert_eq!(
+                store.which_matches(code, 1111111109, 1)?.as_deref(),
+                Some(account.as_str())
+            );
+            assert_eq!(store.which_matches(code, 1111111109 + 600, 1)?, None);
+        }
+
+        // The previous password is only found within the drift
+        let previous = store
+            .get("john")
+            .unwrap()
+            .totp()
+            .generate(1111111109 - 30)?;
+        assert_eq!(
+            store.which_matches(&previous, 1111111109, 1)?.as_deref(),
+            Some("john")
+        );
+        assert_eq!(store.which_matches(&previous, 1111111109, 0)?, None);
+
+        assert!(store.remove("jane").is_some());
+        assert_eq!(store.which_matches("07081804", 1111111109, 1)?, None);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "custom")]
+    fn credential_from_file_and_env() -> Result<(), ThotpError> {
+        use super::super::custom::{otp_custom, Sha1};
+
+        let uri = "otpauth://totp/Big%20Corp:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8";
+        let password = otp_custom::<Sha1>(b"12345678901234567890", 1111111109 / 30, 8)?;
+
+        let path = std::env::temp_dir().join(format!("thotp-uri-{}", std::process::id()));
+        std::fs::write(&path, format!("{}\n", uri))?;
+        let credential = Credential::from_uri_file(&path);
+        std::fs::remove_file(&path)?;
+        let credential = credential?;
+        assert_eq!(credential.account(), "john");
+        assert!(credential.verify(&password, 1111111109, 1)?.valid);
+
+        std::env::set_var("THOTP_TEST_CREDENTIAL_URI", uri);
+        let credential = Credential::from_env("THOTP_TEST_CREDENTIAL_URI")?;
+        assert_eq!(credential.issuer(), Some("Big Corp"));
+        assert!(credential.verify(&password, 1111111109, 1)?.valid);
+
+        assert!(matches!(
+            Credential::from_env("THOTP_TEST_CREDENTIAL_URI_UNSET"),
+            Err(ThotpError::Env { .. })
+        ));
+        assert!(matches!(
+            Credential::from_uri_file(path),
+            Err(ThotpError::Io(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn describe_uri() -> Result<(), ThotpError> {
+        let summary = describe_otpauth(
+            "otpauth://totp/john?secret=JBSWY3DPEHPK3PXP&issuer=Big%20Corp&algorithm=SHA256&digits=8&period=60",
+        )?;
+        assert_eq!(
+            summary,
+            "TOTP for Big Corp:john, SHA256, 8 digits, 60s period, 10 byte secret"
+        );
+        assert!(!summary.contains("JBSWY3DPEHPK3PXP"));
+        assert!(!summary.contains("JBSW"));
+
+        let summary = describe_otpauth("otpauth://hotp/john?secret=JBSWY3DPEHPK3PXP&counter=7")?;
+        assert_eq!(
+            summary,
+            "HOTP for john, SHA1, 6 digits, counter 7, 10 byte secret"
+        );
+
+        assert!(describe_otpauth("otpauth://totp/john").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn uri_secret_escaping() -> Result<(), ThotpError> {
+        // Base32 secrets are never escaped
+        let uri = otp_uri("totp", "JBSWY3DPEHPK3PXP====", "john", "Big Corp", None)?;
+        assert!(uri.contains("secret=JBSWY3DPEHPK3PXP====&"));
+
+        // A Base64 secret containing both `+` and `/`
+        let secret = b"\xfb\xef\xff some secret";
+        let encoded = data_encoding::BASE64.encode(secret);
+        assert!(encoded.contains('+') && encoded.contains('/'));
+
+        let uri = otp_uri("totp", &encoded, "john", "Big Corp", None)?;
+        let escaped = encoded.replace('+', "%2B").replace('/', "%2F");
+        assert!(uri.contains(&format!("secret={}&", escaped)));
+        assert_eq!(parse_otpauth(&uri)?.secret, secret);
+
+        // Unescaped `+` are kept as is in the secret
+        let uri = format!("otpauth://totp/john?secret={}", encoded);
+        assert_eq!(parse_otpauth(&uri)?.secret, secret);
+
+        // Mistyped Base32 secrets are not decoded as Base64, although they would be valid Base64
+        for mistyped in ["JBSWY3DPEHPK3Q", "jbswy3dpehpk3g"] {
+            assert!(data_encoding::BASE64_NOPAD
+                .decode(mistyped.as_bytes())
+                .is_ok());
+            let uri = format!("otpauth://totp/john?secret={}", mistyped);
+            assert!(matches!(parse_otpauth(&uri), Err(ThotpError::Encoding(_))));
+        }
+
+        // Mixed case is only valid in Base64
+        let encoded = data_encoding::BASE64_NOPAD.encode(b"Hello!");
+        assert_eq!(encoded, "SGVsbG8h");
+        let uri = format!("otpauth://totp/john?secret={}", encoded);
+        assert_eq!(parse_otpauth(&uri)?.secret, b"Hello!");
+
+        assert!(matches!(
+            parse_otpauth("otpauth://totp/john?secret=not%20encoded"),
+            Err(ThotpError::Encoding(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn code_data_uri() -> Result<(), ThotpError> {
+        let uri = "otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP&issuer=Big%20Corp";
+        let data_uri = generate_code_data_uri(uri, 200)?;
+
+        let payload = data_uri.strip_prefix("data:image/png;base64,").unwrap();
+        let png = data_encoding::BASE64.decode(payload.as_bytes())?;
+        assert_eq!(png, generate_code_png(uri, 200)?);
+
+        // The chunks and their checksums
+        let mut chunks = Vec::new();
+        let mut rest = png.strip_prefix(PNG_SIGNATURE).unwrap();
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (chunk, crc) = rest[4..8 + len + 4].split_at(4 + len);
+            assert_eq!(crc32(chunk).to_be_bytes(), crc);
+            chunks.push((&chunk[..4], &chunk[4..]));
+            rest = &rest[12 + len..];
+        }
+        let kinds: Vec<_> = chunks.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, [&b"IHDR"[..], b"IDAT", b"IEND"]);
+
+        let (width, height) = png_dimensions(&png)?;
+        let modules = QrCode::new(uri)?.width() as u32;
+        let unit = 200u32.div_ceil(modules + 8);
+        assert_eq!(
+            (width, height),
+            ((modules + 8) * unit, (modules + 8) * unit)
+        );
+        assert!(width >= 200);
+
+        // Inflate the stored blocks
+        let zlib = chunks[1].1;
+        let mut pixels = Vec::new();
+        let mut blocks = &zlib[2..zlib.len() - 4];
+        while !blocks.is_empty() {
+            let len = u16::from_le_bytes([blocks[1], blocks[2]]) as usize;
+            assert_eq!(!len as u16, u16::from_le_bytes([blocks[3], blocks[4]]));
+            pixels.extend_from_slice(&blocks[5..5 + len]);
+            blocks = &blocks[5 + len..];
+        }
+        let row = width as usize + 1;
+        assert_eq!(pixels.len(), row * height as usize);
+
+        // The quiet zone is white and the top left finder pattern starts right after it
+        let quiet = 4 * unit as usize;
+        assert_eq!(pixels[0], 0);
+        assert_eq!(pixels[1], 0xff);
+        assert_eq!(pixels[quiet * row + quiet], 0xff);
+        assert_eq!(pixels[quiet * row + quiet + 1], 0x00);
+        Ok(())
+    }
+
+    #[test]
+    fn capacity_info() -> Result<(), ThotpError> {
+        let short = qr_capacity_info("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP")?;
+        let uri = otp_uri(
+            "totp",
+            &"JBSWY3DPEHPK3PXP".repeat(16),
+            "Big Corp:john.doe.with.a.very.long.address@email.com",
+            "Big Corp",
+            None,
+        )?;
+        let long = qr_capacity_info(&uri)?;
+
+        assert!(long.version > short.version);
+        assert!(long.modules > short.modules);
+        assert!(long.min_size > short.min_size);
+
+        for info in [short, long] {
+            // Every version adds 4 modules to the 21 of version 1
+            assert_eq!(info.modules, 17 + 4 * info.version as u32);
+            assert_eq!(info.min_size, (info.modules + 8) * 4);
+        }
+
+        let png = generate_code_png(&uri, long.min_size)?;
+        assert_eq!(png_dimensions(&png)?, (long.min_size, long.min_size));
+
+        assert!(qr_capacity_info(&"A".repeat(8000)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "custom")]
+    fn portable_credential_html() -> Result<(), ThotpError> {
+        let credential = Credential::from_uri(
+            "otpauth://totp/Big%20Corp:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Big%20Corp&algorithm=SHA256&digits=8&period=60",
+        )?;
+        let html = credential_to_portable_html(&credential, "john@email.com")?;
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<img src=\"data:image/png;base64,"));
+        assert!(html.contains("<code>GEZD GNBV GY3T QOJQ GEZD GNBV GY3T QOJQ</code>"));
+        assert!(html.contains("<title>Big Corp (john@email.com)</title>"));
+        assert!(!html.contains("Recovery codes"));
+
+        // Nothing is loaded from the network
+        assert!(!html.contains("http://") && !html.contains("https://"));
+        assert!(!html.contains("src=\"//"));
+
+        // The QR code holds every parameter of the credential
+        let uri = "otpauth://totp/Big%20Corp%3Ajohn%40email%2Ecom?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Big%20Corp&algorithm=SHA256&digits=8&period=60";
+        assert!(html.contains(&generate_code_data_uri(uri, 300)?));
+
+        let parsed = parse_otpauth(uri)?;
+        assert_eq!(parsed.to_totp()?.to_bytes()?, credential.totp().to_bytes()?);
+        assert_eq!(parsed.account, "john@email.com");
+
+        // A credential without an issuer
+        let credential = Credential::from_uri("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP")?;
+        let html = credential_to_portable_html(&credential, "john")?;
+        assert!(html.contains("<title>john</title>"));
+        assert!(html.contains("<code>JBSW Y3DP EHPK 3PXP</code>"));
+        Ok(())
+    }
+
+    #[test]
+    fn enrollment_sheet() -> Result<(), ThotpError> {
+        let recovery_codes: Vec<_> = (0..10)
+            .map(|i| format!("{:04}-{:04}", i, 9999 - i))
+            .collect();
+        let enrollment = Enrollment {
+            otp_uri: "otpauth://totp/Big%20%26%20Co:%3Cjohn%3E?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Big%20%26%20Co".to_string(),
+            recovery_codes: recovery_codes.clone(),
+        };
+
+        let html = enrollment_sheet_html(&enrollment)?;
+
+        assert!(html.contains("<code>GEZD GNBV GY3T QOJQ GEZD GNBV GY3T QOJQ</code>"));
+        assert_eq!(html.matches("<li>").count(), 10);
+        for code in &recovery_codes {
+            assert!(html.contains(&format!("<li><code>{}</code></li>", code)));
+        }
+
+        // The label is escaped
+        assert!(html.contains("<title>Big &amp; Co (&lt;john&gt;)</title>"));
+        assert!(!html.contains("<john>"));
+
+        // The QR code is the one of the uri
+        let qr = generate_code_data_uri(&enrollment.otp_uri, 300)?;
+        assert!(html.contains(&format!("<img src=\"{}\"", qr)));
+
+        // Secrets that do not fill the last group are not padded
+        let enrollment = Enrollment {
+            otp_uri: "otpauth://totp/john?secret=GEZDGNBV".to_string(),
+            recovery_codes: Vec::new(),
+        };
+        let html = enrollment_sheet_html(&enrollment)?;
+        assert!(html.contains("<code>GEZD GNBV</code>"));
+        assert!(!html.contains("<li>"));
+        assert!(!format!("{:?}", enrollment).contains("GEZD"));
+
+        let enrollment = Enrollment {
+            otp_uri: "https://example.com".to_string(),
+            recovery_codes: Vec::new(),
+        };
+        assert!(enrollment_sheet_html(&enrollment).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn code_with_logo() -> Result<(), ThotpError> {
+        let uri = "otpauth://totp/Big%20Corp:john?secret=JBSWY3DPEHPK3PXP&issuer=Big%20Corp";
+        let mut logo = data_encoding::BASE64.decode(
+            b"iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==",
+        )?;
+
+        let svg = generate_code_svg_with_logo(uri, 200, &logo)?;
+        assert!(svg.starts_with("<?xml") && svg.ends_with("</svg>"));
+        assert!(svg.contains(&data_encoding::BASE64.encode(&logo)));
+
+        // Without the logo it is the same code as the one generated for the uri
+        let start = svg.find("<image").unwrap();
+        let end = svg[start..].find("/>").unwrap() + start + 2;
+        let without_logo = format!("{}{}", &svg[..start], &svg[end..]);
+        assert_eq!(
+            without_logo,
+            generate_code_svg(uri, Some(200), Some(200), EcLevel::H)?
+        );
+
+        // A 16x16 logo is small enough, a 200x200 one is not
+        logo[16..24].copy_from_slice(&[0, 0, 0, 16, 0, 0, 0, 16]);
+        assert!(generate_code_svg_with_logo(uri, 200, &logo)?.contains(r#"width="16" height="16""#));
+        logo[16..24].copy_from_slice(&[0, 0, 0, 200, 0, 0, 0, 200]);
+        assert!(matches!(
+            generate_code_svg_with_logo(uri, 200, &logo),
+            Err(ThotpError::InvalidLogo(_))
+        ));
+
+        assert!(matches!(
+            generate_code_svg_with_logo(uri, 200, b"GIF89a"),
+            Err(ThotpError::InvalidLogo(_))
+        ));
+        Ok(())
+    }
+
+    /// Returns the index of the codeword held by every module of the QR code generated for the uri,
+    /// or `None` for the modules of the functional patterns, along with the block of every codeword.
+    /// The codewords are numbered in the order they are placed, data codewords first.
+    fn codeword_layout(uri: &str) -> (Vec<Option<usize>>, Vec<usize>) {
+        use qrcode::bits::encode_auto;
+        use qrcode::canvas::{Canvas, Module};
+        use qrcode::ec::construct_codewords;
+        use qrcode::types::Color as ModuleColor;
+
+        let mut bits = encode_auto(uri.as_bytes(), EcLevel::H).unwrap();
+        bits.push_terminator(EcLevel::H).unwrap();
+        let version = bits.version();
+        let raw = bits.into_bytes();
+        let (data, ec) = construct_codewords(&raw, version, EcLevel::H).unwrap();
+        let total = data.len() + ec.len();
+        let passes = usize::BITS - total.leading_zeros();
+
+        // Tag every codeword with one bit of its index per pass, both in the raw data to find out how
+        // it is interleaved and on the canvas to find out where it is placed
+        let width = version.width() as usize;
+        let mut modules = vec![Some(0); width * width];
+        let mut raw_index = vec![0; data.len()];
+        for pass in 0..passes {
+            let tag = |i: usize| if i >> pass & 1 == 1 { 0xff } else { 0 };
+
+            let tagged: Vec<u8> = (0..raw.len()).map(tag).collect();
+            let (interleaved, _) = construct_codewords(&tagged, version, EcLevel::H).unwrap();
+            for (index, codeword) in raw_index.iter_mut().zip(interleaved) {
+                *index |= (codeword as usize & 1) << pass;
+            }
+
+            let data: Vec<u8> = (0..data.len()).map(tag).collect();
+            let ec: Vec<u8> = (data.len()..total).map(tag).collect();
+            let mut canvas = Canvas::new(version, EcLevel::H);
+            canvas.draw_all_functional_patterns();
+            canvas.draw_data(&data, &ec);
+
+            for (i, module) in modules.iter_mut().enumerate() {
+                match canvas.get((i % width) as i16, (i / width) as i16) {
+                    Module::Unmasked(ModuleColor::Dark) => *module = module.map(|m| m | 1 << pass),
+                    Module::Unmasked(ModuleColor::Light) => {}
+                    _ => *module = None,
+                }
+            }
+        }
+
+        // The blocks are interleaved one codeword at a time, so the first codeword of every block
+        // comes first and the raw index only decreases once all of them are placed
+        let blocks = (1..raw_index.len())
+            .find(|&i| raw_index[i] < raw_index[i - 1])
+            .unwrap_or(raw_index.len());
+        let starts = &raw_index[..blocks];
+
+        let mut block_of: Vec<usize> = raw_index
+            .iter()
+            .map(|index| starts.iter().filter(|&start| start <= index).count() - 1)
+            .collect();
+        block_of.extend((0..ec.len()).map(|i| i % blocks));
+        (modules, block_of)
+    }
+
+    #[test]
+    fn code_with_logo_error_correction() -> Result<(), ThotpError> {
+        let mut logo = data_encoding::BASE64.decode(
+            b"iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==",
+        )?;
+        let attribute = |svg: &str, tag: &str, name: &str| -> u32 {
+            let tag = &svg[svg.find(tag).unwrap()..];
+            let value = &tag[tag.find(&format!(" {}=\"", name)).unwrap() + name.len() + 3..];
+            value[..value.find('"').unwrap()].parse().unwrap()
+        };
+
+        // Codes of increasing versions, with up to 48 blocks of codewords
+        for secret_len in [16, 64, 256, 1024] {
+            let uri = format!("otpauth://totp/john?secret={}", "A".repeat(secret_len));
+            let code = QrCode::with_error_correction_level(&uri, EcLevel::H)?;
+            let (modules, block_of) = codeword_layout(&uri);
+            let blocks = block_of.iter().max().unwrap() + 1;
+            assert_eq!(modules.len(), code.width() * code.width());
+
+            // Each block can correct half of its error correction codewords
+            let budget = code.max_allowed_errors() / blocks;
+
+            for size in [100u32, 500] {
+                // The largest square logo that is accepted
+                let (mut accepted, mut rejected) = (1, size + 1);
+                while rejected - accepted > 1 {
+                    let side = (accepted + rejected) / 2;
+                    logo[16..24]
+                        .copy_from_slice(&[side.to_be_bytes(), side.to_be_bytes()].concat());
+                    match generate_code_svg_with_logo(&uri, size, &logo) {
+                        Ok(_) => accepted = side,
+                        Err(_) => rejected = side,
+                    }
+                }
+                logo[16..24]
+                    .copy_from_slice(&[accepted.to_be_bytes(), accepted.to_be_bytes()].concat());
+                let svg = generate_code_svg_with_logo(&uri, size, &logo)?;
+
+                let unit = attribute(&svg, "<svg", "width") / (code.width() as u32 + 8);
+                let (x, y) = (
+                    attribute(&svg, "<image", "x"),
+                    attribute(&svg, "<image", "y"),
+                );
+                let (width, height) = (
+                    attribute(&svg, "<image", "width"),
+                    attribute(&svg, "<image", "height"),
+                );
+
+                // Every codeword a module of which is even partially covered counts as an error
+                let mut covered = std::collections::BTreeSet::new();
+                for (i, codeword) in modules.iter().enumerate() {
+                    let left = (i % code.width() + 4) as u32 * unit;
+                    let top = (i / code.width() + 4) as u32 * unit;
+                    let overlaps =
+                        left < x + width && left + unit > x && top < y + height && top + unit > y;
+                    if let (true, Some(codeword)) = (overlaps, codeword) {
+                        covered.insert(*codeword);
+                    }
+                }
+
+                for block in 0..blocks {
+                    let errors = covered.iter().filter(|&&c| block_of[c] == block).count();
+                    assert!(
+                        errors <= budget,
+                        "{} of the codewords of block {} are covered, {} can be corrected",
+                        errors,
+                        block,
+                        budget
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn uri_equivalence() -> Result<(), ThotpError> {
+        assert!(uri_equivalent(
+            "otpauth://totp/Big%20Corp:john?secret=GEZDGNBVGY3TQOJQ&issuer=Big%20Corp&algorithm=SHA1",
+            "otpauth://totp/Big+Corp%3Ajohn?issuer=Big+Corp&period=30&secret=GEZDGNBVGY3TQOJQ",
+        )?);
+
+        assert!(!uri_equivalent(
+            "otpauth://totp/john?secret=GEZDGNBVGY3TQOJQ&issuer=Big%20Corp",
+            "otpauth://totp/john?secret=GEZDGNBVGY3TQOJR&issuer=Big%20Corp",
+        )?);
+
+        assert!(!uri_equivalent(
+            "otpauth://totp/john?secret=GEZDGNBVGY3TQOJQ&issuer=Big%20Corp",
+            "otpauth://totp/john?secret=GEZDGNBVGY3TQOJQ&issuer=Big%20Corp&digits=8",
+        )?);
+
+        // Long periods are compared as well
+        assert!(!uri_equivalent(
+            "otpauth://totp/john?secret=GEZDGNBVGY3TQOJQ&period=300",
+            "otpauth://totp/john?secret=GEZDGNBVGY3TQOJQ&period=600",
+        )?);
+
+        assert!(uri_equivalent("otpauth://totp/john?secret=GEZDGNBV", "invalid").is_err());
+        Ok(())
+    }
+
     // These tests don't assert anything, but are useful for debugging qr codes and codes from
     // the authenticator
     #[test]
@@ -0,0 +1,1839 @@
+//! Contains stateful helpers for servers verifying OTPs, such as replay protection for TOTPs,
+//! counter tracking for HOTPs and locking out credentials after too many failed attempts.
+//!
+//! The types in this module keep track of what has been accepted so far and never hold the
+//! secrets, they are always provided by the caller when verifying.
+
+use super::custom::{otp_custom, verify_totp_custom, HmacKey, OtpHash, TotpResult};
+use super::otp_core::{
+    checked_time_slice, ct_eq, resolve_timestamp, ALLOWED_DRIFT, DIGITS_DEFAULT, TIME_STEP,
+};
+use super::totp::Totp;
+pub use super::VerifyOutcome;
+use super::{Counter, ThotpError};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The outcome of a verification performed by a [TotpReplayGuard].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use = "the verification result must be checked"]
+pub enum TotpGuardOutcome {
+    /// The password was valid and its time slice was never accepted before. The `drift` is the
+    /// number of time slices the password deviates from the current one.
+    Accepted { drift: i16 },
+
+    /// The password was valid, but its time slice is not newer than the last accepted one.
+    Replayed,
+
+    /// The password did not match any time slice in the allowed drift.
+    Invalid,
+}
+
+/// Keeps track of the newest time slice a TOTP was accepted for, rejecting any password whose
+/// time slice is not newer than it. This prevents the same password, or an older one that is
+/// still in the allowed drift, from being used twice.
+///
+/// A guard is meant to be kept per credential.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, Sha1};
+/// use thotp::server::{TotpGuardOutcome, TotpReplayGuard};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 1_000_000_000 / 30, 6).unwrap();
+///
+/// let mut guard = TotpReplayGuard::new();
+///
+/// let outcome = guard
+///     .verify_and_advance::<Sha1>(secret, &password, 1_000_000_000, 6, 30, 1)
+///     .unwrap();
+/// assert_eq!(outcome, TotpGuardOutcome::Accepted { drift: 0 });
+///
+/// let outcome = guard
+///     .verify_and_advance::<Sha1>(secret, &password, 1_000_000_000, 6, 30, 1)
+///     .unwrap();
+/// assert_eq!(outcome, TotpGuardOutcome::Replayed);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TotpReplayGuard {
+    watermark: Option<u64>,
+}
+
+impl TotpReplayGuard {
+    /// The version of the state written by [TotpReplayGuard::serialize_state].
+    pub const STATE_VERSION: u8 = 1;
+
+    /// Creates a guard that has not accepted any passwords yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a guard with the given time slice as the newest accepted one.
+    pub fn with_watermark(watermark: u64) -> Self {
+        Self {
+            watermark: Some(watermark),
+        }
+    }
+
+    /// Returns the newest accepted time slice, if any.
+    pub fn watermark(&self) -> Option<u64> {
+        self.watermark
+    }
+
+    /// Serializes the watermark so the guard can be restored with [TotpReplayGuard::from_state],
+    /// e.g. to keep the replay protection across a reload.
+    ///
+    /// The state starts with a version byte, currently [TotpReplayGuard::STATE_VERSION], followed by
+    /// a `0` byte if no password was accepted yet, otherwise a `1` byte and the big endian watermark.
+    pub fn serialize_state(&self) -> Vec<u8> {
+        let mut state = vec![Self::STATE_VERSION];
+        match self.watermark {
+            Some(watermark) => {
+                state.push(1);
+                state.extend_from_slice(&watermark.to_be_bytes());
+            }
+            None => state.push(0),
+        }
+        state
+    }
+
+    /// Restores a guard from a state created with [TotpReplayGuard::serialize_state], returns a
+    /// [ThotpError::InvalidState] if the state is malformed or a [ThotpError::UnsupportedVersion] if
+    /// it was written by a newer version.
+    pub fn from_state(state: &[u8]) -> Result<Self, ThotpError> {
+        match state {
+            [1, 0] => Ok(Self::new()),
+            [1, 1, watermark @ ..] => Ok(Self::with_watermark(read_u64(watermark)?)),
+            [1, ..] | [] => Err(ThotpError::InvalidState),
+            [version, ..] => Err(ThotpError::UnsupportedVersion(*version)),
+        }
+    }
+
+    /// Verifies the password in the range of `[-allowed_drift, allowed_drift]` time slices and,
+    /// if it matches a slice newer than the watermark, moves the watermark to that slice.
+    ///
+    /// The watermark is moved to the slice the password matched and not the current one,
+    /// so an older password that is still in the allowed drift cannot be accepted after it.
+    /// If the password matches several slices, the one closest to the current slice is used,
+    /// like [verify_totp_custom] does.
+    /// Since this takes `&mut self` the check and the update can never interleave with
+    /// another verification.
+    ///
+    /// If a `timestamp` of 0 is provided, the current system time will be used for the calculation.
+    #[must_use = "the verification result must be checked"]
+    pub fn verify_and_advance<H>(
+        &mut self,
+        secret: &[u8],
+        password: &str,
+        timestamp: u64,
+        digits: u8,
+        step: u8,
+        allowed_drift: u8,
+    ) -> Result<TotpGuardOutcome, ThotpError>
+    where
+        H: OtpHash,
+    {
+        let timestamp = resolve_timestamp(timestamp)?;
+        let nonce = checked_time_slice(timestamp, step)?;
+
+        let (valid, drift) =
+            verify_totp_custom::<H>(password, secret, timestamp, digits, step, allowed_drift)?;
+        if !valid {
+            return Ok(TotpGuardOutcome::Invalid);
+        }
+
+        // The matched slice is within the window, which was clamped to the u64 range
+        let n = (nonce as i128 + drift as i128) as u64;
+        if matches!(self.watermark, Some(watermark) if n <= watermark) {
+            return Ok(TotpGuardOutcome::Replayed);
+        }
+        self.watermark = Some(n);
+
+        Ok(TotpGuardOutcome::Accepted { drift })
+    }
+}
+
+/// Keeps track of the HOTP counter of a single credential, advancing it past every accepted password.
+///
+/// The counter never wraps around. The lookahead stops at `u64::MAX` and a password accepted for that
+/// counter results in a [ThotpError::CounterOverflow] since the counter can not be advanced past it.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, Sha1};
+/// use thotp::server::HotpServer;
+/// use thotp::Counter;
+///
+/// let secret = b"super secret";
+/// let mut server = HotpServer::new(Counter::new(0), 5, 6).unwrap();
+///
+/// let password = otp_custom::<Sha1>(secret, 3, 6).unwrap();
+///
+/// assert!(server.verify::<Sha1>(secret, &password).unwrap());
+/// assert_eq!(server.counter(), Counter::new(4));
+///
+/// // The same password can not be used again
+/// assert!(!server.verify::<Sha1>(secret, &password).unwrap());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HotpServer {
+    counter: Counter,
+    lookahead: u8,
+    digits: u8,
+}
+
+impl HotpServer {
+    /// The version of the state written by [HotpServer::serialize_state].
+    pub const STATE_VERSION: u8 = 1;
+
+    /// Creates a server starting at the given counter, accepting passwords from up to
+    /// `lookahead` counters after it. Returns a [ThotpError::InvalidDigits] if the digits are not in
+    /// the range of `6..=10`.
+    pub fn new(counter: impl Into<Counter>, lookahead: u8, digits: u8) -> Result<Self, ThotpError> {
+        if !(6..=10).contains(&digits) {
+            return Err(ThotpError::InvalidDigits);
+        }
+
+        Ok(Self {
+            counter: counter.into(),
+            lookahead,
+            digits,
+        })
+    }
+
+    /// Returns the counter the next password is expected for.
+    pub fn counter(&self) -> Counter {
+        self.counter
+    }
+
+    /// Serializes the counter, lookahead and digits so the server can be restored with
+    /// [HotpServer::from_state], e.g. to keep the counter across a reload. The secret is not part
+    /// of the server and has to be provided again when verifying.
+    ///
+    /// The state starts with a version byte, currently [HotpServer::STATE_VERSION], followed by the
+    /// big endian counter and the lookahead and digits bytes.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::server::HotpServer;
+    ///
+    /// let server = HotpServer::new(42, 5, 6).unwrap();
+    /// let restored = HotpServer::from_state(&server.serialize_state()).unwrap();
+    ///
+    /// assert_eq!(server, restored);
+    /// ```
+    pub fn serialize_state(&self) -> Vec<u8> {
+        let mut state = vec![Self::STATE_VERSION];
+        state.extend_from_slice(&self.counter.as_u64().to_be_bytes());
+        state.extend_from_slice(&[self.lookahead, self.digits]);
+        state
+    }
+
+    /// Restores a server from a state created with [HotpServer::serialize_state], returns a
+    /// [ThotpError::InvalidState] if the state is malformed or its digits are not in the range of
+    /// `6..=10`, or a [ThotpError::UnsupportedVersion] if it was written by a newer version.
+    pub fn from_state(state: &[u8]) -> Result<Self, ThotpError> {
+        match state {
+            [1, counter @ .., lookahead, digits] => {
+                Self::new(read_u64(counter)?, *lookahead, *digits)
+                    .map_err(|_| ThotpError::InvalidState)
+            }
+            [1, ..] | [] => Err(ThotpError::InvalidState),
+            [version, ..] => Err(ThotpError::UnsupportedVersion(*version)),
+        }
+    }
+
+    /// Generates passwords in the range of `[counter, counter + lookahead]` and compares them to the
+    /// given one. If any of them match, the counter is set to the one after the match.
+    #[must_use = "the verification result must be checked"]
+    pub fn verify<H>(&mut self, secret: &[u8], password: &str) -> Result<bool, ThotpError>
+    where
+        H: OtpHash,
+    {
+        match self.find_match::<H>(self.counter, secret, password)? {
+            Some(next) => {
+                self.counter = next;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Verifies the password like [HotpServer::verify], but against the counter kept in the given
+    /// store instead of the one of the server, e.g. to share it between servers. The counter is
+    /// only advanced with [CounterStore::compare_and_set], so of two concurrent verifications of the
+    /// same password only one is accepted.
+    ///
+    /// If the counter was advanced concurrently, the password is verified again against the new
+    /// counter, so a password that was used in the meantime is rejected.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::custom::{otp_custom, Sha1};
+    /// use thotp::server::{AtomicCounterStore, CounterStore, HotpServer};
+    /// use thotp::Counter;
+    ///
+    /// let secret = b"super secret";
+    /// let server = HotpServer::new(0, 5, 6).unwrap();
+    /// let store = AtomicCounterStore::new(0);
+    ///
+    /// let password = otp_custom::<Sha1>(secret, 3, 6).unwrap();
+    ///
+    /// assert!(server.verify_shared::<Sha1, _>(&store, secret, &password).unwrap());
+    /// assert_eq!(store.load().unwrap(), Counter::new(4));
+    /// assert!(!server.verify_shared::<Sha1, _>(&store, secret, &password).unwrap());
+    /// ```
+    #[must_use = "the verification result must be checked"]
+    pub fn verify_shared<H, S>(
+        &self,
+        store: &S,
+        secret: &[u8],
+        password: &str,
+    ) -> Result<bool, ThotpError>
+    where
+        H: OtpHash,
+        S: CounterStore + ?Sized,
+    {
+        loop {
+            let expected = store.load()?;
+
+            let next = match self.find_match::<H>(expected, secret, password)? {
+                Some(next) => next,
+                None => return Ok(false),
+            };
+
+            if store.compare_and_set(expected, next)? {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Returns the counter after the one in `[start, start + lookahead]` whose password matches the
+    /// given one.
+    fn find_match<H>(
+        &self,
+        start: Counter,
+        secret: &[u8],
+        password: &str,
+    ) -> Result<Option<Counter>, ThotpError>
+    where
+        H: OtpHash,
+    {
+        if password.len() != self.digits as usize {
+            return Ok(None);
+        }
+
+        for offset in 0..=self.lookahead as u64 {
+            let current = match start.as_u64().checked_add(offset) {
+                Some(current) => Counter::new(current),
+                None => break,
+            };
+
+            let pass = otp_custom::<H>(secret, current.as_u64(), self.digits)?;
+
+            if ct_eq(pass.as_bytes(), password.as_bytes()) {
+                return current
+                    .checked_next()
+                    .map(Some)
+                    .ok_or(ThotpError::CounterOverflow);
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Stores the HOTP counter of a credential for [HotpServer::verify_shared], e.g. in a database so
+/// the counter is shared between servers.
+///
+/// Implementations must make [CounterStore::compare_and_set] atomic: the counter must only be
+/// replaced if it still equals `expected` at the moment it is written, with no other write in
+/// between the comparison and the write, e.g. with an `UPDATE ... WHERE counter = expected` or a
+/// transaction. Otherwise two concurrent verifications can both accept the same password.
+pub trait CounterStore {
+    /// Returns the current counter.
+    fn load(&self) -> Result<Counter, ThotpError>;
+
+    /// Sets the counter to `new` if it currently is `expected`, returns `false` without changing it
+    /// otherwise.
+    fn compare_and_set(&self, expected: Counter, new: Counter) -> Result<bool, ThotpError>;
+}
+
+/// A [CounterStore] keeping the counter in memory in an atomic integer, shared between threads.
+#[derive(Debug, Default)]
+pub struct AtomicCounterStore {
+    counter: AtomicU64,
+}
+
+impl AtomicCounterStore {
+    /// Creates a store starting at the given counter.
+    pub fn new(counter: impl Into<Counter>) -> Self {
+        Self {
+            counter: AtomicU64::new(counter.into().as_u64()),
+        }
+    }
+}
+
+impl CounterStore for AtomicCounterStore {
+    fn load(&self) -> Result<Counter, ThotpError> {
+        Ok(Counter::new(self.counter.load(Ordering::Acquire)))
+    }
+
+    fn compare_and_set(&self, expected: Counter, new: Counter) -> Result<bool, ThotpError> {
+        Ok(self
+            .counter
+            .compare_exchange(
+                expected.as_u64(),
+                new.as_u64(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok())
+    }
+}
+
+/// A bounded, in memory record of recently accepted passwords, used to flag passwords that are
+/// accepted more than once in a short time span as possible replays without any persistent storage.
+///
+/// The pairs of credential identifiers and passwords are recorded in two bloom filters, each sized
+/// for `capacity` entries with a false positive rate of at most about 1%. Pairs are recorded in the current
+/// filter, which becomes the previous one once it is `ttl` seconds old or holds `capacity` entries,
+/// dropping the filter before it. A pair is therefore flagged if it was seen in the last `ttl`
+/// seconds, and possibly for up to twice as long, while the memory used never exceeds the two
+/// filters. Only the bits of a keyed hash are kept, never the password itself.
+///
+/// Different pairs can set the same bits, so the cache is only advisory and should not replace a
+/// [TotpReplayGuard] where replays must be rejected.
+#[derive(Debug, Clone)]
+pub struct RecentCodeCache {
+    capacity: usize,
+    ttl: u64,
+    state: RandomState,
+    current: BloomFilter,
+    previous: BloomFilter,
+}
+
+/// The number of bits set in a [BloomFilter] for each entry.
+const BLOOM_HASHES: u64 = 7;
+
+/// The number of bits a [BloomFilter] keeps per entry, which with [BLOOM_HASHES] gives a false
+/// positive rate of about 1% once it is full.
+const BLOOM_BITS_PER_ENTRY: usize = 10;
+
+/// The least number of bits a [BloomFilter] keeps, so the filters of small caches rarely flag a pair
+/// that was never recorded.
+const BLOOM_BITS_MIN: usize = 1024;
+
+/// A generation of a [RecentCodeCache].
+#[derive(Debug, Clone)]
+struct BloomFilter {
+    started_at: u64,
+    len: usize,
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    fn new(capacity: usize, started_at: u64) -> Self {
+        let bits = capacity
+            .saturating_mul(BLOOM_BITS_PER_ENTRY)
+            .max(BLOOM_BITS_MIN)
+            .div_ceil(64);
+
+        Self {
+            started_at,
+            len: 0,
+            bits: vec![0; bits],
+        }
+    }
+
+    /// Returns the positions of the bits of the hash in a filter of `words` words, derived with
+    /// double hashing.
+    fn positions(words: usize, hash: u64) -> impl Iterator<Item = usize> {
+        let len = words as u64 * 64;
+        let step = hash.rotate_left(32) | 1;
+
+        (0..BLOOM_HASHES).map(move |i| (hash.wrapping_add(i.wrapping_mul(step)) % len) as usize)
+    }
+
+    fn contains(&self, hash: u64) -> bool {
+        self.len > 0
+            && Self::positions(self.bits.len(), hash)
+                .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    fn insert(&mut self, hash: u64) {
+        for bit in Self::positions(self.bits.len(), hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    fn clear(&mut self, started_at: u64) {
+        self.bits.fill(0);
+        self.len = 0;
+        self.started_at = started_at;
+    }
+}
+
+impl RecentCodeCache {
+    /// Creates an empty cache recording at most `capacity` entries per filter, for at least `ttl`
+    /// seconds each. A `ttl` covering the whole allowed drift, i.e. `step * (2 * allowed_drift + 1)`,
+    /// is recommended.
+    pub fn new(capacity: usize, ttl: u64) -> Self {
+        Self {
+            capacity,
+            ttl,
+            state: RandomState::new(),
+            current: BloomFilter::new(capacity, 0),
+            previous: BloomFilter::new(capacity, 0),
+        }
+    }
+
+    /// Returns the number of entries in the cache, including expired ones which were not dropped yet.
+    pub fn len(&self) -> usize {
+        self.current.len + self.previous.len
+    }
+
+    /// Returns `true` if the cache has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Records the password as accepted for the credential at the given unix timestamp. Returns
+    /// `true` if the same pair was probably already recorded in the last `ttl` seconds.
+    pub fn check_and_insert(&mut self, secret_id: &str, password: &str, timestamp: u64) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+
+        // Drop the generations whose entries are all expired
+        let age = timestamp.saturating_sub(self.current.started_at);
+        if age >= self.ttl.saturating_mul(2) {
+            self.previous.clear(timestamp);
+            self.current.clear(timestamp);
+        } else if age >= self.ttl {
+            self.rotate(timestamp);
+        }
+
+        let hash = self.state.hash_one((secret_id, password));
+
+        if self.current.contains(hash) || self.previous.contains(hash) {
+            return true;
+        }
+
+        if self.current.len >= self.capacity {
+            self.rotate(timestamp);
+        }
+
+        self.current.insert(hash);
+
+        false
+    }
+
+    /// Makes the current filter the previous one and starts an empty one at the timestamp.
+    fn rotate(&mut self, timestamp: u64) {
+        std::mem::swap(&mut self.current, &mut self.previous);
+        self.current.clear(timestamp);
+    }
+}
+
+/// A bounded, in memory cache of the passwords generated for the time slices in the allowed drift of
+/// recently verified credentials, so verifying another password of the same credential in the same
+/// time slice is a string comparison instead of computing the HMACs again.
+///
+/// Passwords of time slices which fell out of the allowed drift are dropped and once the cache holds
+/// the passwords of `capacity` credentials, the least recently used credential is evicted.
+///
+/// Unlike the [RecentCodeCache], this cache holds the valid passwords themselves, so anyone able to
+/// read the memory of the process can read the currently valid passwords of the cached credentials.
+///
+/// The passwords are cached by the credential identifier along with a keyed hash of the secret, the
+/// digit length and the algorithm, so the passwords of a rotated secret are never returned for the
+/// new one and are evicted like any other unused credential.
+#[derive(Debug, Clone, Default)]
+pub struct GeneratedCodeCache {
+    capacity: usize,
+    state: RandomState,
+    /// The cached passwords by their credential, along with the generation they were last used in
+    codes: HashMap<CodeKey, (u64, Vec<(u64, String)>)>,
+    /// The cached credentials by the generation they were last used in, the first one is the least
+    /// recently used
+    order: BTreeMap<u64, CodeKey>,
+    generation: u64,
+}
+
+/// Identifies the passwords of a credential in a [GeneratedCodeCache].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CodeKey {
+    secret_id: String,
+    secret: u64,
+    digits: u8,
+    algorithm: &'static str,
+}
+
+impl GeneratedCodeCache {
+    /// Creates an empty cache holding the passwords of at most `capacity` credentials.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: RandomState::new(),
+            codes: HashMap::with_capacity(capacity),
+            order: BTreeMap::new(),
+            generation: 0,
+        }
+    }
+
+    /// Returns the number of credentials in the cache.
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    /// Returns `true` if the cache has no credentials.
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    /// Returns the key the passwords of the credential are cached by.
+    fn key<H>(&self, secret_id: &str, secret: &[u8], digits: u8) -> CodeKey {
+        CodeKey {
+            secret_id: secret_id.to_string(),
+            secret: self.state.hash_one(secret),
+            digits,
+            algorithm: std::any::type_name::<H>(),
+        }
+    }
+
+    /// Returns the cached password of the credential for the time slice, dropping the passwords of
+    /// slices older than `oldest`.
+    fn get(&mut self, key: &CodeKey, slice: u64, oldest: u64) -> Option<String> {
+        let (_, codes) = self.codes.get_mut(key)?;
+        codes.retain(|(cached, _)| *cached >= oldest);
+
+        let code = codes
+            .iter()
+            .find(|(cached, _)| *cached == slice)
+            .map(|(_, code)| code.clone())?;
+
+        self.touch(key);
+        Some(code)
+    }
+
+    /// Caches the password of the credential for the time slice, dropping the passwords of slices
+    /// older than `oldest`.
+    fn insert(&mut self, key: &CodeKey, slice: u64, code: String, oldest: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        match self.codes.get_mut(key) {
+            Some((_, codes)) => {
+                codes.retain(|(cached, _)| *cached >= oldest);
+                codes.push((slice, code));
+                self.touch(key);
+            }
+            None => {
+                if self.codes.len() >= self.capacity {
+                    if let Some((_, evicted)) = self.order.pop_first() {
+                        self.codes.remove(&evicted);
+                    }
+                }
+                self.generation += 1;
+                self.codes
+                    .insert(key.clone(), (self.generation, vec![(slice, code)]));
+                self.order.insert(self.generation, key.clone());
+            }
+        }
+    }
+
+    /// Marks the credential as the most recently used one.
+    fn touch(&mut self, key: &CodeKey) {
+        if let Some((used, _)) = self.codes.get_mut(key) {
+            if let Some(cached) = self.order.remove(used) {
+                self.generation += 1;
+                *used = self.generation;
+                self.order.insert(self.generation, cached);
+            }
+        }
+    }
+}
+
+/// Verifies TOTPs of many credentials with the same algorithm, digit length, time step and allowed drift.
+///
+/// Optionally consults a [RecentCodeCache] and flags a password which was already accepted for the
+/// same credential with [TotpResult::suspected_replay], as well as a [GeneratedCodeCache] to avoid
+/// computing the same passwords again.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, Sha1};
+/// use thotp::server::{RecentCodeCache, TotpVerifier};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 1_000_000_000 / 30, 6).unwrap();
+///
+/// let mut verifier = TotpVerifier::<Sha1>::new(6, 30, 1).with_recent_codes(RecentCodeCache::new(1024, 90));
+///
+/// let result = verifier.verify("john", secret, &password, 1_000_000_000).unwrap();
+/// assert!(result.valid && !result.suspected_replay);
+///
+/// let result = verifier.verify("john", secret, &password, 1_000_000_010).unwrap();
+/// assert!(result.valid && result.suspected_replay);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TotpVerifier<H> {
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    recent_codes: Option<RecentCodeCache>,
+    generated_codes: Option<GeneratedCodeCache>,
+    _hash: PhantomData<fn() -> H>,
+}
+
+impl<H> TotpVerifier<H>
+where
+    H: OtpHash,
+{
+    /// Creates a verifier accepting passwords in the range of `[-allowed_drift, allowed_drift]` time slices.
+    pub fn new(digits: u8, step: u8, allowed_drift: u8) -> Self {
+        Self {
+            digits,
+            step,
+            allowed_drift,
+            recent_codes: None,
+            generated_codes: None,
+            _hash: PhantomData,
+        }
+    }
+
+    /// Sets the cache used to flag suspected replays.
+    pub fn with_recent_codes(mut self, cache: RecentCodeCache) -> Self {
+        self.recent_codes = Some(cache);
+        self
+    }
+
+    /// Sets the cache of generated passwords consulted before computing any HMACs. See
+    /// [GeneratedCodeCache] for what keeping the passwords in memory implies.
+    pub fn with_generated_codes(mut self, cache: GeneratedCodeCache) -> Self {
+        self.generated_codes = Some(cache);
+        self
+    }
+
+    /// Verifies the password of the credential identified by `secret_id`. The identifier is only
+    /// used for the [RecentCodeCache].
+    ///
+    /// If a `timestamp` of 0 is provided, the current system time will be used for the calculation.
+    #[must_use = "the verification result must be checked"]
+    pub fn verify(
+        &mut self,
+        secret_id: &str,
+        secret: &[u8],
+        password: &str,
+        timestamp: u64,
+    ) -> Result<TotpResult, ThotpError> {
+        if password.len() != self.digits as usize {
+            return Ok(TotpResult::default());
+        }
+
+        let timestamp = resolve_timestamp(timestamp)?;
+
+        let nonce = checked_time_slice(timestamp, self.step)?;
+        let start = nonce.saturating_sub(self.allowed_drift as u64);
+        let end = nonce.saturating_add(self.allowed_drift as u64);
+
+        // The key is only initialized once a password is not cached
+        let mut key = None;
+        let cache_key = self
+            .generated_codes
+            .as_ref()
+            .map(|cache| cache.key::<H>(secret_id, secret, self.digits));
+
+        for n in start..=end {
+            let cached = match (&mut self.generated_codes, &cache_key) {
+                (Some(cache), Some(cache_key)) => cache.get(cache_key, n, start),
+                _ => None,
+            };
+
+            let pass = match cached {
+                Some(pass) => pass,
+                None => {
+                    let key = match key {
+                        Some(ref key) => key,
+                        None => key.insert(HmacKey::<H>::new(secret)?),
+                    };
+
+                    let pass = key.code(n, self.digits)?;
+                    if let (Some(cache), Some(cache_key)) = (&mut self.generated_codes, &cache_key)
+                    {
+                        cache.insert(cache_key, n, pass.clone(), start);
+                    }
+                    pass
+                }
+            };
+
+            if ct_eq(pass.as_bytes(), password.as_bytes()) {
+                let suspected_replay = match self.recent_codes {
+                    Some(ref mut cache) => cache.check_and_insert(secret_id, password, timestamp),
+                    None => false,
+                };
+
+                return Ok(TotpResult {
+                    valid: true,
+                    discrepancy: (n as i128 - nonce as i128) as i16,
+                    suspected_replay,
+                });
+            }
+        }
+
+        Ok(TotpResult::default())
+    }
+}
+
+/// Stores the number of consecutive failed attempts of each credential for an [AttemptLimiter], e.g.
+/// in a database so the lockout is shared between servers.
+///
+/// Implementations must make [AttemptStore::increment_failures] atomic: the count must be read and
+/// written with no other write in between, e.g. with an `UPDATE ... SET failures = failures + 1
+/// RETURNING failures` or a transaction. Otherwise concurrent failed attempts can overwrite each
+/// other's count and keep a credential from ever being locked out.
+pub trait AttemptStore {
+    /// Returns the number of consecutive failed attempts of the credential, 0 if there are none.
+    fn failures(&self, key: &str) -> u32;
+
+    /// Sets the number of consecutive failed attempts of the credential.
+    fn set_failures(&mut self, key: &str, failures: u32);
+
+    /// Counts a failed attempt of the credential and returns the new number of consecutive failed
+    /// attempts.
+    fn increment_failures(&mut self, key: &str) -> u32;
+}
+
+/// An [AttemptStore] keeping the failed attempts in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryAttemptStore {
+    failures: HashMap<String, u32>,
+}
+
+impl AttemptStore for MemoryAttemptStore {
+    fn failures(&self, key: &str) -> u32 {
+        self.failures.get(key).copied().unwrap_or(0)
+    }
+
+    fn set_failures(&mut self, key: &str, failures: u32) {
+        if failures == 0 {
+            self.failures.remove(key);
+        } else {
+            self.failures.insert(key.to_string(), failures);
+        }
+    }
+
+    fn increment_failures(&mut self, key: &str) -> u32 {
+        let failures = self.failures.entry(key.to_string()).or_default();
+        *failures = failures.saturating_add(1);
+        *failures
+    }
+}
+
+/// The outcome of a verification performed by an [AttemptLimiter].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use = "the verification result must be checked"]
+pub enum AttemptOutcome {
+    /// The password was valid and the failed attempts were reset. The `drift` is the number of time
+    /// slices the password deviates from the current one.
+    Accepted { drift: i16 },
+
+    /// The password was invalid, `remaining` more attempts can fail before the credential is locked
+    /// out.
+    Rejected { remaining: u32 },
+
+    /// Too many attempts failed, the password was not verified.
+    LockedOut,
+}
+
+/// Locks out credentials after `max_attempts` consecutive failed attempts, until they are reset
+/// with [AttemptLimiter::reset]. A successful attempt resets the failed attempts of the credential.
+///
+/// ## Example
+/// ```
+/// use thotp::server::{AttemptLimiter, AttemptOutcome};
+/// use thotp::totp::Totp;
+///
+/// let totp = Totp::new(b"super secret".to_vec()).with_clock(|| 1_000_000_000);
+/// let mut limiter = AttemptLimiter::new(2);
+///
+/// let outcome = limiter.verify_totp("john", &totp, "000000", 0).unwrap();
+/// assert_eq!(outcome, AttemptOutcome::Rejected { remaining: 1 });
+///
+/// let outcome = limiter.verify_totp("john", &totp, "000000", 0).unwrap();
+/// assert_eq!(outcome, AttemptOutcome::LockedOut);
+///
+/// // Even the right password is not accepted anymore
+/// let password = totp.generate(0).unwrap();
+/// assert_eq!(limiter.verify_totp("john", &totp, &password, 0).unwrap(), AttemptOutcome::LockedOut);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AttemptLimiter<S = MemoryAttemptStore> {
+    max_attempts: u32,
+    store: S,
+}
+
+impl AttemptLimiter {
+    /// Creates a limiter keeping the failed attempts in memory.
+    pub fn new(max_attempts: u32) -> Self {
+        Self::with_store(max_attempts, MemoryAttemptStore::default())
+    }
+}
+
+impl<S: AttemptStore> AttemptLimiter<S> {
+    /// Creates a limiter keeping the failed attempts in the given store.
+    pub fn with_store(max_attempts: u32, store: S) -> Self {
+        Self {
+            max_attempts,
+            store,
+        }
+    }
+
+    /// Returns the store of the failed attempts.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the number of attempts of the credential that can fail before it is locked out.
+    pub fn remaining(&self, key: &str) -> u32 {
+        self.max_attempts.saturating_sub(self.store.failures(key))
+    }
+
+    /// Resets the failed attempts of the credential, lifting its lockout.
+    pub fn reset(&mut self, key: &str) {
+        self.store.set_failures(key, 0);
+    }
+
+    /// Verifies the password of the credential identified by `key` with [Totp::verify], unless it
+    /// is locked out. Every attempt is counted before the password is verified and a successful one
+    /// resets the count, so of many concurrent attempts on a shared store at most `max_attempts` are
+    /// ever verified.
+    ///
+    /// If a `timestamp` of 0 is provided, the clock of the [Totp] is used for the calculation.
+    #[must_use = "the verification result must be checked"]
+    pub fn verify_totp(
+        &mut self,
+        key: &str,
+        totp: &Totp,
+        password: &str,
+        timestamp: u64,
+    ) -> Result<AttemptOutcome, ThotpError> {
+        // Counted by the store before verifying, so concurrent attempts on a shared store can not
+        // all pass the lockout check before any of them is counted
+        let attempts = self.store.increment_failures(key);
+        if attempts > self.max_attempts {
+            return Ok(AttemptOutcome::LockedOut);
+        }
+
+        let result = totp.verify(password, timestamp)?;
+
+        if result.valid {
+            self.store.set_failures(key, 0);
+            return Ok(AttemptOutcome::Accepted {
+                drift: result.discrepancy,
+            });
+        }
+
+        match self.max_attempts.saturating_sub(attempts) {
+            0 => Ok(AttemptOutcome::LockedOut),
+            remaining => Ok(AttemptOutcome::Rejected { remaining }),
+        }
+    }
+}
+
+/// The direction the clock of an authenticator is drifting in, as reported by a [DriftTrendDetector].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftDirection {
+    /// The clock is running fast, passwords match later and later time slices
+    Ahead,
+
+    /// The clock is running slow, passwords match earlier and earlier time slices
+    Behind,
+}
+
+/// A drift trend of a credential detected by a [DriftTrendDetector].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DriftTrend {
+    /// The direction of the drift
+    pub direction: DriftDirection,
+
+    /// The number of time slices the discrepancy moved by over the recorded window
+    pub magnitude: u16,
+}
+
+/// Records the discrepancies of the last accepted passwords of each credential and detects the ones
+/// whose discrepancy keeps moving in the same direction, which hints at a failing authenticator clock.
+/// Such a user can be asked to sync the clock of the device before the passwords fall out of the
+/// allowed drift and the user is locked out.
+///
+/// A trend is reported once `window` discrepancies were recorded for the credential, each of them
+/// is at least as far in the same direction as the one before, and the last one is at least
+/// `threshold` time slices away from the first one.
+///
+/// ## Example
+/// ```
+/// use thotp::server::{DriftDirection, DriftTrend, DriftTrendDetector};
+///
+/// let mut detector = DriftTrendDetector::new(4, 2);
+///
+/// assert_eq!(detector.record("john", 0), None);
+/// assert_eq!(detector.record("john", 1), None);
+/// assert_eq!(detector.record("john", 1), None);
+///
+/// let trend = detector.record("john", 2);
+/// assert_eq!(
+///     trend,
+///     Some(DriftTrend { direction: DriftDirection::Ahead, magnitude: 2 })
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DriftTrendDetector {
+    window: usize,
+    threshold: u16,
+    discrepancies: HashMap<String, VecDeque<i16>>,
+}
+
+impl DriftTrendDetector {
+    /// Creates a detector reporting trends over the last `window` discrepancies of a credential that
+    /// moved by at least `threshold` time slices. A discrepancy that never moves is no trend, even
+    /// with a `threshold` of 0.
+    pub fn new(window: usize, threshold: u16) -> Self {
+        Self {
+            window,
+            threshold,
+            discrepancies: HashMap::new(),
+        }
+    }
+
+    /// Records the discrepancy of a password accepted for the credential identified by `key` and
+    /// returns its trend, if any. Only the last `window` discrepancies are kept.
+    pub fn record(&mut self, key: &str, discrepancy: i16) -> Option<DriftTrend> {
+        if self.window == 0 {
+            return None;
+        }
+
+        let discrepancies = self.discrepancies.entry(key.to_string()).or_default();
+        if discrepancies.len() >= self.window {
+            discrepancies.pop_front();
+        }
+        discrepancies.push_back(discrepancy);
+
+        self.trend(key)
+    }
+
+    /// Returns the trend of the recorded discrepancies of the credential identified by `key`, if any.
+    pub fn trend(&self, key: &str) -> Option<DriftTrend> {
+        let discrepancies = self.discrepancies.get(key)?;
+        if self.window == 0 || discrepancies.len() < self.window {
+            return None;
+        }
+
+        let (first, last) = (*discrepancies.front()?, *discrepancies.back()?);
+        let pairs = || discrepancies.iter().zip(discrepancies.iter().skip(1));
+
+        let direction = if last > first && pairs().all(|(a, b)| b >= a) {
+            DriftDirection::Ahead
+        } else if last < first && pairs().all(|(a, b)| b <= a) {
+            DriftDirection::Behind
+        } else {
+            return None;
+        };
+
+        let magnitude = last.abs_diff(first);
+        (magnitude >= self.threshold).then_some(DriftTrend {
+            direction,
+            magnitude,
+        })
+    }
+
+    /// Forgets the recorded discrepancies of the credential, e.g. after the user synced the clock.
+    pub fn reset(&mut self, key: &str) {
+        self.discrepancies.remove(key);
+    }
+}
+
+/// A password submitted by a user, e.g. through a web form, before it has been normalized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeSubmission {
+    /// The password as entered, spaces and dashes used to group its digits are allowed
+    pub code: String,
+
+    /// The unix timestamp at which the password was submitted, 0 for the current system time. The
+    /// password is verified for this time, so it must be recorded by the server when the submission
+    /// is received and never be taken from the payload sent by the client. See
+    /// [VerifyConfig::max_server_skew] for bounding how far it can be from the current time.
+    pub submitted_at: u64,
+}
+
+/// The parameters [verify_submission] verifies passwords with. Defaults to 6 digits, a time step of
+/// 30, an allowed drift of 1 and no maximum server skew.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyConfig {
+    /// The digit length of the passwords
+    pub digits: u8,
+
+    /// The time step
+    pub step: u8,
+
+    /// The number of time slices prior and subsequent to the current one whose passwords are accepted
+    pub allowed_drift: u8,
+
+    /// The largest difference in seconds between [CodeSubmission::submitted_at] and the current
+    /// system time, like [Totp::with_max_server_skew]. `None` if it is not checked
+    pub max_server_skew: Option<u64>,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        Self {
+            digits: DIGITS_DEFAULT,
+            step: TIME_STEP,
+            allowed_drift: ALLOWED_DRIFT,
+            max_server_skew: None,
+        }
+    }
+}
+
+/// Normalizes a password entered or pasted by a user. Surrounding whitespace is trimmed, including
+/// the non-breaking and zero width spaces pasting often brings along, and the spaces and dashes of
+/// formatted passwords such as `123 456` are removed. Returns a [ThotpError::InvalidCode] if anything
+/// but digits remains.
+///
+/// ## Example
+/// ```
+/// use thotp::server::normalize_code;
+///
+/// assert_eq!(normalize_code("\u{00A0}123-456\n").unwrap(), "123456");
+/// assert!(normalize_code("123 45b").is_err());
+/// ```
+pub fn normalize_code(code: &str) -> Result<String, ThotpError> {
+    let code: String = code
+        .trim_matches(|c: char| c.is_whitespace() || ZERO_WIDTH.contains(&c))
+        .chars()
+        .filter(|c| *c != ' ' && *c != '-')
+        .collect();
+
+    if !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ThotpError::InvalidCode);
+    }
+
+    Ok(code)
+}
+
+/// Invisible characters which are not whitespace, but are trimmed like it: the zero width space,
+/// the word joiner and the byte order mark.
+const ZERO_WIDTH: [char; 3] = ['\u{200B}', '\u{2060}', '\u{FEFF}'];
+
+/// Verifies a submitted password. The password is normalized with [normalize_code], then it has to
+/// consist of exactly `digits` digits and is compared in constant time in the range of
+/// `[-allowed_drift, allowed_drift]` time slices. Bundling these steps keeps callers from forgetting
+/// to normalize the password or check its length.
+///
+/// The password is verified for the time it was submitted at. Returns a
+/// [ThotpError::TimestampOutOfRange] if that is further off the current time than
+/// [VerifyConfig::max_server_skew] allows.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::{otp_custom, Sha1};
+/// use thotp::server::{verify_submission, CodeSubmission, VerifyConfig, VerifyOutcome};
+///
+/// let secret = b"super secret";
+/// let password = otp_custom::<Sha1>(secret, 1_000_000_000 / 30, 6).unwrap();
+///
+/// let submission = CodeSubmission {
+///     code: format!("{} {}", &password[..3], &password[3..]),
+///     submitted_at: 1_000_000_000,
+/// };
+///
+/// let outcome = verify_submission::<Sha1>(&submission, secret, VerifyConfig::default()).unwrap();
+/// assert_eq!(outcome, VerifyOutcome::Accepted { drift: 0 });
+/// ```
+pub fn verify_submission<H>(
+    submission: &CodeSubmission,
+    secret: &[u8],
+    config: VerifyConfig,
+) -> Result<VerifyOutcome, ThotpError>
+where
+    H: OtpHash,
+{
+    let code = match normalize_code(&submission.code) {
+        Ok(code) if code.len() == config.digits as usize => code,
+        _ => return Ok(VerifyOutcome::Malformed),
+    };
+
+    // A timestamp of 0 is the current time, so it can not be off
+    if let Some(max) = config
+        .max_server_skew
+        .filter(|_| submission.submitted_at != 0)
+    {
+        if submission.submitted_at.abs_diff(resolve_timestamp(0)?) > max {
+            return Err(ThotpError::TimestampOutOfRange);
+        }
+    }
+
+    let (valid, drift) = verify_totp_custom::<H>(
+        &code,
+        secret,
+        submission.submitted_at,
+        config.digits,
+        config.step,
+        config.allowed_drift,
+    )?;
+
+    match valid {
+        true => Ok(VerifyOutcome::Accepted { drift }),
+        false => Ok(VerifyOutcome::Rejected),
+    }
+}
+
+/// Reads a big endian `u64` from a state created by one of the `serialize_state` methods.
+fn read_u64(bytes: &[u8]) -> Result<u64, ThotpError> {
+    let bytes = bytes.try_into().map_err(|_| ThotpError::InvalidState)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::custom::{otp_custom, Sha1};
+    use super::*;
+
+    const TEST_KEY: &[u8; 20] = b"12345678901234567890";
+    const TIMESTAMP: u64 = 1_234_567_890;
+
+    #[test]
+    fn replay_guard_accept() -> Result<(), ThotpError> {
+        let nonce = TIMESTAMP / 30;
+        let mut guard = TotpReplayGuard::new();
+
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce - 1, 6)?;
+        let outcome = guard.verify_and_advance::<Sha1>(TEST_KEY, &password, TIMESTAMP, 6, 30, 1)?;
+        assert_eq!(outcome, TotpGuardOutcome::Accepted { drift: -1 });
+        assert_eq!(guard.watermark(), Some(nonce - 1));
+
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce, 6)?;
+        let outcome = guard.verify_and_advance::<Sha1>(TEST_KEY, &password, TIMESTAMP, 6, 30, 1)?;
+        assert_eq!(outcome, TotpGuardOutcome::Accepted { drift: 0 });
+        assert_eq!(guard.watermark(), Some(nonce));
+        Ok(())
+    }
+
+    #[test]
+    fn replay_guard_replay() -> Result<(), ThotpError> {
+        let nonce = TIMESTAMP / 30;
+        let mut guard = TotpReplayGuard::new();
+
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce + 1, 6)?;
+        let outcome = guard.verify_and_advance::<Sha1>(TEST_KEY, &password, TIMESTAMP, 6, 30, 1)?;
+        assert_eq!(outcome, TotpGuardOutcome::Accepted { drift: 1 });
+        assert_eq!(guard.watermark(), Some(nonce + 1));
+
+        // The same password again
+        let outcome = guard.verify_and_advance::<Sha1>(TEST_KEY, &password, TIMESTAMP, 6, 30, 1)?;
+        assert_eq!(outcome, TotpGuardOutcome::Replayed);
+
+        // An older password that is still in the allowed drift
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce, 6)?;
+        let outcome = guard.verify_and_advance::<Sha1>(TEST_KEY, &password, TIMESTAMP, 6, 30, 1)?;
+        assert_eq!(outcome, TotpGuardOutcome::Replayed);
+        assert_eq!(guard.watermark(), Some(nonce + 1));
+        Ok(())
+    }
+
+    #[test]
+    fn replay_guard_closest_match() -> Result<(), ThotpError> {
+        // With a single digit, find a slice whose password collides with one two slices earlier but
+        // with none closer to the current slice
+        let code = |n: u64| otp_custom::<Sha1>(TEST_KEY, n, 1);
+        let mut nonce = TIMESTAMP / 30;
+        while code(nonce - 2)? != code(nonce + 1)?
+            || code(nonce + 1)? == code(nonce)?
+            || code(nonce + 1)? == code(nonce - 1)?
+        {
+            nonce += 1;
+        }
+
+        let mut guard = TotpReplayGuard::new();
+        let password = code(nonce + 1)?;
+        let outcome =
+            guard.verify_and_advance::<Sha1>(TEST_KEY, &password, nonce * 30, 1, 30, 2)?;
+        assert_eq!(outcome, TotpGuardOutcome::Accepted { drift: 1 });
+        assert_eq!(guard.watermark(), Some(nonce + 1));
+        Ok(())
+    }
+
+    #[test]
+    fn hotp_server() -> Result<(), ThotpError> {
+        let mut server = HotpServer::new(10, 2, 6)?;
+
+        let password = otp_custom::<Sha1>(TEST_KEY, 13, 6)?;
+        assert!(!server.verify::<Sha1>(TEST_KEY, &password)?);
+        assert_eq!(server.counter(), Counter::new(10));
+
+        let password = otp_custom::<Sha1>(TEST_KEY, 12, 6)?;
+        assert!(server.verify::<Sha1>(TEST_KEY, &password)?);
+        assert_eq!(server.counter(), Counter::new(13));
+        Ok(())
+    }
+
+    #[test]
+    fn hotp_server_overflow() -> Result<(), ThotpError> {
+        // The lookahead does not wrap around
+        let mut server = HotpServer::new(u64::MAX - 1, 5, 6)?;
+        let password = otp_custom::<Sha1>(TEST_KEY, 0, 6)?;
+        assert!(!server.verify::<Sha1>(TEST_KEY, &password)?);
+
+        let password = otp_custom::<Sha1>(TEST_KEY, u64::MAX, 6)?;
+        assert!(matches!(
+            server.verify::<Sha1>(TEST_KEY, &password),
+            Err(ThotpError::CounterOverflow)
+        ));
+        assert_eq!(server.counter(), Counter::new(u64::MAX - 1));
+        Ok(())
+    }
+
+    #[test]
+    fn hotp_shared_counter() -> Result<(), ThotpError> {
+        let server = HotpServer::new(0, 5, 6)?;
+        let store = AtomicCounterStore::new(10);
+        let password = otp_custom::<Sha1>(TEST_KEY, 12, 6)?;
+
+        // Two concurrent verifications of the same password, only one of them wins the swap
+        let barrier = std::sync::Barrier::new(2);
+        let accepted = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        server.verify_shared::<Sha1, _>(&store, TEST_KEY, &password)
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        assert_eq!(accepted.iter().filter(|accepted| **accepted).count(), 1);
+        assert_eq!(store.load()?, Counter::new(13));
+
+        // A store changed between the load and the swap rejects the swap
+        assert!(!store.compare_and_set(Counter::new(10), Counter::new(11))?);
+        assert_eq!(store.load()?, Counter::new(13));
+        Ok(())
+    }
+
+    #[test]
+    fn state_round_trip() -> Result<(), ThotpError> {
+        let mut server = HotpServer::new(Counter::new(10), 3, 6)?;
+        let password = otp_custom::<Sha1>(TEST_KEY, 12, 6)?;
+        assert!(server.verify::<Sha1>(TEST_KEY, &password)?);
+
+        let mut restored = HotpServer::from_state(&server.serialize_state())?;
+        assert_eq!(restored, server);
+        assert_eq!(restored.counter(), Counter::new(13));
+        // The password stays used after restoring
+        assert!(!restored.verify::<Sha1>(TEST_KEY, &password)?);
+
+        let mut guard = TotpReplayGuard::new();
+        assert_eq!(
+            TotpReplayGuard::from_state(&guard.serialize_state())?,
+            guard
+        );
+
+        let password = otp_custom::<Sha1>(TEST_KEY, TIMESTAMP / 30, 6)?;
+        assert_eq!(
+            guard.verify_and_advance::<Sha1>(TEST_KEY, &password, TIMESTAMP, 6, 30, 1)?,
+            TotpGuardOutcome::Accepted { drift: 0 }
+        );
+
+        let mut restored = TotpReplayGuard::from_state(&guard.serialize_state())?;
+        assert_eq!(restored.watermark(), Some(TIMESTAMP / 30));
+        assert_eq!(
+            restored.verify_and_advance::<Sha1>(TEST_KEY, &password, TIMESTAMP, 6, 30, 1)?,
+            TotpGuardOutcome::Replayed
+        );
+
+        // The version comes first
+        assert_eq!(guard.serialize_state()[0], TotpReplayGuard::STATE_VERSION);
+        assert_eq!(server.serialize_state()[0], HotpServer::STATE_VERSION);
+        assert!(matches!(
+            TotpReplayGuard::from_state(&[2, 0]),
+            Err(ThotpError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            HotpServer::from_state(&[2; 11]),
+            Err(ThotpError::UnsupportedVersion(2))
+        ));
+
+        for state in [&[][..], &[1], &[1, 2], &[1, 1, 0, 0], &[1; 9], &[1; 11]] {
+            assert!(matches!(
+                TotpReplayGuard::from_state(state),
+                Err(ThotpError::InvalidState)
+            ));
+        }
+        let digits = |digits| [1, 0, 0, 0, 0, 0, 0, 0, 10, 5, digits];
+        assert!(HotpServer::from_state(&digits(6)).is_ok());
+        for state in [
+            &[][..],
+            &[1; 10],
+            &[1; 12],
+            &digits(0),
+            &digits(5),
+            &digits(11),
+        ] {
+            assert!(matches!(
+                HotpServer::from_state(state),
+                Err(ThotpError::InvalidState)
+            ));
+        }
+
+        // Every server that can be created can be restored
+        for digits in [4, 5, 11] {
+            assert!(matches!(
+                HotpServer::new(0, 5, digits),
+                Err(ThotpError::InvalidDigits)
+            ));
+        }
+        for digits in 6..=10 {
+            let server = HotpServer::new(0, 5, digits)?;
+            assert_eq!(HotpServer::from_state(&server.serialize_state())?, server);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn generated_code_cache() -> Result<(), ThotpError> {
+        let password = otp_custom::<Sha1>(TEST_KEY, TIMESTAMP / 30, 6)?;
+        let mut verifier =
+            TotpVerifier::<Sha1>::new(6, 30, 1).with_generated_codes(GeneratedCodeCache::new(2));
+
+        let result = verifier.verify("john", TEST_KEY, &password, TIMESTAMP)?;
+        assert!(result.valid && result.discrepancy == 0);
+
+        // The cached passwords are used for the same secret
+        let cached = verifier.verify("john", TEST_KEY, &password, TIMESTAMP + 1)?;
+        assert_eq!(cached, result);
+        assert_eq!(verifier.generated_codes.as_ref().unwrap().len(), 1);
+
+        // After the secret is rotated the passwords of the old one are not returned
+        let rotated = b"rotated secret";
+        let rotated_password = otp_custom::<Sha1>(rotated, TIMESTAMP / 30, 6)?;
+        assert!(
+            !verifier
+                .verify("john", rotated, &password, TIMESTAMP)?
+                .valid
+        );
+        assert!(
+            verifier
+                .verify("john", rotated, &rotated_password, TIMESTAMP)?
+                .valid
+        );
+
+        // The next slice is not cached yet while the current one now counts as the previous one
+        let next = otp_custom::<Sha1>(rotated, TIMESTAMP / 30 + 2, 6)?;
+        let result = verifier.verify("john", rotated, &rotated_password, TIMESTAMP + 30)?;
+        assert!(result.valid && result.discrepancy == -1);
+        let result = verifier.verify("john", rotated, &next, TIMESTAMP + 30)?;
+        assert!(result.valid && result.discrepancy == 1);
+
+        // Slices out of the drift are dropped
+        let result = verifier.verify("john", rotated, &rotated_password, TIMESTAMP + 60)?;
+        assert!(!result.valid);
+
+        // The least recently used credential, the old secret of john, is evicted
+        assert!(
+            verifier
+                .verify("jane", TEST_KEY, &password, TIMESTAMP)?
+                .valid
+        );
+        let cache = verifier.generated_codes.as_ref().unwrap();
+        assert_eq!(cache.len(), 2);
+        let old = cache.key::<Sha1>("john", TEST_KEY, 6);
+        assert!(!cache.codes.contains_key(&old));
+        assert!(cache
+            .codes
+            .contains_key(&cache.key::<Sha1>("john", rotated, 6)));
+        assert!(cache
+            .codes
+            .contains_key(&cache.key::<Sha1>("jane", TEST_KEY, 6)));
+        assert_eq!(cache.order.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn attempt_limiter() -> Result<(), ThotpError> {
+        let totp = Totp::new(TEST_KEY.to_vec());
+        let password = totp.generate(TIMESTAMP)?;
+        let mut limiter = AttemptLimiter::new(3);
+        assert_eq!(limiter.remaining("john"), 3);
+
+        for remaining in [2, 1] {
+            assert_eq!(
+                limiter.verify_totp("john", &totp, "000000", TIMESTAMP)?,
+                AttemptOutcome::Rejected { remaining }
+            );
+        }
+
+        // A success resets the failed attempts
+        assert_eq!(
+            limiter.verify_totp("john", &totp, &password, TIMESTAMP)?,
+            AttemptOutcome::Accepted { drift: 0 }
+        );
+        assert_eq!(limiter.remaining("john"), 3);
+        assert_eq!(limiter.store().failures("john"), 0);
+
+        for _ in 0..2 {
+            let _ = limiter.verify_totp("john", &totp, "000000", TIMESTAMP)?;
+        }
+        assert_eq!(
+            limiter.verify_totp("john", &totp, "000000", TIMESTAMP)?,
+            AttemptOutcome::LockedOut
+        );
+        assert_eq!(
+            limiter.verify_totp("john", &totp, &password, TIMESTAMP)?,
+            AttemptOutcome::LockedOut
+        );
+        assert_eq!(limiter.remaining("john"), 0);
+
+        // Other credentials are not affected
+        assert_eq!(
+            limiter.verify_totp("jane", &totp, &password, TIMESTAMP)?,
+            AttemptOutcome::Accepted { drift: 0 }
+        );
+
+        limiter.reset("john");
+        assert_eq!(
+            limiter.verify_totp("john", &totp, &password, TIMESTAMP)?,
+            AttemptOutcome::Accepted { drift: 0 }
+        );
+
+        let mut limiter = AttemptLimiter::new(0);
+        assert_eq!(
+            limiter.verify_totp("john", &totp, &password, TIMESTAMP)?,
+            AttemptOutcome::LockedOut
+        );
+
+        let mut store = MemoryAttemptStore::default();
+        assert_eq!(store.increment_failures("john"), 1);
+        assert_eq!(store.increment_failures("john"), 2);
+        assert_eq!(store.failures("john"), 2);
+        store.set_failures("john", u32::MAX);
+        assert_eq!(store.increment_failures("john"), u32::MAX);
+        Ok(())
+    }
+
+    #[test]
+    fn shared_attempt_store() -> Result<(), ThotpError> {
+        use std::sync::{Arc, Mutex};
+
+        // A store shared between servers, counting the failures under a lock
+        #[derive(Clone, Default)]
+        struct SharedStore(Arc<Mutex<MemoryAttemptStore>>);
+
+        impl AttemptStore for SharedStore {
+            fn failures(&self, key: &str) -> u32 {
+                self.0.lock().unwrap().failures(key)
+            }
+
+            fn set_failures(&mut self, key: &str, failures: u32) {
+                self.0.lock().unwrap().set_failures(key, failures)
+            }
+
+            fn increment_failures(&mut self, key: &str) -> u32 {
+                self.0.lock().unwrap().increment_failures(key)
+            }
+        }
+
+        let totp = Totp::new(TEST_KEY.to_vec());
+        let store = SharedStore::default();
+        let mut first = AttemptLimiter::with_store(3, store.clone());
+        let mut second = AttemptLimiter::with_store(3, store);
+
+        assert_eq!(
+            first.verify_totp("john", &totp, "000000", TIMESTAMP)?,
+            AttemptOutcome::Rejected { remaining: 2 }
+        );
+        assert_eq!(
+            second.verify_totp("john", &totp, "000000", TIMESTAMP)?,
+            AttemptOutcome::Rejected { remaining: 1 }
+        );
+        assert_eq!(
+            first.verify_totp("john", &totp, "000000", TIMESTAMP)?,
+            AttemptOutcome::LockedOut
+        );
+        assert_eq!(second.remaining("john"), 0);
+
+        // Failures counted concurrently are never lost
+        let store = SharedStore::default();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let mut limiter = AttemptLimiter::with_store(u32::MAX, store.clone());
+                let totp = &totp;
+                scope.spawn(move || {
+                    for _ in 0..25 {
+                        let _ = limiter.verify_totp("john", totp, "000000", TIMESTAMP);
+                    }
+                });
+            }
+        });
+        assert_eq!(store.failures("john"), 100);
+
+        // Of many concurrent guesses at most the allowed attempts are verified
+        let verified = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let counted = verified.clone();
+        let totp = Totp::new(TEST_KEY.to_vec()).with_clock(move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            TIMESTAMP
+        });
+        let store = SharedStore::default();
+        let barrier = std::sync::Barrier::new(16);
+        std::thread::scope(|scope| {
+            for _ in 0..16 {
+                let mut limiter = AttemptLimiter::with_store(3, store.clone());
+                let (totp, barrier) = (&totp, &barrier);
+                scope.spawn(move || {
+                    barrier.wait();
+                    let _ = limiter.verify_totp("john", totp, "000000", 0);
+                });
+            }
+        });
+        assert_eq!(verified.load(Ordering::SeqCst), 3);
+        assert_eq!(store.failures("john"), 16);
+        Ok(())
+    }
+
+    #[test]
+    fn code_submission() -> Result<(), ThotpError> {
+        let config = VerifyConfig {
+            digits: 8,
+            ..VerifyConfig::default()
+        };
+        let password = otp_custom::<Sha1>(TEST_KEY, TIMESTAMP / 30 + 1, 8)?;
+        let submit = |code: String| CodeSubmission {
+            code,
+            submitted_at: TIMESTAMP,
+        };
+
+        let formatted = submit(format!(
+            " {}-{} {}\n",
+            &password[..2],
+            &password[2..5],
+            &password[5..]
+        ));
+        assert_eq!(
+            verify_submission::<Sha1>(&formatted, TEST_KEY, config)?,
+            VerifyOutcome::Accepted { drift: 1 }
+        );
+        assert_eq!(
+            verify_submission::<Sha1>(&submit(password.clone()), TEST_KEY, config)?,
+            VerifyOutcome::Accepted { drift: 1 }
+        );
+
+        assert_eq!(
+            verify_submission::<Sha1>(&submit(password[..6].to_string()), TEST_KEY, config)?,
+            VerifyOutcome::Malformed
+        );
+        assert_eq!(
+            verify_submission::<Sha1>(&submit(String::from("1234567a")), TEST_KEY, config)?,
+            VerifyOutcome::Malformed
+        );
+        assert_eq!(
+            verify_submission::<Sha1>(&submit(String::from("0000 0000")), TEST_KEY, config)?,
+            VerifyOutcome::Rejected
+        );
+
+        // A submission time too far from the current one is not verified
+        let skewed = VerifyConfig {
+            max_server_skew: Some(300),
+            ..config
+        };
+        assert!(matches!(
+            verify_submission::<Sha1>(&submit(password.clone()), TEST_KEY, skewed),
+            Err(ThotpError::TimestampOutOfRange)
+        ));
+        let now = CodeSubmission {
+            code: otp_custom::<Sha1>(TEST_KEY, resolve_timestamp(0)? / 30, 8)?,
+            submitted_at: resolve_timestamp(0)?,
+        };
+        assert!(matches!(
+            verify_submission::<Sha1>(&now, TEST_KEY, skewed)?,
+            VerifyOutcome::Accepted { .. }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn pasted_codes() {
+        for code in [
+            "123456\n",
+            "\r\n123456\r\n",
+            "\t123456\t",
+            "\u{00A0}123456\u{00A0}",
+            "\u{200B}123456",
+            "\u{FEFF}\u{2060} 123 456 \u{200B}\n",
+        ] {
+            assert_eq!(normalize_code(code).unwrap(), "123456", "{:?}", code);
+        }
+        assert_eq!(normalize_code("12-34 56").unwrap(), "123456");
+
+        // Only surrounding whitespace is trimmed
+        for code in [
+            "123\u{00A0}456",
+            "123\u{200B}456",
+            "123\t456",
+            "12345a",
+            "١٢٣٤٥٦",
+        ] {
+            assert!(
+                matches!(normalize_code(code), Err(ThotpError::InvalidCode)),
+                "{:?}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn recent_code_cache() {
+        let mut cache = RecentCodeCache::new(2, 90);
+        assert!(!cache.check_and_insert("john", "123456", 100));
+        assert!(cache.check_and_insert("john", "123456", 150));
+        assert!(!cache.check_and_insert("jane", "123456", 150));
+        assert_eq!(cache.len(), 2);
+
+        // Still flagged while its filter is the previous one
+        assert!(cache.check_and_insert("john", "123456", 190));
+
+        // Expired once both filters are older than the ttl
+        assert!(!cache.check_and_insert("john", "123456", 380));
+        assert_eq!(cache.len(), 1);
+
+        // A full filter becomes the previous one, dropping the one before it
+        assert!(!cache.check_and_insert("jane", "123456", 380));
+        assert!(!cache.check_and_insert("john", "654321", 380));
+        assert!(cache.check_and_insert("john", "123456", 380));
+        assert!(!cache.check_and_insert("jane", "654321", 380));
+        assert!(!cache.check_and_insert("bob", "123456", 380));
+        assert!(!cache.check_and_insert("john", "123456", 380));
+        assert_eq!(cache.len(), 4);
+
+        // Nothing is recorded without a capacity
+        let mut cache = RecentCodeCache::new(0, 90);
+        assert!(!cache.check_and_insert("john", "123456", 100));
+        assert!(!cache.check_and_insert("john", "123456", 100));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn drift_trend() {
+        let mut detector = DriftTrendDetector::new(5, 2);
+
+        // A clock running fast
+        let trends: Vec<_> = [0, 0, 1, 1, 2, 3]
+            .into_iter()
+            .map(|discrepancy| detector.record("john", discrepancy))
+            .collect();
+        assert_eq!(trends[..4], [None; 4]);
+        let ahead = |magnitude| DriftTrend {
+            direction: DriftDirection::Ahead,
+            magnitude,
+        };
+        assert_eq!(trends[4..], [Some(ahead(2)), Some(ahead(3))]);
+
+        // A clock running slow, other credentials are tracked separately
+        for discrepancy in [0, -1, -1, -2] {
+            assert_eq!(detector.record("jane", discrepancy), None);
+        }
+        assert_eq!(
+            detector.record("jane", -2),
+            Some(DriftTrend {
+                direction: DriftDirection::Behind,
+                magnitude: 2
+            })
+        );
+        assert_eq!(detector.trend("john"), Some(ahead(3)));
+
+        // A steady offset or a change of direction is no trend
+        for discrepancy in [1, 1, 1, 1, 1, 1] {
+            assert_eq!(detector.record("steady", discrepancy), None);
+        }
+        for discrepancy in [0, 1, 2, 1, 3] {
+            assert_eq!(detector.record("noisy", discrepancy), None);
+        }
+
+        // Moving too little is no trend either
+        for discrepancy in [0, 0, 0, 0, 1] {
+            assert_eq!(detector.record("slight", discrepancy), None);
+        }
+
+        detector.reset("john");
+        assert_eq!(detector.trend("john"), None);
+        assert_eq!(detector.record("john", 3), None);
+
+        let mut detector = DriftTrendDetector::new(0, 1);
+        assert_eq!(detector.record("john", 1), None);
+    }
+
+    #[test]
+    fn totp_verifier_replay() -> Result<(), ThotpError> {
+        let nonce = TIMESTAMP / 30;
+        let password = otp_custom::<Sha1>(TEST_KEY, nonce, 6)?;
+
+        let mut verifier =
+            TotpVerifier::<Sha1>::new(6, 30, 1).with_recent_codes(RecentCodeCache::new(16, 90));
+
+        let result = verifier.verify("john", TEST_KEY, &password, TIMESTAMP)?;
+        assert_eq!(
+            result,
+            TotpResult {
+                valid: true,
+                discrepancy: 0,
+                suspected_replay: false
+            }
+        );
+
+        // Still in the window of the drift
+        let result = verifier.verify("john", TEST_KEY, &password, TIMESTAMP + 30)?;
+        assert_eq!(
+            result,
+            TotpResult {
+                valid: true,
+                discrepancy: -1,
+                suspected_replay: true
+            }
+        );
+
+        // Invalid passwords are never recorded
+        let result = verifier.verify("john", TEST_KEY, "000000", TIMESTAMP)?;
+        assert_eq!(result, TotpResult::default());
+
+        // Without a cache nothing is flagged
+        let mut verifier = TotpVerifier::<Sha1>::new(6, 30, 1);
+        assert!(
+            verifier
+                .verify("john", TEST_KEY, &password, TIMESTAMP)?
+                .valid
+        );
+        let result = verifier.verify("john", TEST_KEY, &password, TIMESTAMP)?;
+        assert!(result.valid && !result.suspected_replay);
+        Ok(())
+    }
+
+    #[test]
+    fn replay_guard_invalid() -> Result<(), ThotpError> {
+        let mut guard = TotpReplayGuard::with_watermark(5);
+        let outcome = guard.verify_and_advance::<Sha1>(TEST_KEY, "fail", TIMESTAMP, 6, 30, 1)?;
+        assert_eq!(outcome, TotpGuardOutcome::Invalid);
+        assert_eq!(guard.watermark(), Some(5));
+        Ok(())
+    }
+}
@@ -0,0 +1,944 @@
+//! Contains the [Totp] builder which holds a secret along with all the parameters needed to generate
+//! and verify its TOTPs, so they don't have to be passed to every call.
+//!
+//! Secrets are usually shared as Base32 text, like in otp uris and manual entry keys, so
+//! [Totp::from_base32] is the constructor to use in most cases. [Totp::new] takes the raw secret
+//! bytes instead, passing it the Base32 text is a common mistake that results in wrong passwords.
+//!
+//! ## Example
+//! ```
+//! use thotp::custom::{otp_custom, Algorithm, Sha256};
+//! use thotp::totp::Totp;
+//!
+//! let totp = Totp::from_base32("JBSWY3DPEHPK3PXP")
+//!     .unwrap()
+//!     .with_algorithm(Algorithm::Sha256)
+//!     .with_digits(8)
+//!     .with_clock(|| 1_000_000_000);
+//!
+//! let password = totp.generate(0).unwrap();
+//!
+//! // The decoded secret
+//! let secret = b"Hello!\xde\xad\xbe\xef";
+//! assert_eq!(password, otp_custom::<Sha256>(secret, 1_000_000_000 / 30, 8).unwrap());
+//! assert!(totp.verify(&password, 0).unwrap().valid);
+//! ```
+
+use super::custom::{
+    next_boundary_at, otp_custom, verify_totp_custom, Algorithm, HmacKey, Sha1, Sha256, Sha512,
+    TotpResult,
+};
+#[cfg(feature = "encoding")]
+use super::encoding::SecretEncoding;
+use super::otp_core::{checked_time_slice, ct_eq, ALLOWED_DRIFT, DIGITS_DEFAULT, TIME_STEP};
+use super::ThotpError;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A clock returning the current unix timestamp.
+type Clock = Arc<dyn Fn() -> u64 + Send + Sync>;
+
+/// Holds a secret and the parameters used to generate and verify its TOTPs. By default these are
+/// the RFC recommended SHA1, 6 digits, a time step of 30 and an allowed drift of 1.
+#[derive(Clone)]
+pub struct Totp {
+    secret: Vec<u8>,
+    algorithm: Algorithm,
+    digits: u8,
+    step: u8,
+    allowed_drift: u8,
+    clock: Option<Clock>,
+    max_server_skew: Option<u64>,
+}
+
+impl Totp {
+    /// The version of the binary format written by [Totp::to_bytes].
+    pub const FORMAT_VERSION: u8 = 2;
+
+    /// Creates a TOTP for the given raw secret bytes with the default parameters. Use
+    /// [Totp::from_base32] for a Base32 encoded secret.
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            algorithm: Algorithm::default(),
+            digits: DIGITS_DEFAULT,
+            step: TIME_STEP,
+            allowed_drift: ALLOWED_DRIFT,
+            clock: None,
+            max_server_skew: None,
+        }
+    }
+
+    /// Creates a TOTP for the given Base32 encoded secret with the default parameters, the way
+    /// authenticator apps accept secrets: the padding is optional, the letters are case insensitive
+    /// and spaces used to group them are ignored.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::totp::Totp;
+    ///
+    /// let totp = Totp::from_base32("jbsw y3dp ehpk 3pxp").unwrap();
+    ///
+    /// assert_eq!(totp.secret(), b"Hello!\xde\xad\xbe\xef");
+    /// ```
+    #[cfg(feature = "encoding")]
+    pub fn from_base32(secret: &str) -> Result<Self, ThotpError> {
+        Self::from_encoded(secret, SecretEncoding::Base32)
+    }
+
+    /// Creates a TOTP for the given secret in the given encoding with the default parameters. Spaces
+    /// used to group the secret are ignored.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::encoding::SecretEncoding;
+    /// use thotp::totp::Totp;
+    ///
+    /// let totp = Totp::from_encoded("48656c6c6f21deadbeef", SecretEncoding::Hex).unwrap();
+    ///
+    /// assert_eq!(totp.secret(), Totp::from_base32("JBSWY3DPEHPK3PXP").unwrap().secret());
+    /// ```
+    #[cfg(feature = "encoding")]
+    pub fn from_encoded(secret: &str, encoding: SecretEncoding) -> Result<Self, ThotpError> {
+        let secret: String = secret.split_whitespace().collect();
+        Ok(Self::new(encoding.decode(&secret)?))
+    }
+
+    /// Sets the hashing algorithm.
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Sets the digit length of the passwords.
+    pub fn with_digits(mut self, digits: u8) -> Self {
+        self.digits = digits;
+        self
+    }
+
+    /// Sets the time step.
+    pub fn with_step(mut self, step: u8) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Sets the number of time slices prior and subsequent to the current one whose passwords are
+    /// considered valid.
+    pub fn with_drift(mut self, allowed_drift: u8) -> Self {
+        self.allowed_drift = allowed_drift;
+        self
+    }
+
+    /// Sets the clock used when a timestamp of 0 is passed to [Totp::generate] or [Totp::verify] instead
+    /// of the system time, e.g. to use a fixed time in tests or a cached clock. The clock must return
+    /// the current unix timestamp in seconds.
+    pub fn with_clock(mut self, clock: impl Fn() -> u64 + Send + Sync + 'static) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Sets the largest difference in seconds between an explicit timestamp passed to [Totp::verify]
+    /// and the current time, i.e. the clock or if none is set the system time. Verifying with a
+    /// timestamp further off returns a [ThotpError::TimestampOutOfRange] instead of checking a distant
+    /// window, e.g. when the timestamp is supplied by a client. It is not checked by default.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::totp::Totp;
+    /// use thotp::ThotpError;
+    ///
+    /// let totp = Totp::new(b"super secret".to_vec())
+    ///     .with_clock(|| 1_000_000_000)
+    ///     .with_max_server_skew(300);
+    /// let password = totp.generate(1_000_003_600).unwrap();
+    ///
+    /// assert!(matches!(
+    ///     totp.verify(&password, 1_000_003_600),
+    ///     Err(ThotpError::TimestampOutOfRange)
+    /// ));
+    /// ```
+    pub fn with_max_server_skew(mut self, secs: u64) -> Self {
+        self.max_server_skew = Some(secs);
+        self
+    }
+
+    /// Returns the secret.
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Returns the hashing algorithm.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Returns the digit length of the passwords.
+    pub fn digits(&self) -> u8 {
+        self.digits
+    }
+
+    /// Returns the time step.
+    pub fn step(&self) -> u8 {
+        self.step
+    }
+
+    /// Returns the allowed drift.
+    pub fn allowed_drift(&self) -> u8 {
+        self.allowed_drift
+    }
+
+    /// Returns the maximum server skew, if one is set.
+    pub fn max_server_skew(&self) -> Option<u64> {
+        self.max_server_skew
+    }
+
+    /// Encodes the secret and parameters in a compact binary format, e.g. to cache the TOTPs of many
+    /// users. The clock is not part of it and has to be set again after decoding.
+    ///
+    /// The format starts with a version byte, currently [Totp::FORMAT_VERSION], followed by one byte
+    /// each for the algorithm (`0` for SHA1, `1` for SHA256 and `2` for SHA512), digits, step and
+    /// allowed drift. The maximum server skew follows as a `0` byte if none is set, otherwise a `1`
+    /// byte followed by the big endian skew. The rest are the raw bytes of the secret, so the encoded
+    /// TOTP has to be kept as secret as the secret itself.
+    ///
+    /// Only TOTPs that [Totp::from_bytes] can decode are encoded, so the digits have to be in
+    /// `6..=10` and the step must not be zero. [ThotpError::InvalidDigits] or
+    /// [ThotpError::InvalidStep] is returned otherwise.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::custom::Algorithm;
+    /// use thotp::totp::Totp;
+    ///
+    /// let totp = Totp::new(b"super secret".to_vec()).with_algorithm(Algorithm::Sha256);
+    /// let bytes = totp.to_bytes().unwrap();
+    ///
+    /// assert_eq!(&bytes[..6], [2, 1, 6, 30, 1, 0]);
+    /// assert_eq!(Totp::from_bytes(&bytes).unwrap().secret(), b"super secret");
+    /// assert!(totp.with_digits(4).to_bytes().is_err());
+    /// ```
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ThotpError> {
+        if !(6..=10).contains(&self.digits) {
+            return Err(ThotpError::InvalidDigits);
+        }
+        if self.step == 0 {
+            return Err(ThotpError::InvalidStep);
+        }
+
+        let algorithm = match self.algorithm {
+            Algorithm::Sha1 => 0,
+            Algorithm::Sha256 => 1,
+            Algorithm::Sha512 => 2,
+        };
+
+        let mut bytes = Vec::with_capacity(14 + self.secret.len());
+        bytes.extend_from_slice(&[
+            Self::FORMAT_VERSION,
+            algorithm,
+            self.digits,
+            self.step,
+            self.allowed_drift,
+        ]);
+        match self.max_server_skew {
+            Some(skew) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&skew.to_be_bytes());
+            }
+            None => bytes.push(0),
+        }
+        bytes.extend_from_slice(&self.secret);
+        Ok(bytes)
+    }
+
+    /// Decodes a TOTP encoded with [Totp::to_bytes]. Version 1 of the format, which has no maximum
+    /// server skew, is decoded as well. Returns a [ThotpError::UnsupportedVersion] if it was encoded
+    /// in any other version of the format, a [ThotpError::InvalidStep] if the step is 0 and a
+    /// [ThotpError::InvalidState] if it is otherwise malformed, e.g. if the digits are not in the
+    /// range of `6..=10`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ThotpError> {
+        // The version is checked first, other versions may be shorter
+        let (algorithm, digits, step, allowed_drift, rest) = match bytes {
+            [1 | 2, algorithm, digits, step, allowed_drift, rest @ ..] => {
+                (*algorithm, *digits, *step, *allowed_drift, rest)
+            }
+            [1 | 2, ..] | [] => return Err(ThotpError::InvalidState),
+            [version, ..] => return Err(ThotpError::UnsupportedVersion(*version)),
+        };
+
+        let (max_server_skew, secret) = match (bytes[0], rest) {
+            (1, secret) => (None, secret),
+            (_, [0, secret @ ..]) => (None, secret),
+            (_, [1, skew @ ..]) if skew.len() >= 8 => {
+                let (skew, secret) = skew.split_at(8);
+                let skew = u64::from_be_bytes(skew.try_into().expect("the skew is 8 bytes"));
+                (Some(skew), secret)
+            }
+            _ => return Err(ThotpError::InvalidState),
+        };
+
+        let algorithm = match algorithm {
+            0 => Algorithm::Sha1,
+            1 => Algorithm::Sha256,
+            2 => Algorithm::Sha512,
+            _ => return Err(ThotpError::InvalidState),
+        };
+
+        if !(6..=10).contains(&digits) {
+            return Err(ThotpError::InvalidState);
+        }
+
+        if step == 0 {
+            return Err(ThotpError::InvalidStep);
+        }
+
+        let totp = Self::new(secret)
+            .with_algorithm(algorithm)
+            .with_digits(digits)
+            .with_step(step)
+            .with_drift(allowed_drift);
+
+        Ok(match max_server_skew {
+            Some(skew) => totp.with_max_server_skew(skew),
+            None => totp,
+        })
+    }
+
+    /// Generates the password for the given unix timestamp.
+    ///
+    /// If a `timestamp` of 0 is provided, the clock or if none is set the current system time will
+    /// be used for the calculation.
+    pub fn generate(&self, timestamp: u64) -> Result<String, ThotpError> {
+        let nonce = checked_time_slice(self.timestamp(timestamp)?, self.step)?;
+
+        match self.algorithm {
+            Algorithm::Sha1 => otp_custom::<Sha1>(&self.secret, nonce, self.digits),
+            Algorithm::Sha256 => otp_custom::<Sha256>(&self.secret, nonce, self.digits),
+            Algorithm::Sha512 => otp_custom::<Sha512>(&self.secret, nonce, self.digits),
+        }
+    }
+
+    /// Waits until the next time slice starts and returns its password, so the password is valid for
+    /// the whole time step instead of possibly expiring a moment after it was handed out, e.g. in a
+    /// test harness or a client syncing passwords.
+    ///
+    /// This blocks the calling thread for up to a whole time step, use [Totp::wait_for_next_code_with]
+    /// to wait some other way. The wait is measured with the clock or if none is set the current
+    /// system time, and the password is generated for the start of the next slice, so it is the same
+    /// even if the thread wakes up late.
+    pub fn wait_for_next_code(&self) -> Result<String, ThotpError> {
+        self.wait_for_next_code_with(std::thread::sleep)
+    }
+
+    /// Same as [Totp::wait_for_next_code], except the time until the next slice starts is passed to
+    /// `sleep` instead of sleeping on the calling thread, e.g. to skip the wait in tests.
+    ///
+    /// ## Example
+    /// ```
+    /// use std::time::Duration;
+    /// use thotp::totp::Totp;
+    ///
+    /// // One second before the next time slice
+    /// let totp = Totp::new(b"super secret".to_vec()).with_clock(|| 1_000_000_019);
+    ///
+    /// let code = totp
+    ///     .wait_for_next_code_with(|wait| assert_eq!(wait, Duration::from_secs(1)))
+    ///     .unwrap();
+    /// assert_eq!(code, totp.generate(1_000_000_020).unwrap());
+    /// ```
+    pub fn wait_for_next_code_with(
+        &self,
+        sleep: impl FnOnce(Duration),
+    ) -> Result<String, ThotpError> {
+        let now = match self.clock {
+            Some(ref clock) => Duration::from_secs(clock()),
+            None => SystemTime::now().duration_since(UNIX_EPOCH)?,
+        };
+
+        let boundary = next_boundary_at(now.as_secs(), self.step)?;
+        sleep(Duration::from_secs(boundary).saturating_sub(now));
+
+        self.generate(boundary)
+    }
+
+    /// Verifies the given password for the given unix timestamp, in the range of
+    /// `[-allowed_drift, allowed_drift]` time slices.
+    ///
+    /// If a `timestamp` of 0 is provided, the clock or if none is set the current system time will
+    /// be used for the calculation. Returns a [ThotpError::TimestampOutOfRange] if the timestamp is
+    /// further off than [Totp::with_max_server_skew] allows.
+    #[must_use = "the verification result must be checked"]
+    pub fn verify(&self, password: &str, timestamp: u64) -> Result<TotpResult, ThotpError> {
+        self.verify_with_drift(password, timestamp, self.allowed_drift)
+    }
+
+    /// Verifies the given password like [Totp::verify] and also returns the password of the current
+    /// time slice, e.g. to show it right after the user authenticated. Both are calculated for the
+    /// same timestamp, so the returned password is never from a different slice than the one the
+    /// verification was relative to, even when the clock is read right at a slice boundary.
+    ///
+    /// If a `timestamp` of 0 is provided, the clock or if none is set the current system time will
+    /// be used for the calculation.
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::totp::Totp;
+    ///
+    /// let totp = Totp::new(b"super secret".to_vec()).with_clock(|| 1_000_000_000);
+    /// let password = totp.generate(0).unwrap();
+    ///
+    /// let (result, current) = totp.verify_and_current(&password, 0).unwrap();
+    ///
+    /// assert!(result.valid);
+    /// assert_eq!(current, password);
+    /// ```
+    #[must_use = "the verification result must be checked"]
+    pub fn verify_and_current(
+        &self,
+        password: &str,
+        timestamp: u64,
+    ) -> Result<(TotpResult, String), ThotpError> {
+        let timestamp = self.timestamp(timestamp)?;
+
+        let result = self.verify_with_drift(password, timestamp, self.allowed_drift)?;
+        Ok((result, self.generate(timestamp)?))
+    }
+
+    /// Generates the password for each unix timestamp the `ticks` yield, e.g. from a timer pushing
+    /// the current password to a client. The HMAC key is only initialized once for all of them, so
+    /// this is faster than calling [Totp::generate] for every tick.
+    ///
+    /// A tick of 0 generates the password for the clock or if none is set the current system time,
+    /// just like [Totp::generate].
+    ///
+    /// ## Example
+    /// ```
+    /// use thotp::totp::Totp;
+    ///
+    /// let totp = Totp::new(b"super secret".to_vec());
+    /// let codes: Vec<_> = totp
+    ///     .watch([30, 60, 90])
+    ///     .unwrap()
+    ///     .collect::<Result<_, _>>()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(codes[1], totp.generate(60).unwrap());
+    /// ```
+    pub fn watch<'a, I>(
+        &'a self,
+        ticks: I,
+    ) -> Result<impl Iterator<Item = Result<String, ThotpError>> + 'a, ThotpError>
+    where
+        I: IntoIterator<Item = u64>,
+        I::IntoIter: 'a,
+    {
+        let key = match self.algorithm {
+            Algorithm::Sha1 => Key::Sha1(HmacKey::new(&self.secret)?),
+            Algorithm::Sha256 => Key::Sha256(HmacKey::new(&self.secret)?),
+            Algorithm::Sha512 => Key::Sha512(HmacKey::new(&self.secret)?),
+        };
+
+        Ok(ticks.into_iter().map(move |tick| {
+            let nonce = checked_time_slice(self.timestamp(tick)?, self.step)?;
+
+            match key {
+                Key::Sha1(ref key) => key.code(nonce, self.digits),
+                Key::Sha256(ref key) => key.code(nonce, self.digits),
+                Key::Sha512(ref key) => key.code(nonce, self.digits),
+            }
+        }))
+    }
+
+    /// Verifies the given password like [Totp::verify], but with the given drift instead of the one
+    /// set on the TOTP.
+    pub(super) fn verify_with_drift(
+        &self,
+        password: &str,
+        timestamp: u64,
+        drift: u8,
+    ) -> Result<TotpResult, ThotpError> {
+        // A timestamp of 0 is the current time, so it can not be off
+        if let Some(max) = self.max_server_skew.filter(|_| timestamp != 0) {
+            if timestamp.abs_diff(self.timestamp(0)?) > max {
+                return Err(ThotpError::TimestampOutOfRange);
+            }
+        }
+
+        let timestamp = self.timestamp(timestamp)?;
+        let (secret, digits, step) = (&self.secret, self.digits, self.step);
+
+        let (valid, discrepancy) = match self.algorithm {
+            Algorithm::Sha1 => {
+                verify_totp_custom::<Sha1>(password, secret, timestamp, digits, step, drift)?
+            }
+            Algorithm::Sha256 => {
+                verify_totp_custom::<Sha256>(password, secret, timestamp, digits, step, drift)?
+            }
+            Algorithm::Sha512 => {
+                verify_totp_custom::<Sha512>(password, secret, timestamp, digits, step, drift)?
+            }
+        };
+
+        Ok(TotpResult {
+            valid,
+            discrepancy,
+            suspected_replay: false,
+        })
+    }
+
+    /// Returns the given timestamp, or the current one if it is 0.
+    fn timestamp(&self, timestamp: u64) -> Result<u64, ThotpError> {
+        if timestamp != 0 {
+            return Ok(timestamp);
+        }
+
+        match self.clock {
+            Some(ref clock) => Ok(clock()),
+            None => Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()),
+        }
+    }
+}
+
+/// The parameters that differ between two TOTPs, as returned by [plan_migration].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationPlan {
+    /// Whether the secret changed
+    pub secret_changed: bool,
+
+    /// Whether the hashing algorithm changed
+    pub algorithm_changed: bool,
+
+    /// Whether the digit length changed
+    pub digits_changed: bool,
+
+    /// Whether the time step changed
+    pub step_changed: bool,
+
+    /// Whether the allowed drift changed
+    pub drift_changed: bool,
+}
+
+impl MigrationPlan {
+    /// Whether the new TOTP accepts the passwords of the old one, so authenticator apps keep working
+    /// without being set up again. This is only the case if at most the allowed drift changed, since
+    /// it is the only parameter not used to generate the passwords.
+    pub fn is_compatible(&self) -> bool {
+        !(self.secret_changed || self.algorithm_changed || self.digits_changed || self.step_changed)
+    }
+
+    /// Whether the passwords of both TOTPs have to be verified until every authenticator app was set up
+    /// again, e.g. with [verify_totp_multi_secret](super::custom::verify_totp_multi_secret) or
+    /// [verify_totp_algorithms](super::custom::verify_totp_algorithms). This is the case if the
+    /// secret, algorithm or digit length changed.
+    ///
+    /// A change of only the step is not compatible either, but the same secret and algorithm generate
+    /// the passwords of both steps, so during the transition the old step can be accepted by also
+    /// verifying with it, see [verify_totp_period](super::custom::verify_totp_period).
+    pub fn requires_dual_verification(&self) -> bool {
+        self.secret_changed || self.algorithm_changed || self.digits_changed
+    }
+}
+
+/// Compares the parameters of two TOTPs, e.g. before moving a user from 6 to 8 digits or from SHA1 to
+/// SHA256, to find out whether authenticator apps have to be set up again and how passwords should be
+/// verified until they are. The secrets are compared in constant time.
+///
+/// ## Example
+/// ```
+/// use thotp::custom::Algorithm;
+/// use thotp::totp::{plan_migration, Totp};
+///
+/// let from = Totp::new(b"super secret".to_vec());
+/// let to = from.clone().with_algorithm(Algorithm::Sha256);
+///
+/// let plan = plan_migration(&from, &to);
+///
+/// assert!(plan.algorithm_changed);
+/// assert!(!plan.is_compatible());
+/// assert!(plan.requires_dual_verification());
+/// ```
+pub fn plan_migration(from: &Totp, to: &Totp) -> MigrationPlan {
+    MigrationPlan {
+        secret_changed: !ct_eq(&from.secret, &to.secret),
+        algorithm_changed: from.algorithm != to.algorithm,
+        digits_changed: from.digits != to.digits,
+        step_changed: from.step != to.step,
+        drift_changed: from.allowed_drift != to.allowed_drift,
+    }
+}
+
+/// The initialized HMAC key of one of the algorithms.
+enum Key {
+    Sha1(HmacKey<Sha1>),
+    Sha256(HmacKey<Sha256>),
+    Sha512(HmacKey<Sha512>),
+}
+
+impl fmt::Debug for Totp {
+    // The secret is omitted on purpose
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Totp")
+            .field("algorithm", &self.algorithm)
+            .field("digits", &self.digits)
+            .field("step", &self.step)
+            .field("allowed_drift", &self.allowed_drift)
+            .field("clock", &self.clock.is_some())
+            .field("max_server_skew", &self.max_server_skew)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    const TEST_KEY: &[u8; 20] = b"12345678901234567890";
+
+    #[test]
+    fn totp_defaults() -> Result<(), ThotpError> {
+        let totp = Totp::new(TEST_KEY.to_vec());
+        assert_eq!(totp.algorithm(), Algorithm::Sha1);
+        assert_eq!(totp.digits(), 6);
+        assert_eq!(totp.step(), 30);
+        assert_eq!(totp.allowed_drift(), 1);
+
+        let password = totp.generate(1111111109)?;
+        assert_eq!(password, otp_custom::<Sha1>(TEST_KEY, 1111111109 / 30, 6)?);
+        assert!(totp.verify(&password, 1111111109)?.valid);
+        assert!(totp.verify(&password, 0).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn totp_fixed_clock() -> Result<(), ThotpError> {
+        let secret: &[u8] = b"12345678901234567890123456789012";
+        let totp = Totp::new(secret)
+            .with_algorithm(Algorithm::Sha256)
+            .with_digits(8)
+            .with_clock(|| 1111111109);
+
+        assert_eq!(totp.generate(0)?, "68084774");
+        assert_eq!(totp.generate(0)?, totp.generate(1111111109)?);
+
+        let result = totp.verify("68084774", 0)?;
+        assert!(result.valid);
+        assert_eq!(result.discrepancy, 0);
+
+        // An explicit timestamp takes precedence over the clock
+        assert_eq!(totp.generate(20000000000)?, "77737706");
+        assert!(!totp.verify("68084774", 20000000000)?.valid);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_bytes_round_trip() -> Result<(), ThotpError> {
+        let totp = Totp::new(TEST_KEY.to_vec())
+            .with_algorithm(Algorithm::Sha512)
+            .with_digits(8)
+            .with_step(60)
+            .with_drift(2);
+
+        let bytes = totp.to_bytes()?;
+        assert_eq!(bytes.len(), 6 + TEST_KEY.len());
+        assert_eq!(&bytes[6..], TEST_KEY);
+
+        let decoded = Totp::from_bytes(&bytes)?;
+        assert_eq!(decoded.secret(), TEST_KEY);
+        assert_eq!(decoded.algorithm(), Algorithm::Sha512);
+        assert_eq!(decoded.digits(), 8);
+        assert_eq!(decoded.step(), 60);
+        assert_eq!(decoded.allowed_drift(), 2);
+        assert_eq!(decoded.max_server_skew(), None);
+        assert_eq!(decoded.generate(1111111109)?, totp.generate(1111111109)?);
+
+        // The maximum server skew is kept
+        let skewed = totp.clone().with_max_server_skew(300).to_bytes()?;
+        assert_eq!(&skewed[5..14], [1, 0, 0, 0, 0, 0, 0, 1, 44]);
+        assert_eq!(&skewed[14..], TEST_KEY);
+        assert_eq!(Totp::from_bytes(&skewed)?.max_server_skew(), Some(300));
+        assert_eq!(Totp::from_bytes(&skewed)?.secret(), TEST_KEY);
+        for truncated in [&skewed[..5], &skewed[..13]] {
+            assert!(matches!(
+                Totp::from_bytes(truncated),
+                Err(ThotpError::InvalidState)
+            ));
+        }
+
+        // Version 1 has no maximum server skew
+        let mut version_1 = vec![1, 2, 8, 60, 2];
+        version_1.extend_from_slice(TEST_KEY);
+        let decoded = Totp::from_bytes(&version_1)?;
+        assert_eq!(decoded.secret(), TEST_KEY);
+        assert_eq!(decoded.max_server_skew(), None);
+        assert_eq!(decoded.to_bytes()?, bytes);
+
+        // An empty secret is encoded as well
+        assert!(Totp::from_bytes(&Totp::new(Vec::new()).to_bytes()?)?
+            .secret()
+            .is_empty());
+
+        // Other versions are rejected instead of misparsed, even if they are too short
+        let mut future = bytes.clone();
+        future[0] = 3;
+        assert!(matches!(
+            Totp::from_bytes(&future),
+            Err(ThotpError::UnsupportedVersion(3))
+        ));
+        assert!(matches!(
+            Totp::from_bytes(&[0]),
+            Err(ThotpError::UnsupportedVersion(0))
+        ));
+
+        let mut unknown = bytes;
+        unknown[1] = 3;
+        assert!(matches!(
+            Totp::from_bytes(&unknown),
+            Err(ThotpError::InvalidState)
+        ));
+        assert!(matches!(
+            Totp::from_bytes(&[1, 0, 6]),
+            Err(ThotpError::InvalidState)
+        ));
+
+        // Parameters the passwords can not be generated with are rejected when decoding
+        assert!(matches!(
+            Totp::from_bytes(&[1, 0, 6, 0, 1]),
+            Err(ThotpError::InvalidStep)
+        ));
+        for digits in [0, 5, 11, u8::MAX] {
+            assert!(matches!(
+                Totp::from_bytes(&[1, 0, digits, 30, 1]),
+                Err(ThotpError::InvalidState)
+            ));
+        }
+        assert!(matches!(
+            Totp::from_bytes(&[]),
+            Err(ThotpError::InvalidState)
+        ));
+
+        // ... so they are not encoded either
+        for digits in [0, 5, 11, u8::MAX] {
+            assert!(matches!(
+                Totp::new(TEST_KEY.to_vec()).with_digits(digits).to_bytes(),
+                Err(ThotpError::InvalidDigits)
+            ));
+        }
+        assert!(matches!(
+            Totp::new(TEST_KEY.to_vec()).with_step(0).to_bytes(),
+            Err(ThotpError::InvalidStep)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "encoding")]
+    fn totp_from_base32() -> Result<(), ThotpError> {
+        // The Base32 encoding of the test key
+        let encoded = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        let raw = Totp::new(TEST_KEY.to_vec());
+
+        for secret in [
+            encoded,
+            "gezdgnbvgy3tqojqgezdgnbvgy3tqojq",
+            "GEZD GNBV GY3T QOJQ GEZD GNBV GY3T QOJQ",
+        ] {
+            let totp = Totp::from_base32(secret)?;
+            assert_eq!(totp.secret(), TEST_KEY);
+            for timestamp in [59, 1111111109, 1234567890, 20000000000] {
+                assert_eq!(totp.generate(timestamp)?, raw.generate(timestamp)?);
+            }
+        }
+
+        // Passing the Base32 text as the raw secret results in different passwords
+        assert_ne!(Totp::new(encoded).generate(59)?, raw.generate(59)?);
+
+        let hex = Totp::from_encoded(
+            "3132333435363738393031323334353637383930",
+            SecretEncoding::Hex,
+        )?;
+        assert_eq!(hex.secret(), TEST_KEY);
+        assert!(Totp::from_base32("not base32!").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn totp_verify_and_current() -> Result<(), ThotpError> {
+        let totp = Totp::new(TEST_KEY.to_vec()).with_digits(8);
+
+        // The password of the previous slice
+        let previous = totp.generate(1111111109 - 30)?;
+        let (result, current) = totp.verify_and_current(&previous, 1111111109)?;
+        assert!(result.valid);
+        assert_eq!(result.discrepancy, -1);
+        assert_eq!(current, totp.generate(1111111109)?);
+        assert_eq!(current, "07081804");
+
+        let (result, current) = totp.verify_and_current("00000000", 1111111111)?;
+        assert!(!result.valid);
+        assert_eq!(current, "14050471");
+
+        // A clock is read only once and used for both
+        let ticks = Arc::new(AtomicU64::new(1111111109));
+        let clock = Arc::clone(&ticks);
+        let totp = totp.with_clock(move || clock.fetch_add(1, Ordering::SeqCst));
+        let (result, current) = totp.verify_and_current("07081804", 0)?;
+        assert_eq!((result.valid, result.discrepancy), (true, 0));
+        assert_eq!(current, "07081804");
+        assert_eq!(ticks.load(Ordering::SeqCst), 1111111110);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_watch() -> Result<(), ThotpError> {
+        let ticks = [59, 1111111109, 1111111111, 1234567890, 2000000000];
+
+        for algorithm in [Algorithm::Sha1, Algorithm::Sha256, Algorithm::Sha512] {
+            let totp = Totp::new(TEST_KEY.to_vec())
+                .with_algorithm(algorithm)
+                .with_digits(8);
+
+            let codes = totp.watch(ticks)?.collect::<Result<Vec<_>, _>>()?;
+            let expected = ticks
+                .iter()
+                .map(|&tick| totp.generate(tick))
+                .collect::<Result<Vec<_>, _>>()?;
+            assert_eq!(codes, expected);
+        }
+
+        // Ticks of 0 use the clock
+        let now = Arc::new(AtomicU64::new(1111111109));
+        let clock = now.clone();
+        let totp = Totp::new(TEST_KEY.to_vec())
+            .with_digits(8)
+            .with_clock(move || clock.load(Ordering::SeqCst));
+
+        let ticks = std::iter::repeat_n(0, 3).inspect(move |_| {
+            now.fetch_add(30, Ordering::SeqCst);
+        });
+        let codes = totp.watch(ticks)?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            codes,
+            [
+                totp.generate(1111111139)?,
+                totp.generate(1111111169)?,
+                totp.generate(1111111199)?
+            ]
+        );
+
+        // A step of 0 fails every tick instead of dividing by it
+        let totp = Totp::new(TEST_KEY.to_vec()).with_step(0);
+        assert!(matches!(totp.generate(59), Err(ThotpError::InvalidStep)));
+        assert!(totp
+            .watch([59, 89])?
+            .all(|code| matches!(code, Err(ThotpError::InvalidStep))));
+        Ok(())
+    }
+
+    #[test]
+    fn totp_migration_plan() -> Result<(), ThotpError> {
+        let from = Totp::new(TEST_KEY.to_vec()).with_clock(|| 1111111109);
+
+        let plan = plan_migration(&from, &from.clone());
+        assert_eq!(plan, MigrationPlan::default());
+        assert!(plan.is_compatible());
+
+        // Only the drift changing keeps the passwords
+        let to = from.clone().with_drift(3);
+        let plan = plan_migration(&from, &to);
+        assert!(plan.drift_changed);
+        assert!(plan.is_compatible());
+        assert!(!plan.requires_dual_verification());
+        assert!(to.verify(&from.generate(0)?, 0)?.valid);
+
+        // A step change keeps the key but not the passwords
+        let to = from.clone().with_step(60);
+        let plan = plan_migration(&from, &to);
+        assert_eq!(
+            plan,
+            MigrationPlan {
+                step_changed: true,
+                ..Default::default()
+            }
+        );
+        assert!(!plan.is_compatible());
+        assert!(!plan.requires_dual_verification());
+
+        // An algorithm change requires verifying both
+        let to = from.clone().with_algorithm(Algorithm::Sha256);
+        let plan = plan_migration(&from, &to);
+        assert_eq!(
+            plan,
+            MigrationPlan {
+                algorithm_changed: true,
+                ..Default::default()
+            }
+        );
+        assert!(!plan.is_compatible());
+        assert!(plan.requires_dual_verification());
+        assert!(!to.verify(&from.generate(0)?, 0)?.valid);
+
+        let to = Totp::new(b"another secret".to_vec()).with_digits(8);
+        let plan = plan_migration(&from, &to);
+        assert!(plan.secret_changed && plan.digits_changed);
+        assert!(plan.requires_dual_verification());
+        Ok(())
+    }
+
+    #[test]
+    fn totp_next_code() -> Result<(), ThotpError> {
+        let totp = Totp::new(TEST_KEY.to_vec())
+            .with_digits(8)
+            .with_clock(|| 1111111109);
+
+        let mut waited = None;
+        let code = totp.wait_for_next_code_with(|wait| waited = Some(wait))?;
+        assert_eq!(waited, Some(Duration::from_secs(1)));
+
+        // The password of the next slice and not of the current one
+        assert_eq!(code, totp.generate(1111111110)?);
+        assert_eq!(code, "14050471");
+        assert_ne!(code, totp.generate(0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_server_skew() -> Result<(), ThotpError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let totp = Totp::new(TEST_KEY.to_vec()).with_max_server_skew(300);
+
+        let password = totp.generate(now + 3600)?;
+        assert!(matches!(
+            totp.verify(&password, now + 3600),
+            Err(ThotpError::TimestampOutOfRange)
+        ));
+        assert!(matches!(
+            totp.verify(&password, now - 3600),
+            Err(ThotpError::TimestampOutOfRange)
+        ));
+
+        // Timestamps within the limit and the current time are verified as usual
+        let password = totp.generate(now + 60)?;
+        assert!(totp.verify(&password, now + 60)?.valid);
+        assert!(totp.verify(&totp.generate(0)?, 0).is_ok());
+
+        // The limit is relative to the clock if one is set
+        let totp = totp.with_clock(|| 1111111109);
+        assert!(totp.verify(&totp.generate(1111111409)?, 1111111409)?.valid);
+        assert!(matches!(
+            totp.verify(&totp.generate(1111111410)?, 1111111410),
+            Err(ThotpError::TimestampOutOfRange)
+        ));
+
+        // Without a limit any timestamp is verified
+        let totp = Totp::new(TEST_KEY.to_vec());
+        assert!(totp.verify(&totp.generate(now + 3600)?, now + 3600)?.valid);
+        Ok(())
+    }
+
+    #[test]
+    fn totp_debug_omits_secret() {
+        let totp = Totp::new(TEST_KEY.to_vec());
+        assert!(!format!("{:?}", totp).contains("49"));
+    }
+}